@@ -0,0 +1,180 @@
+// ** Heap
+// the heap is a fixed range of virtual memory that init_heap backs with fresh frames. the
+// allocator only hands out pieces of that range, it never maps anything itself, so a full heap
+// is an allocation failure rather than a reason to grow.
+//
+// GlobalAlloc methods take &self, but every allocator needs to update its bookkeeping. Locked
+// wraps one in a spin Mutex to get &mut access. the lock means interrupt handlers must not
+// allocate: one that interrupts an allocation would spin on the lock forever.
+//
+// the global allocator is the linked list one. two features swap it out:
+//  bump-allocator		faster, but only reuses memory once everything is freed
+//  fixed-size-block-allocator	O(1) for allocations up to 2048 bytes, the linked list handles
+//				the bigger ones
+//
+// ** Heap Statistics
+// every allocator can list the sizes of its free blocks, heap_stats sums them up. lots of free
+// blocks with a small largest one means the heap is fragmented: there is memory left, but an
+// allocation bigger than largest_free_block still fails.
+
+pub mod bump;
+pub mod fixed_size_block;
+pub mod linked_list;
+
+use core::alloc::Layout;
+use core::fmt;
+
+#[cfg(feature = "bump-allocator")]
+use bump::BumpAllocator as HeapAllocator;
+#[cfg(all(
+    feature = "fixed-size-block-allocator",
+    not(feature = "bump-allocator")
+))]
+use fixed_size_block::FixedSizeBlockAllocator as HeapAllocator;
+#[cfg(not(any(feature = "bump-allocator", feature = "fixed-size-block-allocator")))]
+use linked_list::LinkedListAllocator as HeapAllocator;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{FrameAllocator, Mapper, Page, Size4KiB};
+
+use crate::memory;
+
+/// far away from everything the bootloader maps, so it is easy to recognize in a page fault
+pub const HEAP_START: usize = 0x_4444_4444_0000;
+pub const HEAP_SIZE: usize = 100 * 1024;
+
+#[global_allocator]
+static ALLOCATOR: Locked<HeapAllocator> = Locked::new(HeapAllocator::new());
+
+/// an allocator behind a spin lock, so it can implement GlobalAlloc
+pub struct Locked<A> {
+    inner: spin::Mutex<A>,
+}
+
+impl<A> Locked<A> {
+    pub const fn new(inner: A) -> Self {
+        Locked {
+            inner: spin::Mutex::new(inner),
+        }
+    }
+
+    pub fn lock(&self) -> spin::MutexGuard<'_, A> {
+        self.inner.lock()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    pub used: usize,
+    pub free: usize,
+    pub largest_free_block: usize,
+    pub free_block_count: usize,
+}
+
+impl HeapStats {
+    /// the stats of a heap of `heap_size` bytes whose free blocks have the given sizes
+    pub fn from_free_blocks(heap_size: usize, free_blocks: impl Iterator<Item = usize>) -> Self {
+        let mut stats = HeapStats {
+            used: 0,
+            free: 0,
+            largest_free_block: 0,
+            free_block_count: 0,
+        };
+        for size in free_blocks {
+            stats.free += size;
+            stats.largest_free_block = stats.largest_free_block.max(size);
+            stats.free_block_count += 1;
+        }
+        stats.used = heap_size - stats.free;
+        stats
+    }
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "used    {} bytes", self.used)?;
+        writeln!(f, "free    {} bytes", self.free)?;
+        writeln!(
+            f,
+            "        in {} blocks, the largest is {} bytes",
+            self.free_block_count, self.largest_free_block
+        )
+    }
+}
+
+/// walks the free blocks of the global allocator. must not be called from an interrupt handler,
+/// same as allocating
+pub fn heap_stats() -> HeapStats {
+    HeapStats::from_free_blocks(HEAP_SIZE, ALLOCATOR.lock().free_blocks())
+}
+
+/// rounds `addr` up to a multiple of `align`, which has to be a power of two
+pub fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+/// maps the heap range to newly allocated frames and hands it to the global allocator
+pub fn init_heap(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    let heap_start = VirtAddr::new(HEAP_START as u64);
+    let heap_end = heap_start + HEAP_SIZE as u64 - 1u64;
+    let pages = Page::range_inclusive(
+        Page::<Size4KiB>::containing_address(heap_start),
+        Page::containing_address(heap_end),
+    );
+    for page in pages {
+        let frame = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        let flags = memory::KERNEL_PAGE_FLAGS;
+        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    }
+    unsafe { ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE) };
+    Ok(())
+}
+
+#[alloc_error_handler]
+fn alloc_error_handler(layout: Layout) -> ! {
+    panic!("allocation error: {:?}", layout)
+}
+
+#[test_case]
+fn test_align_up() {
+    assert_eq!(align_up(0x1000, 0x1000), 0x1000);
+    assert_eq!(align_up(0x1001, 0x1000), 0x2000);
+    assert_eq!(align_up(17, 16), 32);
+}
+
+#[test_case]
+fn test_heap_pages_are_global() {
+    use x86_64::registers::control::Cr3;
+    use x86_64::structures::paging::mapper::TranslateResult;
+    use x86_64::structures::paging::{OffsetPageTable, PageTable, PageTableFlags, Translate};
+
+    let offset = memory::physical_memory_offset().expect("paging isnt set up");
+    let level_4 = offset + Cr3::read().0.start_address().as_u64();
+    let mapper = unsafe { OffsetPageTable::new(&mut *level_4.as_mut_ptr::<PageTable>(), offset) };
+    for addr in [HEAP_START, HEAP_START + HEAP_SIZE - 1] {
+        let TranslateResult::Mapped { flags, .. } = mapper.translate(VirtAddr::new(addr as u64))
+        else {
+            panic!("heap address {:#x} isnt mapped", addr);
+        };
+        assert!(flags.contains(PageTableFlags::GLOBAL));
+    }
+}
+
+#[test_case]
+fn test_heap_stats() {
+    use alloc::boxed::Box;
+
+    let before = heap_stats();
+    assert_eq!(before.used + before.free, HEAP_SIZE);
+    assert!(before.largest_free_block <= before.free);
+    let block = Box::new([0u8; 256]);
+    let during = heap_stats();
+    assert!(during.used >= before.used + 256);
+    drop(block);
+    assert_eq!(heap_stats().used, before.used);
+}
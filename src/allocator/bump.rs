@@ -0,0 +1,103 @@
+// hands out memory by moving `next` forward and never looks back:
+//  heap: |  used  | used |  used  |......................free......................|
+//        ^heap_start               ^next                                    heap_end^
+// freeing only counts the allocation down. once nothing is allocated any more the whole heap
+// is free again and `next` jumps back to the start. it's very fast and has no per-block
+// overhead, but one long lived allocation keeps everything after it from being reused.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+
+use super::{Locked, align_up};
+
+pub struct BumpAllocator {
+    heap_start: usize,
+    heap_end: usize,
+    next: usize,
+    allocations: usize,
+}
+
+impl Default for BumpAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BumpAllocator {
+    pub const fn new() -> Self {
+        BumpAllocator {
+            heap_start: 0,
+            heap_end: 0,
+            next: 0,
+            allocations: 0,
+        }
+    }
+
+    /// hands `heap_start..heap_start + heap_size` to the allocator
+    ///
+    /// # Safety
+    /// the range must be mapped, unused, and given to the allocator only once
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.heap_start = heap_start;
+        self.heap_end = heap_start + heap_size;
+        self.next = heap_start;
+    }
+
+    /// bytes between `next` and the end of the heap
+    pub fn free_bytes(&self) -> usize {
+        self.heap_end - self.next
+    }
+
+    /// freed memory before `next` cant be handed out again, so there is only one free block
+    pub fn free_blocks(&self) -> impl Iterator<Item = usize> + use<> {
+        core::iter::once(self.free_bytes())
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<BumpAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut bump = self.lock();
+        let start = align_up(bump.next, layout.align());
+        match start.checked_add(layout.size()) {
+            Some(end) if end <= bump.heap_end => {
+                bump.next = end;
+                bump.allocations += 1;
+                start as *mut u8
+            }
+            _ => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+        let mut bump = self.lock();
+        bump.allocations -= 1;
+        if bump.allocations == 0 {
+            bump.next = bump.heap_start;
+        }
+    }
+}
+
+#[test_case]
+fn test_bump_resets_when_empty() {
+    static mut HEAP: [u64; 512] = [0; 512];
+    let heap_start = &raw mut HEAP as usize;
+    let allocator = Locked::new(BumpAllocator::new());
+    unsafe { allocator.lock().init(heap_start, 4096) };
+
+    let layout = Layout::from_size_align(10, 8).unwrap();
+    let mut blocks = [ptr::null_mut(); 100];
+    for block in blocks.iter_mut() {
+        *block = unsafe { allocator.alloc(layout) };
+        assert!(!block.is_null());
+    }
+    assert_eq!(allocator.lock().next, heap_start + 99 * 16 + 10);
+    assert!(unsafe { allocator.alloc(Layout::from_size_align(4096, 8).unwrap()) }.is_null());
+
+    for block in blocks.iter().take(99) {
+        unsafe { allocator.dealloc(*block, layout) };
+    }
+    assert_ne!(allocator.lock().next, heap_start);
+    unsafe { allocator.dealloc(blocks[99], layout) };
+    assert_eq!(allocator.lock().next, heap_start);
+    assert_eq!(allocator.lock().allocations, 0);
+}
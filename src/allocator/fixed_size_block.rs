@@ -0,0 +1,192 @@
+// rounds every allocation up to one of a few block sizes and keeps a free list per size:
+//  BLOCK_SIZES:  8    16    32   ...  2048
+//  heads:       [ ]  [ ]   [ ]  ...  [ ]
+//                |    |                |
+//              block block            block
+//                |
+//              block
+// allocating pops the head of a list and freeing pushes the block back, both O(1) no matter
+// how fragmented the heap is. the price is the memory lost to rounding up.
+//
+// lists start out empty, a block is cut from the linked list allocator the first time its
+// size runs out. freed blocks stay in their list and are never handed back, so memory that
+// once held small blocks can't be used for big ones afterwards.
+// anything bigger than the largest block size, or aligned more than its size, goes straight
+// to the linked list allocator. so do blocks that are too small to hold a ListNode once they
+// are freed.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::{iter, mem};
+
+use super::Locked;
+use super::linked_list::LinkedListAllocator;
+
+/// powers of two, so a block's size is also its alignment
+const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// lives in a free block
+struct ListNode {
+    next: Option<&'static mut ListNode>,
+}
+
+pub struct FixedSizeBlockAllocator {
+    list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
+    fallback: LinkedListAllocator,
+}
+
+impl Default for FixedSizeBlockAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FixedSizeBlockAllocator {
+    pub const fn new() -> Self {
+        const EMPTY: Option<&'static mut ListNode> = None;
+        FixedSizeBlockAllocator {
+            list_heads: [EMPTY; BLOCK_SIZES.len()],
+            fallback: LinkedListAllocator::new(),
+        }
+    }
+
+    /// hands `heap_start..heap_start + heap_size` to the allocator
+    ///
+    /// # Safety
+    /// the range must be mapped, unused, and given to the allocator only once
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        unsafe { self.fallback.init(heap_start, heap_size) };
+    }
+
+    /// free bytes in the fallback allocator, blocks sitting in the free lists are not counted
+    pub fn free_bytes(&self) -> usize {
+        self.fallback.free_bytes()
+    }
+
+    /// the blocks in the free lists and the free blocks of the fallback allocator
+    pub fn free_blocks(&self) -> impl Iterator<Item = usize> + '_ {
+        let listed = self
+            .list_heads
+            .iter()
+            .zip(BLOCK_SIZES)
+            .flat_map(|(head, &size)| {
+                iter::successors(head.as_deref(), |node| node.next.as_deref()).map(move |_| size)
+            });
+        listed.chain(self.fallback.free_blocks())
+    }
+}
+
+/// the free list `layout` is served from, None if it goes to the fallback allocator
+fn list_index(layout: &Layout) -> Option<usize> {
+    let required = layout.size().max(layout.align());
+    BLOCK_SIZES
+        .iter()
+        .position(|&size| size >= required)
+        .filter(|&index| {
+            BLOCK_SIZES[index] >= mem::size_of::<ListNode>()
+                && BLOCK_SIZES[index] >= mem::align_of::<ListNode>()
+        })
+}
+
+unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut allocator = self.lock();
+        match list_index(&layout) {
+            Some(index) => match allocator.list_heads[index].take() {
+                Some(node) => {
+                    allocator.list_heads[index] = node.next.take();
+                    node as *mut ListNode as *mut u8
+                }
+                None => {
+                    let size = BLOCK_SIZES[index];
+                    let block = Layout::from_size_align(size, size).unwrap();
+                    allocator.fallback.allocate(block)
+                }
+            },
+            None => allocator.fallback.allocate(layout),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut allocator = self.lock();
+        match list_index(&layout) {
+            Some(index) => {
+                let node = ptr as *mut ListNode;
+                unsafe {
+                    node.write(ListNode {
+                        next: allocator.list_heads[index].take(),
+                    });
+                    allocator.list_heads[index] = Some(&mut *node);
+                }
+            }
+            None => unsafe { allocator.fallback.deallocate(ptr, layout) },
+        }
+    }
+}
+
+#[test_case]
+fn test_list_index() {
+    let layout = |size, align| Layout::from_size_align(size, align).unwrap();
+    assert_eq!(list_index(&layout(1, 1)), Some(0));
+    assert_eq!(list_index(&layout(9, 8)), Some(1));
+    assert_eq!(list_index(&layout(8, 64)), Some(3));
+    assert_eq!(list_index(&layout(2048, 8)), Some(BLOCK_SIZES.len() - 1));
+    assert_eq!(list_index(&layout(2049, 8)), None);
+}
+
+#[cfg(test)]
+#[repr(align(4096))]
+struct TestHeap([u8; TEST_HEAP_SIZE]);
+
+#[cfg(test)]
+const TEST_HEAP_SIZE: usize = 32 * 1024;
+
+/// allocates and frees a lot of small blocks and returns the tsc cycles it took
+#[cfg(test)]
+fn churn_small_blocks(allocator: &impl GlobalAlloc) -> u64 {
+    let layouts = [8, 24, 64, 200].map(|size| Layout::from_size_align(size, 8).unwrap());
+    let mut blocks = [core::ptr::null_mut(); 32];
+    let start = crate::cpu::rdtsc();
+    for _ in 0..200 {
+        for (i, block) in blocks.iter_mut().enumerate() {
+            *block = unsafe { allocator.alloc(layouts[i % layouts.len()]) };
+            assert!(!block.is_null());
+        }
+        for (i, block) in blocks.iter().enumerate().rev() {
+            unsafe { allocator.dealloc(*block, layouts[i % layouts.len()]) };
+        }
+    }
+    crate::cpu::rdtsc() - start
+}
+
+#[test_case]
+fn test_fixed_size_block_reuse() {
+    static mut HEAP: [TestHeap; 2] = [TestHeap([0; TEST_HEAP_SIZE]), TestHeap([0; TEST_HEAP_SIZE])];
+    let fixed_start = unsafe { &raw mut HEAP[0].0 as usize };
+    let list_start = unsafe { &raw mut HEAP[1].0 as usize };
+
+    let fixed = Locked::new(FixedSizeBlockAllocator::new());
+    unsafe { fixed.lock().init(fixed_start, TEST_HEAP_SIZE) };
+    let list = Locked::new(LinkedListAllocator::new());
+    unsafe { list.lock().init(list_start, TEST_HEAP_SIZE) };
+
+    let fixed_cycles = churn_small_blocks(&fixed);
+    let list_cycles = churn_small_blocks(&list);
+    crate::serial_print!(
+        "fixed size {} cycles, linked list {} cycles ",
+        fixed_cycles,
+        list_cycles
+    );
+
+    // after the first round every block comes out of a free list, so the fallback allocator
+    // only ever gave away one round's worth. it rounds blocks up to 16 bytes itself
+    let first_round: usize = (0..32).map(|i| [16, 32, 64, 256][i % 4]).sum();
+    assert_eq!(fixed.lock().free_bytes(), TEST_HEAP_SIZE - first_round);
+    assert_eq!(list.lock().free_bytes(), TEST_HEAP_SIZE);
+
+    // big allocations bypass the lists
+    let big = Layout::from_size_align(4096, 8).unwrap();
+    let block = unsafe { fixed.alloc(big) };
+    assert!(!block.is_null());
+    unsafe { fixed.dealloc(block, big) };
+    assert_eq!(fixed.lock().free_bytes(), TEST_HEAP_SIZE - first_round);
+}
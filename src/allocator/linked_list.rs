@@ -0,0 +1,233 @@
+// keeps the free parts of the heap in a list, the list nodes live in the free memory itself:
+//  heap: |node|..free..|  used  |node|.free.|    used    |node|....free....|
+//          |_______________________^ |________________________^
+// the list is sorted by address, so freeing a block can merge it with the free blocks right
+// before and after it. without that the heap would end up as a pile of small pieces that no
+// bigger allocation fits in, even though all of it is free.
+//
+// every block (free or used) starts on a multiple of BLOCK_ALIGN and its size is one too, which
+// is also the size of a node. so whatever is left in front of or behind an allocation is either
+// nothing or big enough for a node of its own.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::{iter, mem, ptr};
+
+use super::{Locked, align_up};
+
+struct ListNode {
+    size: usize,
+    next: *mut ListNode,
+}
+
+const BLOCK_ALIGN: usize = 16;
+const _: () = assert!(mem::size_of::<ListNode>() == BLOCK_ALIGN);
+
+pub struct LinkedListAllocator {
+    /// the free block with the lowest address
+    head: *mut ListNode,
+}
+
+// the nodes are only ever touched through the allocator, which is behind a lock
+unsafe impl Send for LinkedListAllocator {}
+
+impl Default for LinkedListAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LinkedListAllocator {
+    pub const fn new() -> Self {
+        LinkedListAllocator {
+            head: ptr::null_mut(),
+        }
+    }
+
+    /// hands `heap_start..heap_start + heap_size` to the allocator. the ends are rounded inwards
+    /// to BLOCK_ALIGN
+    ///
+    /// # Safety
+    /// the range must be mapped, unused, and given to the allocator only once
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        let start = align_up(heap_start, BLOCK_ALIGN);
+        let end = (heap_start + heap_size) & !(BLOCK_ALIGN - 1);
+        if end > start {
+            unsafe { self.add_free_block(start, end - start) };
+        }
+    }
+
+    /// free bytes in total, not all of them in one piece
+    pub fn free_bytes(&self) -> usize {
+        let mut total = 0;
+        let mut node = self.head;
+        while !node.is_null() {
+            unsafe {
+                total += (*node).size;
+                node = (*node).next;
+            }
+        }
+        total
+    }
+
+    /// the sizes of the free blocks, in address order
+    pub fn free_blocks(&self) -> impl Iterator<Item = usize> + '_ {
+        let first = (!self.head.is_null()).then_some(self.head);
+        iter::successors(first, |&node| {
+            let next = unsafe { (*node).next };
+            (!next.is_null()).then_some(next)
+        })
+        .map(|node| unsafe { (*node).size })
+    }
+
+    /// puts a block back into the list, merged with its neighbours if they are free
+    unsafe fn add_free_block(&mut self, addr: usize, size: usize) {
+        debug_assert!(addr.is_multiple_of(BLOCK_ALIGN) && size.is_multiple_of(BLOCK_ALIGN));
+        debug_assert!(size >= BLOCK_ALIGN);
+        unsafe {
+            // find the last free block before `addr`
+            let mut prev: *mut ListNode = ptr::null_mut();
+            let mut link: *mut *mut ListNode = &raw mut self.head;
+            while !(*link).is_null() && ((*link) as usize) < addr {
+                prev = *link;
+                link = &raw mut (*prev).next;
+            }
+            let mut next = *link;
+            let mut size = size;
+            if !next.is_null() && addr + size == next as usize {
+                size += (*next).size;
+                next = (*next).next;
+            }
+            if !prev.is_null() && prev as usize + (*prev).size == addr {
+                (*prev).size += size;
+                (*prev).next = next;
+            } else {
+                let node = addr as *mut ListNode;
+                node.write(ListNode { size, next });
+                *link = node;
+            }
+        }
+    }
+
+    /// the size and alignment a block for `layout` really gets
+    fn block_layout(layout: Layout) -> (usize, usize) {
+        let size = align_up(layout.size().max(1), BLOCK_ALIGN);
+        (size, layout.align().max(BLOCK_ALIGN))
+    }
+
+    /// first fit: takes the allocation out of the first free block it fits in, the rest of the
+    /// block stays free. returns null when nothing fits
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Self::block_layout(layout);
+        let mut link: *mut *mut ListNode = &raw mut self.head;
+        unsafe {
+            while !(*link).is_null() {
+                let block = *link;
+                let block_start = block as usize;
+                let block_end = block_start + (*block).size;
+                let start = align_up(block_start, align);
+                let end = match start.checked_add(size) {
+                    Some(end) if end <= block_end => end,
+                    _ => {
+                        link = &raw mut (*block).next;
+                        continue;
+                    }
+                };
+                *link = (*block).next;
+                if start > block_start {
+                    self.add_free_block(block_start, start - block_start);
+                }
+                if block_end > end {
+                    self.add_free_block(end, block_end - end);
+                }
+                return start as *mut u8;
+            }
+        }
+        ptr::null_mut()
+    }
+
+    /// # Safety
+    /// `ptr` must come from `allocate` on this allocator with the same `layout`
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Self::block_layout(layout);
+        unsafe { self.add_free_block(ptr as usize, size) };
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.lock().allocate(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.lock().deallocate(ptr, layout) };
+    }
+}
+
+#[cfg(test)]
+#[repr(align(4096))]
+struct TestHeap([u8; TEST_HEAP_SIZE]);
+
+#[cfg(test)]
+const TEST_HEAP_SIZE: usize = 16 * 1024;
+
+#[test_case]
+fn test_free_blocks_merge() {
+    static mut HEAP: TestHeap = TestHeap([0; TEST_HEAP_SIZE]);
+    let heap_start = unsafe { &raw mut HEAP.0 as usize };
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe { allocator.lock().init(heap_start, TEST_HEAP_SIZE) };
+
+    let small = Layout::from_size_align(24, 8).unwrap();
+    let page = Layout::from_size_align(100, 4096).unwrap();
+    let mut blocks = [ptr::null_mut(); 64];
+    // allocating and freeing over and over must not lose any memory
+    for round in 0..100 {
+        for (i, block) in blocks.iter_mut().enumerate() {
+            *block = unsafe { allocator.alloc(small) };
+            assert!(!block.is_null(), "round {} block {}", round, i);
+        }
+        let aligned = unsafe { allocator.alloc(page) };
+        assert!(!aligned.is_null());
+        assert!((aligned as usize).is_multiple_of(4096));
+        // free every other block first, so both kinds of merges happen
+        for block in blocks
+            .iter()
+            .step_by(2)
+            .chain(blocks.iter().skip(1).step_by(2))
+        {
+            unsafe { allocator.dealloc(*block, small) };
+        }
+        unsafe { allocator.dealloc(aligned, page) };
+        assert_eq!(allocator.lock().free_bytes(), TEST_HEAP_SIZE);
+    }
+
+    // everything merged back into a single block
+    assert_eq!(allocator.lock().free_blocks().count(), 1);
+    let all = Layout::from_size_align(TEST_HEAP_SIZE, 16).unwrap();
+    let whole = unsafe { allocator.alloc(all) };
+    assert_eq!(whole as usize, heap_start);
+    assert!(unsafe { allocator.alloc(small) }.is_null());
+}
+
+#[test_case]
+fn test_fragmentation_stats() {
+    static mut HEAP: TestHeap = TestHeap([0; TEST_HEAP_SIZE]);
+    let heap_start = unsafe { &raw mut HEAP.0 as usize };
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe { allocator.lock().init(heap_start, TEST_HEAP_SIZE) };
+
+    let layout = Layout::from_size_align(256, 16).unwrap();
+    let mut blocks = [ptr::null_mut(); 16];
+    for block in blocks.iter_mut() {
+        *block = unsafe { allocator.alloc(layout) };
+    }
+    // every other block, so none of the freed ones can merge
+    for block in blocks.iter().step_by(2) {
+        unsafe { allocator.dealloc(*block, layout) };
+    }
+    let stats = super::HeapStats::from_free_blocks(TEST_HEAP_SIZE, allocator.lock().free_blocks());
+    // 8 holes and the rest of the heap behind the last block
+    assert_eq!(stats.free_block_count, 9);
+    assert_eq!(stats.used, 8 * 256);
+    assert_eq!(stats.largest_free_block, TEST_HEAP_SIZE - 16 * 256);
+}
@@ -0,0 +1,126 @@
+// ** Local APIC
+// every core has a local APIC that delivers interrupts to it. its registers are memory mapped
+// (at 0xFEE00000 unless it was moved), 32 bits wide and 16 byte aligned:
+// Offset	Register
+// 0x020	Local APIC ID
+// 0x0B0	End Of Interrupt (EOI), writing anything tells the apic the current interrupt is done
+// 0x0F0	Spurious Interrupt Vector Register (SVR)
+//              bits 0-7: vector used for spurious interrupts, bit 8: software enable
+//
+// ** spurious interrupts
+// if an interrupt goes away while the apic is raising it (e.g. it got masked in the meantime),
+// the apic still has to give the cpu a vector, so it uses the one from the SVR.
+// no interrupt is actually in service at that point, so the spurious handler must NOT send an EOI:
+// the EOI would complete whatever real interrupt is in service below it instead, and that
+// interrupt would be lost (and lower priority ones delivered too early).
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::VirtAddr;
+
+const REG_ID: usize = 0x20;
+const REG_EOI: usize = 0xB0;
+const REG_SVR: usize = 0xF0;
+
+const SVR_APIC_ENABLE: u32 = 1 << 8;
+
+/// vector the apic uses for spurious interrupts. the low 4 bits are hardwired to 1 on older cpus,
+/// so the last vector is the usual choice
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+pub struct LocalApic {
+    base: *mut u32,
+}
+
+// the registers are only touched through the LAPIC mutex
+unsafe impl Send for LocalApic {}
+
+impl LocalApic {
+    /// # Safety
+    /// `base` has to point to the mapped (uncached) local apic registers
+    pub unsafe fn new(base: VirtAddr) -> LocalApic {
+        LocalApic {
+            base: base.as_mut_ptr(),
+        }
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { self.base.byte_add(offset).read_volatile() }
+    }
+
+    fn write(&mut self, offset: usize, value: u32) {
+        unsafe { self.base.byte_add(offset).write_volatile(value) }
+    }
+
+    pub fn id(&self) -> u8 {
+        (self.read(REG_ID) >> 24) as u8
+    }
+
+    /// software enables the apic and sets the vector for spurious interrupts
+    pub fn enable(&mut self, spurious_vector: u8) {
+        let svr = self.read(REG_SVR) & !0xFF;
+        self.write(REG_SVR, svr | SVR_APIC_ENABLE | spurious_vector as u32);
+    }
+
+    pub fn end_of_interrupt(&mut self) {
+        self.write(REG_EOI, 0);
+    }
+}
+
+static LAPIC: Mutex<Option<LocalApic>> = Mutex::new(None);
+static SPURIOUS_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// enables the local apic at `base` with SPURIOUS_VECTOR
+///
+/// # Safety
+/// same as LocalApic::new
+pub unsafe fn init(base: VirtAddr) {
+    let mut lapic = unsafe { LocalApic::new(base) };
+    lapic.enable(SPURIOUS_VECTOR);
+    *LAPIC.lock() = Some(lapic);
+}
+
+/// signals the end of the interrupt being handled, called at the end of every apic irq handler
+/// except the spurious one
+pub fn end_of_interrupt() {
+    if let Some(lapic) = LAPIC.lock().as_mut() {
+        lapic.end_of_interrupt();
+    }
+}
+
+/// how many spurious interrupts arrived so far
+pub fn spurious_count() -> u64 {
+    SPURIOUS_COUNT.load(Ordering::Relaxed)
+}
+
+/// everything the spurious vector handler does. no EOI, see the top of this file
+pub(crate) fn handle_spurious() {
+    SPURIOUS_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+#[repr(align(16))]
+struct MockRegisters([u32; 0x100]);
+
+#[test_case]
+fn test_spurious_does_not_eoi() {
+    const UNTOUCHED: u32 = 0xDEAD_BEEF;
+    let mut regs = MockRegisters([0; 0x100]);
+    regs.0[REG_EOI / 4] = UNTOUCHED;
+    unsafe { init(VirtAddr::from_ptr(regs.0.as_mut_ptr())) };
+
+    let before = spurious_count();
+    handle_spurious();
+    let eoi = unsafe { (&raw const regs.0[REG_EOI / 4]).read_volatile() };
+    assert_eq!(eoi, UNTOUCHED);
+    assert_eq!(spurious_count(), before + 1);
+
+    // a normal irq does write it
+    end_of_interrupt();
+    let eoi = unsafe { (&raw const regs.0[REG_EOI / 4]).read_volatile() };
+    assert_eq!(eoi, 0);
+    let svr = unsafe { (&raw const regs.0[REG_SVR / 4]).read_volatile() };
+    assert_eq!(svr, SVR_APIC_ENABLE | SPURIOUS_VECTOR as u32);
+
+    *LAPIC.lock() = None;
+}
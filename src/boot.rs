@@ -0,0 +1,316 @@
+// kernel parameters, ie. "serial_baud=115200 quiet selftest test_filter=foo log_level=debug serial_color heartbeat"
+//
+// bootloader 0.9 doesnt hand us a command line, so for now it is baked in at build time through
+// the KERNEL_CMDLINE environment variable:
+//      KERNEL_CMDLINE="quiet log_level=debug" cargo run
+// each parameter is either a flag (`quiet`) or a key=value pair, separated by whitespace.
+// unknown or malformed parameters only print a warning, a typo shouldnt stop the kernel from booting.
+//
+// ** Boot Stages
+// init brings the kernel up in steps, and some things are only safe after a certain step (ie.
+// an interrupt arriving before the IDT is loaded triple faults). the current stage is recorded
+// here so code can check instead of relying on being called in the right order.
+//
+// ** Reserved Memory
+// some physical memory is in use before the kernel ever allocates anything (ACPI tables, a
+// framebuffer, the kernel image itself). those ranges are reserved here during boot and the
+// frame allocator has to skip them, otherwise it would hand out frames that are still alive.
+
+use crate::serial_println;
+use core::sync::atomic::{AtomicU8, Ordering};
+use spin::{Mutex, Once};
+use x86_64::PhysAddr;
+
+/// the command line the kernel was built with
+pub const BUILTIN_CMDLINE: &str = match option_env!("KERNEL_CMDLINE") {
+    Some(cmdline) => cmdline,
+    None => "",
+};
+
+static ARGS: Once<KernelArgs<'static>> = Once::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum LogLevel {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+    Trace = 4,
+}
+
+impl LogLevel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<LogLevel> {
+        match s {
+            "error" => Some(LogLevel::Error),
+            "warn" => Some(LogLevel::Warn),
+            "info" => Some(LogLevel::Info),
+            "debug" => Some(LogLevel::Debug),
+            "trace" => Some(LogLevel::Trace),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelArgs<'a> {
+    pub serial_baud: Option<u32>,
+    pub quiet: bool,
+    /// runs the hardware self-tests at boot
+    pub selftest: bool,
+    pub test_filter: Option<&'a str>,
+    pub log_level: LogLevel,
+    /// the terminal on the other end of the serial port understands ANSI colors
+    pub serial_color: bool,
+    /// prints a `.` to serial about once a second, see interrupts::enable_heartbeat
+    pub heartbeat: bool,
+}
+
+impl Default for KernelArgs<'_> {
+    fn default() -> Self {
+        KernelArgs {
+            serial_baud: None,
+            quiet: false,
+            selftest: false,
+            test_filter: None,
+            log_level: LogLevel::Info,
+            serial_color: false,
+            heartbeat: false,
+        }
+    }
+}
+
+pub fn parse_cmdline(cmdline: &str) -> KernelArgs<'_> {
+    let mut args = KernelArgs::default();
+    for param in cmdline.split_whitespace() {
+        let (key, value) = match param.split_once('=') {
+            Some((key, value)) => (key, Some(value)),
+            None => (param, None),
+        };
+        match (key, value) {
+            ("quiet", None) => args.quiet = true,
+            ("selftest", None) => args.selftest = true,
+            ("serial_color", None) => args.serial_color = true,
+            ("heartbeat", None) => args.heartbeat = true,
+            ("serial_baud", Some(value)) => match value.parse() {
+                Ok(baud) => args.serial_baud = Some(baud),
+                Err(_) => {
+                    serial_println!("boot: invalid serial_baud `{}`, ignoring", value);
+                }
+            },
+            ("test_filter", Some(value)) => args.test_filter = Some(value),
+            ("log_level", Some(value)) => match LogLevel::parse(value) {
+                Some(level) => args.log_level = level,
+                None => {
+                    serial_println!("boot: invalid log_level `{}`, ignoring", value);
+                }
+            },
+            _ => {
+                serial_println!("boot: unknown kernel parameter `{}`, ignoring", param);
+            }
+        }
+    }
+    args
+}
+
+/// parses the command line once, later calls keep the first result
+pub fn init(cmdline: &'static str) {
+    ARGS.call_once(|| parse_cmdline(cmdline));
+}
+
+/// uses `args` instead of parsing a command line, ignored if init (or this) already ran
+pub fn init_with_args(args: KernelArgs<'static>) {
+    ARGS.call_once(|| args);
+}
+
+/// the parsed kernel parameters. defaults are returned if `init` hasnt run yet
+pub fn args() -> KernelArgs<'static> {
+    ARGS.get().copied().unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum BootStage {
+    /// nothing is set up yet
+    Early = 0,
+    /// the GDT, TSS and IDT are loaded, exceptions are handled
+    Descriptors = 1,
+    /// init is done
+    Ready = 2,
+    /// init_all also set up paging, the frame allocator and the heap
+    Memory = 3,
+}
+
+static STAGE: AtomicU8 = AtomicU8::new(BootStage::Early as u8);
+
+/// moves to `stage`, going back to an earlier one is ignored
+pub fn advance_stage(stage: BootStage) {
+    STAGE.fetch_max(stage as u8, Ordering::SeqCst);
+}
+
+pub fn stage() -> BootStage {
+    match STAGE.load(Ordering::SeqCst) {
+        0 => BootStage::Early,
+        1 => BootStage::Descriptors,
+        2 => BootStage::Ready,
+        _ => BootStage::Memory,
+    }
+}
+
+pub const MAX_RESERVATIONS: usize = 16;
+
+/// a range of physical memory, `end` is exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhysRange {
+    pub start: PhysAddr,
+    pub end: PhysAddr,
+}
+
+impl PhysRange {
+    pub fn new(start: u64, end: u64) -> PhysRange {
+        PhysRange {
+            start: PhysAddr::new(start),
+            end: PhysAddr::new(end),
+        }
+    }
+
+    pub fn contains(&self, addr: PhysAddr) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    /// touching ranges count as overlapping so they get merged into one
+    fn overlaps(&self, other: &PhysRange) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reservation {
+    pub range: PhysRange,
+    pub reason: &'static str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReserveError {
+    /// all MAX_RESERVATIONS slots are taken
+    TooManyReservations,
+}
+
+struct Reservations {
+    entries: [Option<Reservation>; MAX_RESERVATIONS],
+}
+
+impl Reservations {
+    const fn new() -> Reservations {
+        Reservations {
+            entries: [None; MAX_RESERVATIONS],
+        }
+    }
+
+    fn add(&mut self, range: PhysRange, reason: &'static str) -> Result<(), ReserveError> {
+        let mut merged = Reservation { range, reason };
+        // merging can make the range grow into yet another reservation, so keep going
+        // until nothing overlaps anymore
+        loop {
+            let overlapping = self
+                .entries
+                .iter_mut()
+                .find(|entry| entry.is_some_and(|entry| entry.range.overlaps(&merged.range)));
+            let Some(slot) = overlapping else { break };
+            let existing = slot.take().unwrap();
+            serial_println!(
+                "boot: reservation `{}` overlaps `{}`, merging",
+                merged.reason,
+                existing.reason
+            );
+            merged = Reservation {
+                range: PhysRange {
+                    start: existing.range.start.min(merged.range.start),
+                    end: existing.range.end.max(merged.range.end),
+                },
+                reason: existing.reason,
+            };
+        }
+        let free = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.is_none())
+            .ok_or(ReserveError::TooManyReservations)?;
+        *free = Some(merged);
+        Ok(())
+    }
+
+    fn overlaps(&self, range: &PhysRange) -> bool {
+        self.entries
+            .iter()
+            .flatten()
+            .any(|entry| entry.range.start < range.end && range.start < entry.range.end)
+    }
+}
+
+static RESERVED: Mutex<Reservations> = Mutex::new(Reservations::new());
+
+/// keeps the frame allocator away from `range`. overlapping reservations are merged
+pub fn reserve(range: PhysRange, reason: &'static str) -> Result<(), ReserveError> {
+    RESERVED.lock().add(range, reason)
+}
+
+/// true if any part of `range` is reserved
+pub fn is_reserved(range: PhysRange) -> bool {
+    RESERVED.lock().overlaps(&range)
+}
+
+/// calls `f` with every reservation
+pub fn for_each_reservation(f: impl FnMut(&Reservation)) {
+    RESERVED.lock().entries.iter().flatten().for_each(f);
+}
+
+#[test_case]
+fn test_parse_cmdline() {
+    let args = parse_cmdline(
+        "serial_baud=9600  quiet selftest test_filter=vga log_level=debug serial_color heartbeat bogus",
+    );
+    assert_eq!(args.serial_baud, Some(9600));
+    assert!(args.quiet);
+    assert!(args.selftest);
+    assert_eq!(args.test_filter, Some("vga"));
+    assert_eq!(args.log_level, LogLevel::Debug);
+    assert!(args.serial_color);
+    assert!(args.heartbeat);
+
+    assert_eq!(parse_cmdline(""), KernelArgs::default());
+}
+
+#[test_case]
+fn test_reservations_merge() {
+    let mut reservations = Reservations::new();
+    reservations
+        .add(PhysRange::new(0x10000, 0x20000), "acpi")
+        .unwrap();
+    reservations
+        .add(PhysRange::new(0x40000, 0x50000), "framebuffer")
+        .unwrap();
+    // bridges the two ranges above, so all three end up as one
+    reservations
+        .add(PhysRange::new(0x18000, 0x40000), "kernel")
+        .unwrap();
+
+    let mut entries = reservations.entries.iter().flatten();
+    let merged = entries.next().unwrap();
+    assert_eq!(merged.range, PhysRange::new(0x10000, 0x50000));
+    assert!(entries.next().is_none());
+
+    assert!(reservations.overlaps(&PhysRange::new(0x4F000, 0x50000)));
+    assert!(!reservations.overlaps(&PhysRange::new(0x50000, 0x51000)));
+    assert!(!reservations.overlaps(&PhysRange::new(0xF000, 0x10000)));
+}
@@ -0,0 +1,731 @@
+// control registers and other cpu-wide knobs live here.
+//
+// ** Floating Point Exceptions
+// both the x87 fpu and sse report errors like division by zero or an invalid operation (NaN)
+// through status flags. each of them can be masked, in which case the instruction just produces
+// a default result (inf, NaN...), or unmasked, in which case the cpu raises an exception:
+//  - #MF (vector 16) for x87 instructions, reported only when CR0.NE is set
+//  - #XM (vector 19) for sse instructions, only when CR4.OSXMMEXCPT is set (#UD otherwise)
+// everything is masked after reset, so normal float code never traps. unmasking is meant for
+// hunting down where a NaN comes from.
+//
+// ** Global Pages
+// every time CR3 is reloaded (ie. when switching address spaces), the cpu throws away its whole
+// TLB (translation lookaside buffer, the cache of virtual -> physical translations).
+// kernel mappings are the same in every address space, so throwing them away is wasted work.
+// setting CR4.PGE lets page table entries marked GLOBAL survive a CR3 reload.
+//
+// ** Lazy FPU State Switching
+// every thread that uses the fpu/sse has its own registers (x87 stack, xmm0-15, MXCSR...) that
+// have to be swapped on a context switch. fxsave/fxrstor move all of it to and from a 512 byte,
+// 16 byte aligned area, which is slow enough that we only want to do it when needed:
+//  1. on a switch, we just set CR0.TS (task switched)
+//  2. the first fpu/sse instruction the new thread executes raises #NM (device not available)
+//  3. the #NM handler saves the registers into the previous owner's area, loads the new
+//      thread's area and clears TS, then the instruction is retried
+// a thread that never touches the fpu never pays for any of this.
+
+use core::arch::asm;
+use core::arch::x86_64::__cpuid;
+use core::fmt;
+use core::ptr::NonNull;
+use spin::Mutex;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+use x86_64::registers::model_specific;
+use x86_64::registers::mxcsr::{self, MxCsr};
+
+// x87 control word exception masks
+const X87_INVALID_OPERATION_MASK: u16 = 1 << 0;
+const X87_DIVIDE_BY_ZERO_MASK: u16 = 1 << 2;
+const X87_OVERFLOW_MASK: u16 = 1 << 3;
+
+/// tells the cpu we are busy waiting (pause). it backs off for a moment instead of hammering the
+/// memory bus, which saves power and leaves the core to the other hyperthread.
+/// goes into every polling loop, spin::Mutex already does this on its own
+#[inline(always)]
+pub fn spin_hint() {
+    unsafe { asm!("pause", options(nomem, nostack, preserves_flags)) };
+}
+
+/// sets CR4.PGE so that GLOBAL mappings are kept in the TLB across CR3 reloads
+pub fn enable_global_pages() {
+    unsafe {
+        Cr4::update(|flags| flags.insert(Cr4Flags::PAGE_GLOBAL));
+    }
+}
+
+/// sets CR0.WP so that read only pages are read only for the kernel too. without it ring 0
+/// writes go through, and copy-on-write pages never fault
+pub fn enable_write_protect() {
+    unsafe {
+        Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT));
+    }
+}
+
+/// lets the kernel execute sse instructions. our target is soft-float so the compiler
+/// never emits them on its own
+pub fn enable_sse() {
+    unsafe {
+        Cr0::update(|flags| {
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR);
+            flags.insert(Cr0Flags::MONITOR_COPROCESSOR);
+        });
+        Cr4::update(|flags| flags.insert(Cr4Flags::OSFXSR));
+    }
+}
+
+/// unmasks the invalid operation, divide-by-zero and overflow exceptions of both the x87 fpu
+/// and sse, so the math producing NaN or inf traps. sse has to be enabled first
+pub fn unmask_fp_exceptions() {
+    unsafe {
+        Cr0::update(|flags| flags.insert(Cr0Flags::NUMERIC_ERROR));
+        Cr4::update(|flags| flags.insert(Cr4Flags::OSXMMEXCPT_ENABLE));
+    }
+    mxcsr::update(|flags| {
+        flags.remove(
+            MxCsr::INVALID_OPERATION_MASK | MxCsr::DIVIDE_BY_ZERO_MASK | MxCsr::OVERFLOW_MASK,
+        )
+    });
+
+    let mut control_word: u16 = 0;
+    unsafe {
+        asm!("fnstcw [{}]", in(reg) &mut control_word, options(nostack));
+        control_word &= !(X87_INVALID_OPERATION_MASK | X87_DIVIDE_BY_ZERO_MASK | X87_OVERFLOW_MASK);
+        asm!("fldcw [{}]", in(reg) &control_word, options(nostack));
+    }
+}
+
+/// the fpu/sse registers of one thread, as laid out by fxsave
+#[repr(C, align(16))]
+pub struct FxState([u8; 512]);
+
+impl Default for FxState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FxState {
+    /// the state after `fninit`: x87 control word 0x037F and MXCSR 0x1F80, ie. every
+    /// exception masked. an all zero area would unmask all of them
+    pub const fn new() -> FxState {
+        let mut area = [0; 512];
+        area[0] = 0x7F;
+        area[1] = 0x03;
+        area[24] = 0x80;
+        area[25] = 0x1F;
+        FxState(area)
+    }
+
+    pub fn save(&mut self) {
+        unsafe {
+            asm!("fxsave64 [{}]", in(reg) self.0.as_mut_ptr(), options(nostack));
+        }
+    }
+
+    pub fn restore(&self) {
+        unsafe {
+            asm!("fxrstor64 [{}]", in(reg) self.0.as_ptr(), options(nostack));
+        }
+    }
+}
+
+struct LazyFpu {
+    /// whose state is in the registers right now
+    owner: Option<NonNull<FxState>>,
+    /// whose state should be
+    current: Option<NonNull<FxState>>,
+}
+
+// the pointers are only ever touched with the lock held
+unsafe impl Send for LazyFpu {}
+
+static LAZY_FPU: Mutex<LazyFpu> = Mutex::new(LazyFpu {
+    owner: None,
+    current: None,
+});
+
+/// makes `next` the fpu state of whatever runs from now on. the registers are only swapped once
+/// it actually uses the fpu.
+///
+/// # Safety
+/// `next` has to stay valid (and not move) as long as it may own the fpu
+pub unsafe fn switch_fpu_state(next: *mut FxState) {
+    let mut fpu = LAZY_FPU.lock();
+    fpu.current = NonNull::new(next);
+    if fpu.owner != fpu.current {
+        unsafe {
+            Cr0::update(|flags| flags.insert(Cr0Flags::TASK_SWITCHED));
+        }
+    }
+}
+
+/// the #NM part of the lazy switch, called by the device not available handler
+pub(crate) fn handle_device_not_available() {
+    let mut fpu = LAZY_FPU.lock();
+    // clear TS first, fxsave/fxrstor would raise #NM themselves otherwise
+    unsafe {
+        Cr0::update(|flags| flags.remove(Cr0Flags::TASK_SWITCHED));
+    }
+    let current = fpu
+        .current
+        .expect("#NM without a current fpu state, CR0.TS was set outside switch_fpu_state");
+    if fpu.owner == Some(current) {
+        return;
+    }
+    unsafe {
+        if let Some(mut owner) = fpu.owner {
+            owner.as_mut().save();
+        }
+        current.as_ref().restore();
+    }
+    fpu.owner = Some(current);
+}
+
+// ** MSRs (Model Specific Registers)
+// rdmsr/wrmsr take the msr number in ecx and the value in edx:eax. touching a number the cpu
+// doesnt implement raises #GP, so the kernel names the msrs it uses in the Msr enum instead of
+// passing numbers around:
+// MSR	        Name	        Description
+// 0x1B	        APIC_BASE	    bit 8: bootstrap processor, bit 11: apic enabled, bits 12+: base
+// 0x277	    PAT	            8 memory types, one byte each, selected by the PAT/PCD/PWT bits
+// 0xC0000080	EFER	        bit 0: syscall, bit 8: long mode enable, bit 11: no-execute enable
+// 0xC0000100	FS_BASE	        base address of the fs segment
+// 0xC0000101	GS_BASE	        base address of the gs segment
+// 0xC0000102	KERNEL_GS_BASE	swapped with GS_BASE by swapgs
+// every cpu we can boot on (ie. in long mode) has all of these, so reading them is always fine.
+// msrs that depend on a cpuid feature (like the MTRRs below) go through read_msr_raw after checking it.
+
+/// the msrs the kernel touches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Msr {
+    ApicBase = 0x1B,
+    Pat = 0x277,
+    Efer = 0xC000_0080,
+    FsBase = 0xC000_0100,
+    GsBase = 0xC000_0101,
+    KernelGsBase = 0xC000_0102,
+}
+
+pub fn read_msr(msr: Msr) -> u64 {
+    unsafe { read_msr_raw(msr as u32) }
+}
+
+/// # Safety
+/// the value can change how memory is cached, mapped (EFER.NXE) or addressed (the bases), so the
+/// caller has to make sure nothing relying on the old value breaks
+pub unsafe fn write_msr(msr: Msr, value: u64) {
+    unsafe { write_msr_raw(msr as u32, value) }
+}
+
+/// reads any msr by number, for experimenting with ones that are not in Msr yet
+///
+/// # Safety
+/// the cpu has to implement `msr`, it raises #GP otherwise
+pub unsafe fn read_msr_raw(msr: u32) -> u64 {
+    unsafe { model_specific::Msr::new(msr).read() }
+}
+
+/// # Safety
+/// the cpu has to implement `msr`, and the same as write_msr applies to the value
+pub unsafe fn write_msr_raw(msr: u32, value: u64) {
+    unsafe { model_specific::Msr::new(msr).write(value) }
+}
+
+// ** MTRRs (Memory Type Range Registers)
+// MTRRs tell the cpu how to cache physical memory ranges. RAM should be write-back, MMIO
+// uncacheable and a framebuffer write-combining. a wrong type doesnt break anything but can make
+// MMIO painfully slow, so being able to look at them helps.
+// MSR	        Name	        Description
+// 0xFE	        MTRRCAP	        bits 0-7: variable range count, bit 8: fixed ranges, bit 10: WC
+// 0x2FF	    MTRR_DEF_TYPE	bits 0-7: default type, bit 10: fixed ranges on, bit 11: MTRRs on
+// 0x250	    FIX64K_00000	8 x 64KiB ranges for 0x00000-0x7FFFF
+// 0x258-0x259	FIX16K_*	    16 x 16KiB ranges for 0x80000-0xBFFFF
+// 0x268-0x26F	FIX4K_*	        64 x 4KiB ranges for 0xC0000-0xFFFFF
+// 0x200+2n	    PHYSBASEn	    bits 0-7: type, bits 12+: base address
+// 0x201+2n	    PHYSMASKn	    bit 11: valid, bits 12+: mask
+// each fixed range MSR holds 8 types, one byte each.
+// this is read only, nothing in here changes the MTRRs.
+const IA32_MTRRCAP: u32 = 0xFE;
+const IA32_MTRR_DEF_TYPE: u32 = 0x2FF;
+const IA32_MTRR_PHYSBASE0: u32 = 0x200;
+const FIXED_RANGE_MSRS: [u32; 11] = [
+    0x250, 0x258, 0x259, 0x268, 0x269, 0x26A, 0x26B, 0x26C, 0x26D, 0x26E, 0x26F,
+];
+/// we only look at this many variable ranges, cpus usually have 8 to 10
+pub const MAX_VARIABLE_MTRRS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryType {
+    Uncacheable,
+    WriteCombining,
+    WriteThrough,
+    WriteProtected,
+    WriteBack,
+    Reserved(u8),
+}
+
+impl From<u8> for MemoryType {
+    fn from(value: u8) -> MemoryType {
+        match value {
+            0 => MemoryType::Uncacheable,
+            1 => MemoryType::WriteCombining,
+            4 => MemoryType::WriteThrough,
+            5 => MemoryType::WriteProtected,
+            6 => MemoryType::WriteBack,
+            other => MemoryType::Reserved(other),
+        }
+    }
+}
+
+impl fmt::Display for MemoryType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryType::Uncacheable => write!(f, "UC"),
+            MemoryType::WriteCombining => write!(f, "WC"),
+            MemoryType::WriteThrough => write!(f, "WT"),
+            MemoryType::WriteProtected => write!(f, "WP"),
+            MemoryType::WriteBack => write!(f, "WB"),
+            MemoryType::Reserved(value) => write!(f, "reserved({:#x})", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VariableRange {
+    pub base: u64,
+    pub size: u64,
+    pub memory_type: MemoryType,
+}
+
+impl VariableRange {
+    /// decodes a PHYSBASE/PHYSMASK pair, None if the range isnt enabled
+    pub fn decode(phys_base: u64, phys_mask: u64, phys_addr_bits: u8) -> Option<VariableRange> {
+        const VALID: u64 = 1 << 11;
+        if phys_mask & VALID == 0 {
+            return None;
+        }
+        let addr_mask = (1u64 << phys_addr_bits) - 1;
+        let mask = phys_mask & addr_mask & !0xFFF;
+        Some(VariableRange {
+            base: phys_base & addr_mask & !0xFFF,
+            // the mask has a 1 for every address bit that has to match the base
+            size: (!mask & addr_mask) + 1,
+            memory_type: MemoryType::from(phys_base as u8),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MtrrSummary {
+    pub enabled: bool,
+    pub fixed_enabled: bool,
+    pub write_combining_supported: bool,
+    pub default_type: MemoryType,
+    /// the types of the 88 fixed ranges below 1MiB, None if the cpu has no fixed ranges
+    pub fixed: Option<[MemoryType; 88]>,
+    pub variable: [Option<VariableRange>; MAX_VARIABLE_MTRRS],
+}
+
+impl fmt::Display for MtrrSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "MTRRs {}, default type {}, fixed ranges {}, WC {}",
+            if self.enabled { "enabled" } else { "disabled" },
+            self.default_type,
+            if self.fixed_enabled { "on" } else { "off" },
+            if self.write_combining_supported {
+                "supported"
+            } else {
+                "unsupported"
+            },
+        )?;
+        if let Some(fixed) = self.fixed {
+            write!(f, "fixed:")?;
+            for memory_type in fixed {
+                write!(f, " {}", memory_type)?;
+            }
+            writeln!(f)?;
+        }
+        for (i, range) in self.variable.iter().enumerate() {
+            if let Some(range) = range {
+                writeln!(
+                    f,
+                    "variable {}: {:#x} - {:#x} {}",
+                    i,
+                    range.base,
+                    range.base + range.size - 1,
+                    range.memory_type
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// reads the MTRR configuration, None if the cpu doesnt have MTRRs
+pub fn read_mtrrs() -> Option<MtrrSummary> {
+    // the MTRR msrs dont exist without the feature, reading them would #GP
+    if !has_feature(Feature::Mtrr) {
+        return None;
+    }
+    let phys_addr_bits = if __cpuid(0x8000_0000).eax >= 0x8000_0008 {
+        __cpuid(0x8000_0008).eax as u8
+    } else {
+        36
+    };
+
+    let (cap, def_type) = unsafe { (read_msr_raw(IA32_MTRRCAP), read_msr_raw(IA32_MTRR_DEF_TYPE)) };
+    let variable_count = (cap & 0xFF) as usize;
+
+    let fixed = if cap & (1 << 8) != 0 {
+        let mut types = [MemoryType::Uncacheable; 88];
+        for (i, &msr) in FIXED_RANGE_MSRS.iter().enumerate() {
+            let value = unsafe { read_msr_raw(msr) };
+            for byte in 0..8 {
+                types[i * 8 + byte] = MemoryType::from((value >> (byte * 8)) as u8);
+            }
+        }
+        Some(types)
+    } else {
+        None
+    };
+
+    let mut variable = [None; MAX_VARIABLE_MTRRS];
+    for (n, range) in variable.iter_mut().enumerate().take(variable_count) {
+        let msr = IA32_MTRR_PHYSBASE0 + 2 * n as u32;
+        let (base, mask) = unsafe { (read_msr_raw(msr), read_msr_raw(msr + 1)) };
+        *range = VariableRange::decode(base, mask, phys_addr_bits);
+    }
+
+    Some(MtrrSummary {
+        enabled: def_type & (1 << 11) != 0,
+        fixed_enabled: def_type & (1 << 10) != 0,
+        write_combining_supported: cap & (1 << 10) != 0,
+        default_type: MemoryType::from(def_type as u8),
+        fixed,
+        variable,
+    })
+}
+
+// ** CPUID Features
+// leaf 1 reports what the cpu can do as single bits in ecx and edx. leaf 0 returns the highest
+// basic leaf in eax and the 12 byte vendor id in ebx, edx, ecx (in that order, unlike the
+// hypervisor signature below). the brand string is 48 bytes spread over the extended leaves
+// 0x80000002-0x80000004, which only exist when leaf 0x80000000 says so.
+// Feature	    Leaf 1 bit
+// SSE	        edx 25
+// SSE2	        edx 26
+// APIC	        edx 9
+// MTRR	        edx 12
+// x2APIC	    ecx 21
+// RDRAND	    ecx 30
+// Hypervisor	ecx 31
+// a feature being there says nothing about it being enabled: SSE still needs enable_sse, the
+// x2APIC mode an msr write.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    Sse,
+    Sse2,
+    Apic,
+    Mtrr,
+    X2Apic,
+    Rdrand,
+    Hypervisor,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 7] = [
+        Feature::Sse,
+        Feature::Sse2,
+        Feature::Apic,
+        Feature::Mtrr,
+        Feature::X2Apic,
+        Feature::Rdrand,
+        Feature::Hypervisor,
+    ];
+
+    /// the register (false: edx, true: ecx) and bit of leaf 1 that reports the feature
+    fn location(self) -> (bool, u32) {
+        match self {
+            Feature::Sse => (false, 25),
+            Feature::Sse2 => (false, 26),
+            Feature::Apic => (false, 9),
+            Feature::Mtrr => (false, 12),
+            Feature::X2Apic => (true, 21),
+            Feature::Rdrand => (true, 30),
+            Feature::Hypervisor => (true, 31),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::Sse => "sse",
+            Feature::Sse2 => "sse2",
+            Feature::Apic => "apic",
+            Feature::Mtrr => "mtrr",
+            Feature::X2Apic => "x2apic",
+            Feature::Rdrand => "rdrand",
+            Feature::Hypervisor => "hypervisor",
+        }
+    }
+}
+
+/// whether the cpu reports `feature` in cpuid leaf 1
+pub fn has_feature(feature: Feature) -> bool {
+    let leaf = __cpuid(1);
+    let (in_ecx, bit) = feature.location();
+    let register = if in_ecx { leaf.ecx } else { leaf.edx };
+    register & (1 << bit) != 0
+}
+
+/// the vendor id, ie. "GenuineIntel", "AuthenticAMD"
+pub fn vendor() -> [u8; 12] {
+    let leaf = __cpuid(0);
+    let mut vendor = [0u8; 12];
+    vendor[0..4].copy_from_slice(&leaf.ebx.to_le_bytes());
+    vendor[4..8].copy_from_slice(&leaf.edx.to_le_bytes());
+    vendor[8..12].copy_from_slice(&leaf.ecx.to_le_bytes());
+    vendor
+}
+
+/// the model name the cpu reports, ie. "QEMU Virtual CPU version 2.5+"
+pub struct BrandString([u8; 48]);
+
+impl BrandString {
+    /// without the padding. cpus pad with NULs at the end and some with spaces at the front
+    pub fn as_str(&self) -> &str {
+        let end = self.0.iter().position(|&b| b == 0).unwrap_or(self.0.len());
+        core::str::from_utf8(&self.0[..end]).unwrap_or("").trim()
+    }
+}
+
+impl fmt::Display for BrandString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// the brand string, None if the cpu doesnt have the extended leaves for it
+pub fn brand_string() -> Option<BrandString> {
+    if __cpuid(0x8000_0000).eax < 0x8000_0004 {
+        return None;
+    }
+    let mut brand = [0u8; 48];
+    for (i, chunk) in brand.chunks_exact_mut(16).enumerate() {
+        let leaf = __cpuid(0x8000_0002 + i as u32);
+        for (j, register) in [leaf.eax, leaf.ebx, leaf.ecx, leaf.edx].iter().enumerate() {
+            chunk[j * 4..j * 4 + 4].copy_from_slice(&register.to_le_bytes());
+        }
+    }
+    Some(BrandString(brand))
+}
+
+/// vendor, brand and the features from the table above, printed at boot
+pub struct CpuSummary;
+
+impl fmt::Display for CpuSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let vendor = vendor();
+        writeln!(
+            f,
+            "vendor  {}",
+            core::str::from_utf8(&vendor).unwrap_or("unknown")
+        )?;
+        match brand_string() {
+            Some(brand) => writeln!(f, "brand   {}", brand)?,
+            None => writeln!(f, "brand   unknown")?,
+        }
+        write!(f, "features")?;
+        for feature in Feature::ALL {
+            if has_feature(feature) {
+                write!(f, " {}", feature.name())?;
+            }
+        }
+        writeln!(f)
+    }
+}
+
+// ** Hypervisor Detection
+// a hypervisor sets bit 31 of ecx in cpuid leaf 1 (a real cpu always reports 0 there), and then
+// answers leaf 0x40000000 with a 12 byte vendor signature in ebx, ecx, edx:
+// Signature	    Hypervisor
+// KVMKVMKVM	    KVM (ie. qemu with -enable-kvm)
+// TCGTCGTCGTCG	    qemu without kvm (the Tiny Code Generator)
+// VMwareVMware	    VMware
+// Microsoft Hv	    Hyper-V
+// XenVMMXenVMM	    Xen
+// VBoxVBoxVBox	    VirtualBox
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hypervisor {
+    Kvm,
+    Tcg,
+    VMware,
+    HyperV,
+    Xen,
+    VirtualBox,
+    Other([u8; 12]),
+}
+
+impl Hypervisor {
+    fn from_signature(signature: [u8; 12]) -> Hypervisor {
+        match &signature {
+            b"KVMKVMKVM\0\0\0" => Hypervisor::Kvm,
+            b"TCGTCGTCGTCG" => Hypervisor::Tcg,
+            b"VMwareVMware" => Hypervisor::VMware,
+            b"Microsoft Hv" => Hypervisor::HyperV,
+            b"XenVMMXenVMM" => Hypervisor::Xen,
+            b"VBoxVBoxVBox" => Hypervisor::VirtualBox,
+            _ => Hypervisor::Other(signature),
+        }
+    }
+
+    /// qemu, with or without kvm
+    pub fn is_qemu(self) -> bool {
+        matches!(self, Hypervisor::Kvm | Hypervisor::Tcg)
+    }
+}
+
+/// the hypervisor we run under, None on real hardware
+pub fn detect_hypervisor() -> Option<Hypervisor> {
+    if !has_feature(Feature::Hypervisor) {
+        return None;
+    }
+    let leaf = __cpuid(0x4000_0000);
+    let mut signature = [0u8; 12];
+    signature[0..4].copy_from_slice(&leaf.ebx.to_le_bytes());
+    signature[4..8].copy_from_slice(&leaf.ecx.to_le_bytes());
+    signature[8..12].copy_from_slice(&leaf.edx.to_le_bytes());
+    Some(Hypervisor::from_signature(signature))
+}
+
+// ** Cycle Counting
+// the tsc (time stamp counter) counts cpu cycles since reset, rdtsc reads it into edx:eax.
+// rdtsc is not serializing: the cpu is free to run it before the instructions in front of it
+// have finished, or to start the ones after it early, which blurs short measurements. an lfence
+// on both sides keeps it in place, the first waits for everything before it and the second
+// holds back everything after it. (rdtscp only gives the first half of that.)
+// the counts are cycles at the tsc's own rate, which on modern cpus is constant and not the
+// current clock speed, and under qemu without kvm it is derived from the host's clock.
+
+/// the time stamp counter, fenced so it doesnt move around the code being measured
+#[inline(always)]
+pub fn rdtsc() -> u64 {
+    use core::arch::x86_64::{_mm_lfence, _rdtsc};
+    unsafe {
+        _mm_lfence();
+        let tsc = _rdtsc();
+        _mm_lfence();
+        tsc
+    }
+}
+
+/// runs `f` `iters` times and prints the average cycles per call to serial, which it also
+/// returns. the loop and the timer interrupts that land in it are counted too, so numbers only
+/// mean something next to another bench run the same way
+pub fn bench(name: &str, iters: u64, mut f: impl FnMut()) -> u64 {
+    let iters = iters.max(1);
+    let start = rdtsc();
+    for _ in 0..iters {
+        // keeps the compiler from proving the call does nothing and dropping the loop
+        core::hint::black_box(&mut f)();
+    }
+    let average = (rdtsc() - start) / iters;
+    crate::serial_println!(
+        "bench {}: {} cycles/iter over {} iters",
+        name,
+        average,
+        iters
+    );
+    average
+}
+
+#[test_case]
+fn test_decode_mtrr() {
+    assert_eq!(MemoryType::from(6), MemoryType::WriteBack);
+    assert_eq!(MemoryType::from(1), MemoryType::WriteCombining);
+    assert_eq!(MemoryType::from(2), MemoryType::Reserved(2));
+
+    // 0x8000_0000 - 0xFFFF_FFFF uncacheable, with 36 physical address bits
+    let range = VariableRange::decode(0x8000_0000, 0xF_8000_0800, 36).unwrap();
+    assert_eq!(range.base, 0x8000_0000);
+    assert_eq!(range.size, 0x8000_0000);
+    assert_eq!(range.memory_type, MemoryType::Uncacheable);
+    // the valid bit is clear
+    assert_eq!(VariableRange::decode(0x6, 0xF_8000_0000, 36), None);
+}
+
+#[test_case]
+fn test_lazy_fpu_states_are_separate() {
+    static mut FIRST: FxState = FxState::new();
+    static mut SECOND: FxState = FxState::new();
+    enable_sse();
+
+    unsafe { switch_fpu_state(&raw mut FIRST) };
+    // this traps into #NM, which loads FIRST
+    mxcsr::write(MxCsr::default() | MxCsr::ROUNDING_CONTROL_ZERO);
+
+    unsafe { switch_fpu_state(&raw mut SECOND) };
+    let second = mxcsr::read();
+
+    unsafe { switch_fpu_state(&raw mut FIRST) };
+    let first = mxcsr::read();
+
+    assert_eq!(second, MxCsr::default());
+    assert_eq!(first, MxCsr::default() | MxCsr::ROUNDING_CONTROL_ZERO);
+}
+
+#[test_case]
+fn test_read_apic_base() {
+    let apic_base = read_msr(Msr::ApicBase);
+    assert_ne!(apic_base, 0);
+    // the base is page aligned, the low bits only hold flags
+    assert_ne!(apic_base & !0xFFF, 0);
+    // we are running in long mode
+    assert_ne!(read_msr(Msr::Efer) & (1 << 8), 0);
+}
+
+#[test_case]
+fn test_detect_hypervisor() {
+    // the tests only ever run in qemu, which reports itself as KVM or TCG
+    assert!(detect_hypervisor().is_some_and(Hypervisor::is_qemu));
+}
+
+#[test_case]
+fn test_cpuid_vendor_and_features() {
+    // qemu reports the host's vendor with kvm and "GenuineIntel" or "AuthenticAMD" without
+    let vendor = vendor();
+    assert!(vendor.iter().any(|&b| b != 0));
+    assert!(vendor.is_ascii());
+    assert!(brand_string().is_some_and(|brand| !brand.as_str().is_empty()));
+    // every x86_64 cpu has sse and sse2, the rest is what qemu's cpu models all report
+    assert!(has_feature(Feature::Sse));
+    assert!(has_feature(Feature::Sse2));
+    assert!(has_feature(Feature::Apic));
+    assert!(has_feature(Feature::Hypervisor));
+}
+
+#[test_case]
+fn test_spin_hint_polling_terminates() {
+    // polls until the tsc moved on by a bit, the loop must still see the change
+    let start = rdtsc();
+    let mut polls = 0u64;
+    while rdtsc() - start < 10_000 {
+        spin_hint();
+        polls += 1;
+    }
+    assert!(polls > 0);
+}
+
+#[test_case]
+fn test_bench_empty_closure() {
+    let cycles = bench("empty closure", 1000, || {});
+    // the loop and the call alone take a few cycles, an interrupt here and there cant add
+    // thousands to the average
+    assert!(cycles > 0);
+    assert!(cycles < 10_000, "{} cycles for nothing", cycles);
+}
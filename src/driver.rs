@@ -0,0 +1,215 @@
+// every device driver goes through the same steps: find out whether a device is one it can
+// drive, set it up, then service its interrupts. the Driver trait is that lifecycle, and the
+// registry here ties drivers to devices and IRQ lines:
+//  1. drivers register themselves once at boot
+//  2. bus enumeration (ie. PCI) calls bind for every device it finds. the first registered driver
+//      whose probe accepts the device gets it, and is initialized right away
+//  3. once init is done, the device's IRQ line (if it has one) is routed to the driver. the
+//      interrupt handler for that line calls dispatch_irq, which runs handle_irq of every driver
+//      bound to it (PCI lines can be shared)
+// the order in 3 matters: dispatch_irq locks the driver from interrupt context, an irq arriving
+// while init holds that lock would spin forever.
+// only the lines the IDT has handlers for (the timer and the keyboard for now) are dispatched.
+//
+// there is no heap, so drivers are statics (behind a spin Mutex since init and handle_irq need
+// them mutable) and the registry is a couple of fixed size tables.
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+pub const MAX_DRIVERS: usize = 16;
+pub const MAX_BINDINGS: usize = 32;
+
+/// what bus enumeration knows about a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    /// the legacy IRQ line the device interrupts on, if any
+    pub irq: Option<u8>,
+}
+
+pub trait Driver: Send {
+    fn name(&self) -> &'static str;
+    /// whether this driver can drive `device`. must not touch the hardware
+    fn probe(&self, device: &DeviceInfo) -> bool;
+    /// called once the device is bound to this driver
+    fn init(&mut self);
+    /// called from interrupt context, with interrupts disabled, before the EOI is sent
+    fn handle_irq(&mut self);
+}
+
+type DriverRef = &'static Mutex<dyn Driver>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriverError {
+    /// all MAX_DRIVERS (or MAX_BINDINGS) slots are taken
+    Full,
+    /// no registered driver accepts the device
+    NoDriver,
+}
+
+struct Registry {
+    drivers: [Option<DriverRef>; MAX_DRIVERS],
+    /// IRQ line and the driver serving it
+    bindings: [Option<(u8, DriverRef)>; MAX_BINDINGS],
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    drivers: [None; MAX_DRIVERS],
+    bindings: [None; MAX_BINDINGS],
+});
+
+/// adds `driver` to the drivers bind tries, in registration order
+pub fn register(driver: DriverRef) -> Result<(), DriverError> {
+    // dispatch_irq takes the same lock from interrupt context
+    interrupts::without_interrupts(|| {
+        let mut registry = REGISTRY.lock();
+        let slot = registry
+            .drivers
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(DriverError::Full)?;
+        *slot = Some(driver);
+        Ok(())
+    })
+}
+
+/// finds a driver for `device`, initializes it and routes the device's IRQ to it.
+/// returns the name of the driver
+pub fn bind(device: &DeviceInfo) -> Result<&'static str, DriverError> {
+    let driver = interrupts::without_interrupts(|| {
+        REGISTRY
+            .lock()
+            .drivers
+            .into_iter()
+            .flatten()
+            .find(|driver| driver.lock().probe(device))
+            .ok_or(DriverError::NoDriver)
+    })?;
+    // init runs with interrupts enabled, it may have to wait for the device. its irq isnt
+    // routed to it yet, so dispatch_irq cant be waiting for the lock we hold here
+    let name = {
+        let mut driver = driver.lock();
+        driver.init();
+        driver.name()
+    };
+    if let Some(irq) = device.irq {
+        interrupts::without_interrupts(|| {
+            let mut registry = REGISTRY.lock();
+            let slot = registry
+                .bindings
+                .iter_mut()
+                .find(|slot| slot.is_none())
+                .ok_or(DriverError::Full)?;
+            *slot = Some((irq, driver));
+            Ok(())
+        })?;
+    }
+    Ok(name)
+}
+
+/// runs the IRQ handlers of the drivers bound to `irq`, returns false if there are none.
+/// has to be called with interrupts disabled
+pub fn dispatch_irq(irq: u8) -> bool {
+    let bindings = REGISTRY.lock().bindings;
+    let mut handled = false;
+    for (line, driver) in bindings.into_iter().flatten() {
+        if line == irq {
+            driver.lock().handle_irq();
+            handled = true;
+        }
+    }
+    handled
+}
+
+#[cfg(test)]
+struct MockDriver {
+    inits: usize,
+    irqs: usize,
+}
+
+#[cfg(test)]
+impl Driver for MockDriver {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn probe(&self, device: &DeviceInfo) -> bool {
+        device.vendor_id == 0x1234 && device.class == 0x02
+    }
+
+    fn init(&mut self) {
+        self.inits += 1;
+    }
+
+    fn handle_irq(&mut self) {
+        self.irqs += 1;
+    }
+}
+
+#[test_case]
+fn test_mock_driver_binds_and_gets_irqs() {
+    static MOCK: Mutex<MockDriver> = Mutex::new(MockDriver { inits: 0, irqs: 0 });
+    let saved = interrupts::without_interrupts(|| {
+        let registry = REGISTRY.lock();
+        (registry.drivers, registry.bindings)
+    });
+
+    register(&MOCK).unwrap();
+    let nic = DeviceInfo {
+        vendor_id: 0x1234,
+        device_id: 0x1111,
+        class: 0x02,
+        subclass: 0x00,
+        irq: Some(11),
+    };
+    let unknown = DeviceInfo {
+        vendor_id: 0x8086,
+        ..nic
+    };
+    assert_eq!(bind(&unknown), Err(DriverError::NoDriver));
+    assert_eq!(bind(&nic), Ok("mock"));
+    assert_eq!(MOCK.lock().inits, 1);
+
+    interrupts::without_interrupts(|| {
+        assert!(dispatch_irq(11));
+        assert!(!dispatch_irq(10));
+    });
+    assert_eq!(MOCK.lock().irqs, 1);
+
+    interrupts::without_interrupts(|| {
+        let mut registry = REGISTRY.lock();
+        (registry.drivers, registry.bindings) = saved;
+    });
+}
+
+#[test_case]
+fn test_timer_irq_is_dispatched() {
+    static MOCK: Mutex<MockDriver> = Mutex::new(MockDriver { inits: 0, irqs: 0 });
+    let saved = interrupts::without_interrupts(|| {
+        let registry = REGISTRY.lock();
+        (registry.drivers, registry.bindings)
+    });
+
+    register(&MOCK).unwrap();
+    let device = DeviceInfo {
+        vendor_id: 0x1234,
+        device_id: 0x2222,
+        class: 0x02,
+        subclass: 0x00,
+        irq: Some(crate::pic::InterruptIndex::Timer.irq()),
+    };
+    assert_eq!(bind(&device), Ok("mock"));
+    crate::time::sleep_ms(100);
+    // the timer handler locks the driver too
+    let irqs = interrupts::without_interrupts(|| MOCK.lock().irqs);
+
+    interrupts::without_interrupts(|| {
+        let mut registry = REGISTRY.lock();
+        (registry.drivers, registry.bindings) = saved;
+    });
+    assert!(irqs > 0);
+}
@@ -59,34 +59,92 @@
 // it was used for memory segmentation before paging became a thing, but its still used in 64 bit mode
 // for various stuff like kernel/user mode config/switching or TSS loading
 
+use core::ops::Range;
 use lazy_static::lazy_static;
+use spin::Once;
 use x86_64::VirtAddr;
+use x86_64::instructions::interrupts;
 use x86_64::instructions::{segmentation::Segment, tables::load_tss};
 use x86_64::registers::segmentation::CS;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{FrameAllocator, Mapper, Page, Size4KiB};
 use x86_64::structures::tss::TaskStateSegment;
 
+use crate::memory::GuardedStack;
+
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+/// IST entry of the page fault handler. a page fault can hit when the kernel stack is nearly
+/// used up, so the handler gets a stack of its own instead of whatever is left of that one
+pub const PAGE_FAULT_IST_INDEX: u16 = 1;
+/// IST entry of the hardware irq handlers (timer, keyboard). an irq can arrive on top of any
+/// call chain, so giving them their own stack keeps their usage from adding up with the kernel's
+pub const IRQ_IST_INDEX: u16 = 2;
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        // defining the 0th IST entry as double fault stack
-        // then assigning the top addr of this stack to IST[0]
-        // the reasoning behind assigning the top address is that
-        // stack grows downwards!
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(&raw const STACK);
-            //stack end
-            stack_start + STACK_SIZE as u64
+const DOUBLE_FAULT_STACK_SIZE: usize = 4096 * 5;
+static mut DOUBLE_FAULT_STACK: [u8; DOUBLE_FAULT_STACK_SIZE] = [0; DOUBLE_FAULT_STACK_SIZE];
 
-        };
-        tss
-    };
+// a page fault inside the page fault handler starts again at the top of this same stack and
+// overwrites the outer frame, so the handler must not touch anything that can fault
+const PAGE_FAULT_STACK_SIZE: usize = 4096 * 5;
+static mut PAGE_FAULT_STACK: [u8; PAGE_FAULT_STACK_SIZE] = [0; PAGE_FAULT_STACK_SIZE];
+
+const IRQ_STACK_SIZE: usize = 4096 * 5;
+// only used until init_guarded_irq_stack runs, nothing below a static catches an overflow
+static mut IRQ_STACK: [u8; IRQ_STACK_SIZE] = [0; IRQ_STACK_SIZE];
+
+/// the guard page of the irq stack init_guarded_irq_stack maps, the stack is right above it.
+/// far away from everything the bootloader maps, like the heap
+pub const IRQ_STACK_GUARD: u64 = 0x_5555_5555_0000;
+static GUARDED_IRQ_STACK: Once<GuardedStack> = Once::new();
+
+/// the addresses of the double fault stack, the cpu starts at `end` and grows down
+pub fn double_fault_stack() -> Range<VirtAddr> {
+    let start = VirtAddr::from_ptr(&raw const DOUBLE_FAULT_STACK);
+    start..start + DOUBLE_FAULT_STACK_SIZE as u64
+}
+
+/// the addresses of the page fault stack, the cpu starts at `end` and grows down
+pub fn page_fault_stack() -> Range<VirtAddr> {
+    let start = VirtAddr::from_ptr(&raw const PAGE_FAULT_STACK);
+    start..start + PAGE_FAULT_STACK_SIZE as u64
+}
+
+/// the addresses of the irq stack the irq handlers use right now: the guarded one once
+/// init_guarded_irq_stack ran, the static one before. the cpu starts at `end` and grows down
+pub fn irq_stack() -> Range<VirtAddr> {
+    if let Some(stack) = GUARDED_IRQ_STACK.get() {
+        return stack.range();
+    }
+    let start = VirtAddr::from_ptr(&raw const IRQ_STACK);
+    start..start + IRQ_STACK_SIZE as u64
+}
+
+/// maps a stack with an unmapped guard page at IRQ_STACK_GUARD and switches the irq IST entry
+/// over to it. needs paging to be set up, fails if it already ran
+pub fn init_guarded_irq_stack(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    let guard = Page::containing_address(VirtAddr::new(IRQ_STACK_GUARD));
+    let pages = (IRQ_STACK_SIZE / 4096) as u64;
+    let stack = GuardedStack::map(guard, pages, mapper, frame_allocator)?;
+    let stack = GUARDED_IRQ_STACK.call_once(|| stack);
+    // the cpu reads the entry when an irq arrives, so it must not see half of the new address
+    interrupts::without_interrupts(|| set_ist_entry(IRQ_IST_INDEX, stack.range().end));
+    Ok(())
+}
+
+// a static mut instead of a lazy_static, init_guarded_irq_stack changes the irq entry after the
+// TSS is loaded. init fills in the entries, the cpu only reads them when an interrupt arrives
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+fn set_ist_entry(index: u16, top: VirtAddr) {
+    // the TSS is packed, so the entry is written in place instead of through a reference
+    unsafe { TSS.interrupt_stack_table[index as usize] = top };
+}
 
+lazy_static! {
     static ref GDT: (GlobalDescriptorTable,Selectors) = {
             let mut gdt = GlobalDescriptorTable::new();
 
@@ -111,16 +169,40 @@ lazy_static! {
             // 5. Without loading the TSS selector, the CPU wouldn't know about our safe stack
             // 6. The TSS descriptor also contains access permissions and type information
             // Think of it as: "Hey CPU, our emergency stacks are stored in THIS memory location"
-            let tss_selector=gdt.append(Descriptor::tss_segment(&TSS));
-            (gdt, Selectors{code_selector,tss_selector})
+            // the TSS is a static, it lives as long as the GDT does
+            let tss_selector=gdt.append(unsafe { Descriptor::tss_segment_unchecked(&raw const TSS) });
+
+            // USER SELECTORS EXPLANATION:
+            // Ring 3 code needs descriptors with DPL 3, the kernel ones can't be loaded from
+            // user mode. Nothing runs in ring 3 yet, these are here for when something does.
+            // The data segment goes right before the code segment because sysret derives both
+            // user selectors from a single base in that order (data at base+8, code at base+16)
+            let user_data_selector=gdt.append(Descriptor::user_data_segment());
+            let user_code_selector=gdt.append(Descriptor::user_code_segment());
+            (gdt, Selectors{code_selector,tss_selector,user_code_selector,user_data_selector})
         };
 }
 
 struct Selectors {
     code_selector: SegmentSelector,
     tss_selector: SegmentSelector,
+    user_code_selector: SegmentSelector,
+    user_data_selector: SegmentSelector,
+}
+
+/// the ring 3 code and data selectors, in that order. their RPL is already 3
+pub fn user_selectors() -> (SegmentSelector, SegmentSelector) {
+    (GDT.1.user_code_selector, GDT.1.user_data_selector)
 }
 pub fn init() {
+    // defining the 0th IST entry as double fault stack
+    // then assigning the top addr of this stack to IST[0]
+    // the reasoning behind assigning the top address is that
+    // stack grows downwards!
+    set_ist_entry(DOUBLE_FAULT_IST_INDEX, double_fault_stack().end);
+    set_ist_entry(PAGE_FAULT_IST_INDEX, page_fault_stack().end);
+    set_ist_entry(IRQ_IST_INDEX, irq_stack().end);
+
     // This tells the CPU "forget your old GDT, use this new one instead"
     // The GDT contains our code descriptor and TSS descriptor
     // After this, the CPU knows about our descriptors but isn't using them yet
@@ -146,3 +228,55 @@ pub fn init() {
         load_tss(GDT.1.tss_selector);
     }
 }
+
+#[test_case]
+fn test_ist_stacks_do_not_overlap() {
+    let stacks = [double_fault_stack(), page_fault_stack(), irq_stack()];
+    for (i, a) in stacks.iter().enumerate() {
+        assert!(a.start < a.end);
+        for b in &stacks[i + 1..] {
+            assert!(
+                a.end <= b.start || b.end <= a.start,
+                "{:?} overlaps {:?}",
+                a,
+                b
+            );
+        }
+    }
+    for (index, stack) in [
+        (DOUBLE_FAULT_IST_INDEX, double_fault_stack()),
+        (PAGE_FAULT_IST_INDEX, page_fault_stack()),
+        (IRQ_IST_INDEX, irq_stack()),
+    ] {
+        // the TSS is packed, so the table is copied out before comparing
+        let table = unsafe { TSS.interrupt_stack_table };
+        assert_eq!(table[index as usize], stack.end);
+    }
+}
+
+#[test_case]
+fn test_irq_stack_has_guard_page() {
+    use crate::memory;
+
+    // init_all maps it, the tests run on a fully initialized kernel
+    let stack = irq_stack();
+    let guard = VirtAddr::new(IRQ_STACK_GUARD);
+    assert_eq!(stack.start, guard + 4096u64);
+    assert_eq!(stack.end - stack.start, IRQ_STACK_SIZE as u64);
+    assert!(!memory::is_mapped(guard));
+    assert!(memory::is_mapped(stack.start));
+    assert!(memory::is_mapped(stack.end - 1u64));
+}
+
+#[test_case]
+fn test_user_selectors() {
+    use x86_64::PrivilegeLevel;
+
+    let (code, data) = user_selectors();
+    assert_eq!(code.rpl(), PrivilegeLevel::Ring3);
+    assert_eq!(data.rpl(), PrivilegeLevel::Ring3);
+    // the layout sysret expects
+    assert_eq!(code.index(), data.index() + 1);
+    // and the kernel's code segment is still ring 0
+    assert_eq!(GDT.1.code_selector.rpl(), PrivilegeLevel::Ring0);
+}
@@ -125,28 +125,75 @@
 //
 // Page Fault	                   Page Fault, Invalid TSS, Segment Not Present, Stack-Segment Fault, General Protection Fault
 
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, AtomicUsize, Ordering};
 use lazy_static::lazy_static;
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::registers::control::Cr2;
+use x86_64::registers::mxcsr::{self, MxCsr};
+use x86_64::structures::idt::{
+    DescriptorTable, ExceptionVector, InterruptDescriptorTable, InterruptStackFrame,
+    PageFaultErrorCode, SelectorErrorCode,
+};
+use x86_64::{PrivilegeLevel, VirtAddr};
 
-use crate::{gdt, println};
+use crate::boot::{self, BootStage};
+use crate::pic::{InterruptIndex, PICS};
+use crate::{apic, cpu, driver, gdt, keyboard, memory, println, serial, time};
 // idt must live staticly but should also be mutable. so we use lazy static
 // to initialize it at runtime
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
+        register_default_handlers(&mut idt);
+        idt.device_not_available
+            .set_handler_fn(device_not_available_handler);
+        idt.x87_floating_point
+            .set_handler_fn(x87_floating_point_handler);
+        idt.simd_floating_point
+            .set_handler_fn(simd_floating_point_handler);
+        idt.invalid_tss.set_handler_fn(invalid_tss_handler);
+        idt.segment_not_present
+            .set_handler_fn(segment_not_present_handler);
+        idt[apic::SPURIOUS_VECTOR].set_handler_fn(spurious_interrupt_handler);
         unsafe {
-            idt.double_fault
-                .set_handler_fn(double_fault_handler)
-                // Assigns a Interrupt Stack Table (IST) stack to this handler.
-                // The CPU will then always switch to the specified
-                // stack before the handler is invoked.
-                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+            idt[InterruptIndex::Timer.as_u8()]
+                .set_handler_fn(timer_interrupt_handler)
+                .set_stack_index(gdt::IRQ_IST_INDEX);
+            idt[InterruptIndex::Keyboard.as_u8()]
+                .set_handler_fn(keyboard_interrupt_handler)
+                .set_stack_index(gdt::IRQ_IST_INDEX);
         }
         idt
     };
 }
 
+/// installs the handlers every IDT wants: breakpoint, double fault and page fault (each on its
+/// own IST stack), general protection fault, invalid opcode and divide error. any of them can be
+/// replaced afterwards with set_handler_fn on the entry
+pub fn register_default_handlers(idt: &mut InterruptDescriptorTable) {
+    idt.breakpoint.set_handler_fn(breakpoint_handler);
+    unsafe {
+        idt.page_fault
+            .set_handler_fn(page_fault_handler)
+            .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
+    }
+    idt.general_protection_fault
+        .set_handler_fn(general_protection_fault_handler);
+    idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+    idt.divide_error.set_handler_fn(divide_error_handler);
+    unsafe {
+        idt.double_fault
+            .set_handler_fn(double_fault_handler)
+            // Assigns a Interrupt Stack Table (IST) stack to this handler.
+            // The CPU will then always switch to the specified
+            // stack before the handler is invoked.
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+    }
+}
+
 pub fn init_idt() {
     // now we stard adding exception handlers
     // breakpoint exception is the exception used to temporarily pause a program
@@ -155,21 +202,860 @@ pub fn init_idt() {
     IDT.load();
 }
 
+/// the name of what `vector` is used for, for diagnostics. "Reserved" for the exception vectors
+/// intel keeps for itself, "Unknown" for vectors we dont use
+pub fn vector_name(vector: u8) -> &'static str {
+    const TIMER: u8 = InterruptIndex::Timer.as_u8();
+    const KEYBOARD: u8 = InterruptIndex::Keyboard.as_u8();
+    match vector {
+        0 => "Divide Error",
+        1 => "Debug",
+        2 => "Non Maskable Interrupt",
+        3 => "Breakpoint",
+        4 => "Overflow",
+        5 => "Bound Range Exceeded",
+        6 => "Invalid Opcode",
+        7 => "Device Not Available",
+        8 => "Double Fault",
+        9 => "Coprocessor Segment Overrun",
+        10 => "Invalid TSS",
+        11 => "Segment Not Present",
+        12 => "Stack Segment Fault",
+        13 => "General Protection Fault",
+        14 => "Page Fault",
+        16 => "x87 Floating Point",
+        17 => "Alignment Check",
+        18 => "Machine Check",
+        19 => "SIMD Floating Point",
+        20 => "Virtualization",
+        21 => "Control Protection",
+        28 => "Hypervisor Injection",
+        29 => "VMM Communication",
+        30 => "Security Exception",
+        15 | 22..=27 | 31 => "Reserved",
+        TIMER => "Timer",
+        KEYBOARD => "Keyboard",
+        apic::SPURIOUS_VECTOR => "Spurious",
+        _ => "Unknown",
+    }
+}
+
+// ** Deferred Enabling
+// enabling interrupts before every handler and device is set up crashes on the first irq. so code
+// that needs interrupts (a driver during its init) only asks for them with defer_enable, and init
+// calls enable_when_ready as its last step, which enables them once the boot stage allows it.
+const INTERRUPTS_STAGE: BootStage = BootStage::Ready;
+
+static ENABLE_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// asks for interrupts to be enabled as soon as the kernel is ready for them
+pub fn defer_enable() {
+    ENABLE_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+fn may_enable(requested: bool, stage: BootStage) -> bool {
+    requested && stage >= INTERRUPTS_STAGE
+}
+
+/// enables interrupts if they were requested and the boot stage allows it, returns whether it did
+pub fn enable_when_ready() -> bool {
+    if !may_enable(ENABLE_REQUESTED.load(Ordering::SeqCst), boot::stage()) {
+        return false;
+    }
+    interrupts::enable();
+    true
+}
+
+// ** Nested Exceptions
+// a bug in an exception handler usually ends in a double fault, which on its own says nothing
+// about what was being handled at the time. so every exception handler holds an ExceptionGuard
+// while it runs: it counts how deep in handlers we are and remembers the outermost exception,
+// which the double fault handler prints.
+static EXCEPTION_DEPTH: AtomicUsize = AtomicUsize::new(0);
+static FIRST_EXCEPTION_VECTOR: AtomicU8 = AtomicU8::new(0);
+static FIRST_EXCEPTION_IP: AtomicU64 = AtomicU64::new(0);
+
+/// marks the current exception handler as running until it is dropped
+pub struct ExceptionGuard;
+
+impl ExceptionGuard {
+    pub fn enter(vector: ExceptionVector, stack_frame: &InterruptStackFrame) -> ExceptionGuard {
+        if EXCEPTION_DEPTH.fetch_add(1, Ordering::SeqCst) == 0 {
+            FIRST_EXCEPTION_VECTOR.store(vector as u8, Ordering::SeqCst);
+            FIRST_EXCEPTION_IP.store(stack_frame.instruction_pointer.as_u64(), Ordering::SeqCst);
+        }
+        ExceptionGuard
+    }
+}
+
+impl Drop for ExceptionGuard {
+    fn drop(&mut self) {
+        EXCEPTION_DEPTH.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// how many exception handlers are running, 0 outside of them
+pub fn exception_depth() -> usize {
+    EXCEPTION_DEPTH.load(Ordering::SeqCst)
+}
+
+/// the outermost exception that is being handled and the address it happened at
+pub fn first_exception() -> Option<(ExceptionVector, VirtAddr)> {
+    if exception_depth() == 0 {
+        return None;
+    }
+    let vector = ExceptionVector::try_from(FIRST_EXCEPTION_VECTOR.load(Ordering::SeqCst)).ok()?;
+    Some((
+        vector,
+        VirtAddr::new(FIRST_EXCEPTION_IP.load(Ordering::SeqCst)),
+    ))
+}
+
 /// prints exception:breakpoint when a breakpoint exception is invoked!
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    let _guard = ExceptionGuard::enter(ExceptionVector::Breakpoint, &stack_frame);
     println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
 }
 
+// ** Stack Overflow
+// when the stack runs into its guard page the cpu raises a page fault. the page fault handler
+// has its own IST stack so it gets to run, but an IDT without that (like the stack_overflow
+// test's) pushes the #PF frame onto the stack that just faulted, faults again, and ends up in
+// the double fault handler instead. either way CR2 holds the address the cpu failed to touch.
+// when that address lies within a page of the interrupted stack pointer, the code was writing
+// to (or just below) its own stack, which is what running into the guard page looks like.
+//
+// it is only a guess though:
+// - CR2 is not cleared, so a double fault with another cause can still see an old address that
+//   happens to be near the stack
+// - a frame bigger than the window (a large array on the stack) can touch memory further than a
+//   page below rsp, so that overflow is reported as a plain double fault
+// - the guard page itself is not looked up, a bad pointer into unmapped memory right under the
+//   stack looks the same as an overflow
+
+/// printed ahead of the double fault when it looks like a stack overflow
+pub const STACK_OVERFLOW_MESSAGE: &str = "KERNEL STACK OVERFLOW";
+
+/// how far the faulting address may be from the stack pointer to still count as an overflow
+const STACK_OVERFLOW_WINDOW: u64 = 4096;
+
+/// whether a double fault looks like it was caused by the stack running into its guard page
+pub fn is_stack_overflow(stack_frame: &InterruptStackFrame) -> bool {
+    match Cr2::read() {
+        Ok(fault_addr) => near_stack_pointer(fault_addr, stack_frame.stack_pointer),
+        Err(_) => false,
+    }
+}
+
+/// whether `fault_addr` is within a page of `stack_pointer`, on either side of it. a push faults
+/// just below rsp, a `sub rsp` followed by a store faults at or above it
+fn near_stack_pointer(fault_addr: VirtAddr, stack_pointer: VirtAddr) -> bool {
+    fault_addr.as_u64().abs_diff(stack_pointer.as_u64()) < STACK_OVERFLOW_WINDOW
+}
+
 /// double fault handler. without a double fault, a triple fault will be called which will cause
 /// a continuous reboot! we need to avoid double and triple faults at all cost
 extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
+    if is_stack_overflow(&stack_frame) {
+        println!("{}", STACK_OVERFLOW_MESSAGE);
+    }
+    let depth = exception_depth();
+    if depth > 0 {
+        println!(
+            "double fault occurred while handling exception at depth {}",
+            depth
+        );
+    }
+    if let Some((vector, ip)) = first_exception() {
+        println!("  first exception: {:?} at {:#x}", vector, ip.as_u64());
+    }
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
+// ** Segment Faults
+// loading a segment register (or the TSS, or going through a gate) with a bad descriptor raises
+// #TS (vector 10) for a broken TSS and #NP (vector 11) for a descriptor whose present bit is
+// clear. both push an error code pointing at the offending selector:
+// Bits	Description
+// 0	External, the fault happened while delivering an interrupt or exception
+// 1-2	Table, 00: GDT, 01/11: IDT, 10: LDT
+// 3-15	Index into that table
+// so apart from bit 0 and the IDT case it is the selector itself, minus its RPL.
+
+/// the selector (with RPL 0) a #TS/#NP error code points at
+pub fn faulting_selector(error_code: SelectorErrorCode) -> u16 {
+    let table_bit = match error_code.descriptor_table() {
+        DescriptorTable::Ldt => 1 << 2,
+        _ => 0,
+    };
+    (error_code.index() << 3) as u16 | table_bit
+}
+
+fn report_segment_fault(name: &str, stack_frame: InterruptStackFrame, error_code: u64) -> ! {
+    println!("EXCEPTION: {}", name);
+    report_selector(SelectorErrorCode::new_truncate(error_code));
+    panic!("{:#?}", stack_frame);
+}
+
+fn report_selector(error_code: SelectorErrorCode) {
+    match error_code.descriptor_table() {
+        DescriptorTable::Idt => println!("  IDT vector {}", error_code.index()),
+        table => println!(
+            "  faulting selector {:#x} ({:?} index {})",
+            faulting_selector(error_code),
+            table,
+            error_code.index()
+        ),
+    }
+    if error_code.external() {
+        println!("  while delivering an external event");
+    }
+}
+
+/// #TS, the TSS we switched to (or one of the selectors in it) is broken
+extern "x86-interrupt" fn invalid_tss_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    let _guard = ExceptionGuard::enter(ExceptionVector::InvalidTss, &stack_frame);
+    report_segment_fault("INVALID TSS", stack_frame, error_code);
+}
+
+/// #NP, a descriptor that was loaded has its present bit clear
+extern "x86-interrupt" fn segment_not_present_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    let _guard = ExceptionGuard::enter(ExceptionVector::SegmentNotPresent, &stack_frame);
+    report_segment_fault("SEGMENT NOT PRESENT", stack_frame, error_code);
+}
+
+// ** Page Faults
+// the error code of a #PF says what went wrong, CR2 holds the address that was accessed:
+// Bit	Name	            Set when
+// 0	PROTECTION_VIOLATION	the page was present, 0 means it wasnt mapped
+// 1	CAUSED_BY_WRITE	        the access was a write
+// 2	USER_MODE	            the access came from ring 3
+// 3	MALFORMED_TABLE	        a reserved bit was set in a page table entry
+// 4	INSTRUCTION_FETCH	    the access was an instruction fetch
+//
+// once there are user processes, a fault in ring 3 is that process' problem and should end it
+// (or be handed to it as a signal) instead of taking the kernel down. USER_MODE reports the
+// privilege level of the access itself, so a kernel access never sets it, not even one that
+// touches user memory on behalf of a process. the CPL of the interrupted code segment is
+// checked as well, the two always agree and either one tells that ring 3 faulted.
+// a write to a copy-on-write page is not a fault at all, it is handled first (see memory).
+// a fault in the kernel is reported and the cpu is halted right there (after the panic hooks
+// flushed what they buffer) instead of panicking, so a debugger sees the state as it was. the
+// lib tests are the exception, there it panics so the test fails instead of hanging.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PageFaultAction {
+    /// a bug in the kernel, report it and halt like any other exception
+    KernelFault,
+    /// a user process faulted, only that process has to go
+    TerminateProcess,
+}
+
+fn page_fault_action(error_code: PageFaultErrorCode, cpl: PrivilegeLevel) -> PageFaultAction {
+    if error_code.contains(PageFaultErrorCode::USER_MODE) && cpl == PrivilegeLevel::Ring3 {
+        PageFaultAction::TerminateProcess
+    } else {
+        PageFaultAction::KernelFault
+    }
+}
+
+/// #PF, an access to a page that isnt mapped or that the access isnt allowed on
+extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    let _guard = ExceptionGuard::enter(ExceptionVector::Page, &stack_frame);
+    let cow_fault = PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE;
+    if error_code.contains(cow_fault)
+        && let Ok(addr) = Cr2::read()
+        && memory::handle_cow_fault(addr)
+    {
+        // the page has its own copy now, retrying the write succeeds
+        return;
+    }
+    let cpl = stack_frame.code_segment.rpl();
+    match page_fault_action(error_code, cpl) {
+        PageFaultAction::TerminateProcess => {
+            println!("user fault, would terminate process");
+            // TODO: kill the current process and schedule the next one once there are processes
+            crate::hlt_loop();
+        }
+        PageFaultAction::KernelFault => {
+            if is_stack_overflow(&stack_frame) {
+                println!("{}", STACK_OVERFLOW_MESSAGE);
+            }
+            println!("EXCEPTION: PAGE FAULT");
+            match Cr2::read() {
+                Ok(addr) => println!("  accessed address: {:#x}", addr.as_u64()),
+                Err(err) => println!("  accessed address: {:?}", err),
+            }
+            report_page_fault_cause(error_code);
+            println!("{:#?}", stack_frame);
+            halt_after_fault("PAGE FAULT");
+        }
+    }
+}
+
+/// halting instead of panicking leaves the state as it was for a debugger, but whatever is
+/// buffered still has to get out first. in the lib tests it panics instead: a halted test would
+/// sit there with interrupts disabled until bootimage's timeout, the test watchdog included
+fn halt_after_fault(exception: &str) -> ! {
+    if cfg!(test) {
+        panic!("EXCEPTION: {} in a test", exception);
+    }
+    crate::panic::run_hooks();
+    crate::hlt_loop();
+}
+
+/// prints the bits of a #PF error code in words
+fn report_page_fault_cause(error_code: PageFaultErrorCode) {
+    let cause = if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        "protection violation"
+    } else {
+        "page not present"
+    };
+    let access = if error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+        "instruction fetch"
+    } else if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+        "write"
+    } else {
+        "read"
+    };
+    let mode = if error_code.contains(PageFaultErrorCode::USER_MODE) {
+        "user"
+    } else {
+        "kernel"
+    };
+    println!("  {} on {} in {} mode", cause, access, mode);
+    if error_code.contains(PageFaultErrorCode::MALFORMED_TABLE) {
+        println!("  a reserved bit is set in a page table entry");
+    }
+    println!("  error code: {:?}", error_code);
+}
+
+/// #GP, the catch all for protection violations. if a selector was involved (loading a segment
+/// register, a far jump, an IDT entry) the error code points at it like for #TS/#NP, otherwise
+/// it is 0
+extern "x86-interrupt" fn general_protection_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    let _guard = ExceptionGuard::enter(ExceptionVector::GeneralProtection, &stack_frame);
+    println!("EXCEPTION: GENERAL PROTECTION FAULT");
+    if error_code == 0 {
+        println!("  no selector involved");
+    } else {
+        report_selector(SelectorErrorCode::new_truncate(error_code));
+    }
+    println!("{:#?}", stack_frame);
+    halt_after_fault("GENERAL PROTECTION FAULT");
+}
+
+/// ud2, the instruction that is guaranteed to raise #UD
+const UD2: [u8; 2] = [0x0F, 0x0B];
+
+/// #UD, an instruction this cpu doesnt know (or ud2). by default the cpu is halted. with the
+/// `invalid-opcode-return` feature a ud2 is skipped and execution goes on behind it. #UD is a
+/// fault, RIP still points at the instruction, so just returning would run into it again.
+/// the length of anything else is unknown, that still halts
+extern "x86-interrupt" fn invalid_opcode_handler(mut stack_frame: InterruptStackFrame) {
+    let _guard = ExceptionGuard::enter(ExceptionVector::InvalidOpcode, &stack_frame);
+    let ip = stack_frame.instruction_pointer;
+    println!("EXCEPTION: INVALID OPCODE at {:#x}", ip.as_u64());
+    println!("{:#?}", stack_frame);
+    // RIP pointed at a valid instruction fetch a moment ago, so the bytes are mapped
+    let opcode = unsafe { ip.as_ptr::<[u8; 2]>().read_unaligned() };
+    if !cfg!(feature = "invalid-opcode-return") || opcode != UD2 {
+        halt_after_fault("INVALID OPCODE");
+    }
+    unsafe {
+        stack_frame
+            .as_mut()
+            .update(|frame| frame.instruction_pointer += UD2.len() as u64);
+    }
+}
+
+/// #DE, div or idiv by zero, or a quotient too big for the destination. it is a fault, RIP
+/// still points at the division, so returning would only divide by zero again
+extern "x86-interrupt" fn divide_error_handler(stack_frame: InterruptStackFrame) {
+    let _guard = ExceptionGuard::enter(ExceptionVector::Division, &stack_frame);
+    println!(
+        "EXCEPTION: DIVIDE ERROR at {:#x}",
+        stack_frame.instruction_pointer.as_u64()
+    );
+    println!("{:#?}", stack_frame);
+    halt_after_fault("DIVIDE ERROR");
+}
+
+/// #NM, raised by the first fpu/sse instruction after a lazy fpu switch set CR0.TS
+extern "x86-interrupt" fn device_not_available_handler(stack_frame: InterruptStackFrame) {
+    let _guard = ExceptionGuard::enter(ExceptionVector::DeviceNotAvailable, &stack_frame);
+    cpu::handle_device_not_available();
+}
+
+/// the local apic raised an interrupt that went away. it must not be acknowledged with an EOI
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    apic::handle_spurious();
+}
+
+// ** End Of Interrupt
+// irqs come either from the 8259 PICs or, once they are routed through the IO APIC, from the
+// local apic, and each wants its own EOI: a command to the PIC's port or a write to the LAPIC's
+// EOI register. handlers dont pick, they call eoi (through the EndOfInterrupt guard) and the
+// mode set at init decides. it starts out as Pic, the mode has to change together with the
+// routing, an EOI sent to the wrong controller leaves the real one waiting forever.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EoiMode {
+    Pic,
+    Apic,
+}
+
+/// false: Pic, true: Apic
+static APIC_EOI: AtomicBool = AtomicBool::new(false);
+
+pub fn set_eoi_mode(mode: EoiMode) {
+    APIC_EOI.store(mode == EoiMode::Apic, Ordering::SeqCst);
+}
+
+pub fn eoi_mode() -> EoiMode {
+    if APIC_EOI.load(Ordering::SeqCst) {
+        EoiMode::Apic
+    } else {
+        EoiMode::Pic
+    }
+}
+
+/// acknowledges the irq on `vector` at whichever controller delivered it
+pub fn eoi(vector: u8) {
+    send_eoi(
+        eoi_mode(),
+        vector,
+        |vector| unsafe { PICS.lock().notify_end_of_interrupt(vector) },
+        apic::end_of_interrupt,
+    );
+}
+
+fn send_eoi(mode: EoiMode, vector: u8, pic: impl FnOnce(u8), apic: impl FnOnce()) {
+    match mode {
+        EoiMode::Pic => pic(vector),
+        EoiMode::Apic => apic(),
+    }
+}
+
+/// sends the EOI for its vector when dropped. an irq handler creates one first thing, so the
+/// EOI goes out on every way out of the handler. (there is no unwinding with panic = "abort", a
+/// handler that panics doesnt return at all.)
+pub struct EndOfInterrupt(pub u8);
+
+impl Drop for EndOfInterrupt {
+    fn drop(&mut self) {
+        eoi(self.0);
+    }
+}
+
+// ** Timer Callbacks
+// the scheduler, the clock, watchdogs... all want to run on every timer tick. instead of one
+// handler that knows about all of them, they register a callback with on_tick and the timer
+// handler calls timer_tick, which bumps the tick counter and runs every callback in order
+// (before the EOI is sent).
+// the callbacks run in interrupt context with interrupts disabled: they have to be quick, must
+// not block and must not take a lock that the interrupted code might be holding (print through
+// softirq::schedule instead).
+pub const MAX_TICK_CALLBACKS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallbackError {
+    /// all MAX_TICK_CALLBACKS slots are taken
+    Full,
+}
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+type TickCallback = fn(u64);
+
+static TICK_CALLBACKS: Mutex<[Option<TickCallback>; MAX_TICK_CALLBACKS]> =
+    Mutex::new([None; MAX_TICK_CALLBACKS]);
+
+/// registers `f` to be called with the new tick count on every timer tick
+pub fn on_tick(f: fn(tick: u64)) -> Result<(), CallbackError> {
+    // the timer handler takes the same lock, so it must not fire while we hold it
+    interrupts::without_interrupts(|| {
+        let mut callbacks = TICK_CALLBACKS.lock();
+        let slot = callbacks
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(CallbackError::Full)?;
+        *slot = Some(f);
+        Ok(())
+    })
+}
+
+/// timer ticks since boot
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// IRQ 0, the PIT
+extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    let _eoi = EndOfInterrupt(InterruptIndex::Timer.as_u8());
+    timer_tick();
+    driver::dispatch_irq(InterruptIndex::Timer.irq());
+}
+
+// ** Keyboard
+// the PS/2 controller raises IRQ 1 for every byte the keyboard sends and holds it in its output
+// buffer at port 0x60. reading the port is mandatory: until the byte is read the controller
+// doesnt send another interrupt, and the keyboard looks dead.
+// the bytes are scancodes of scancode set 1, one or more per key press and release. the handler
+// only queues them, keyboard::try_read_key turns them into keys.
+const KEYBOARD_DATA_PORT: u16 = 0x60;
+
+/// IRQ 1, the PS/2 keyboard
+extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    let _eoi = EndOfInterrupt(InterruptIndex::Keyboard.as_u8());
+    let mut port: Port<u8> = Port::new(KEYBOARD_DATA_PORT);
+    let scancode = unsafe { port.read() };
+    keyboard::add_scancode(scancode);
+    driver::dispatch_irq(InterruptIndex::Keyboard.irq());
+}
+
+/// the common part of every timer handler, returns the new tick count.
+/// has to be called with interrupts disabled
+pub fn timer_tick() -> u64 {
+    let tick = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    let callbacks = *TICK_CALLBACKS.lock();
+    for callback in callbacks.into_iter().flatten() {
+        callback(tick);
+    }
+    tick
+}
+
+// ** Heartbeat
+// a hung kernel and one that is busy but quiet look the same from the outside. with the
+// `heartbeat` kernel parameter a `.` goes out on COM1 about once a second, so whoever watches
+// the serial log (ie. CI) can tell them apart. it runs in the timer interrupt, if the port is
+// locked by the code we interrupted that beat is skipped rather than deadlocking.
+// the interval is measured in uptime, not ticks, so it stays a second whatever rate the PIT
+// is programmed to.
+pub const HEARTBEAT_INTERVAL_MS: u64 = 1000;
+
+/// the uptime the next beat is due at
+static NEXT_HEARTBEAT_MS: AtomicU64 = AtomicU64::new(0);
+
+fn heartbeat(_tick: u64) {
+    let now = time::uptime_ms();
+    if now < NEXT_HEARTBEAT_MS.load(Ordering::Relaxed) {
+        return;
+    }
+    serial::try_write_byte(b'.');
+    NEXT_HEARTBEAT_MS.store(now + HEARTBEAT_INTERVAL_MS, Ordering::Relaxed);
+}
+
+/// starts the heartbeat
+pub fn enable_heartbeat() -> Result<(), CallbackError> {
+    on_tick(heartbeat)
+}
+
+/// what the floating point exception handlers do once the exception is reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FpExceptionPolicy {
+    Halt = 0,
+    /// clear the flags and keep going with the masked (default) result
+    Continue = 1,
+}
+
+static FP_EXCEPTION_POLICY: AtomicU8 = AtomicU8::new(FpExceptionPolicy::Halt as u8);
+
+pub fn set_fp_exception_policy(policy: FpExceptionPolicy) {
+    FP_EXCEPTION_POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+fn fp_exception_policy() -> FpExceptionPolicy {
+    match FP_EXCEPTION_POLICY.load(Ordering::Relaxed) {
+        0 => FpExceptionPolicy::Halt,
+        _ => FpExceptionPolicy::Continue,
+    }
+}
+
+// x87 status word exception flags
+const X87_INVALID_OPERATION: u16 = 1 << 0;
+const X87_DENORMAL: u16 = 1 << 1;
+const X87_DIVIDE_BY_ZERO: u16 = 1 << 2;
+const X87_OVERFLOW: u16 = 1 << 3;
+const X87_UNDERFLOW: u16 = 1 << 4;
+const X87_PRECISION: u16 = 1 << 5;
+
+/// #MF, raised on the next x87 instruction after one with an unmasked exception
+extern "x86-interrupt" fn x87_floating_point_handler(stack_frame: InterruptStackFrame) {
+    let _guard = ExceptionGuard::enter(ExceptionVector::X87FloatingPoint, &stack_frame);
+    let status: u16;
+    unsafe {
+        asm!("fnstsw ax", out("ax") status, options(nomem, nostack));
+    }
+    let flags = [
+        (X87_INVALID_OPERATION, "invalid operation"),
+        (X87_DENORMAL, "denormal"),
+        (X87_DIVIDE_BY_ZERO, "divide by zero"),
+        (X87_OVERFLOW, "overflow"),
+        (X87_UNDERFLOW, "underflow"),
+        (X87_PRECISION, "precision"),
+    ];
+    println!("EXCEPTION: x87 FLOATING POINT");
+    for (flag, name) in flags {
+        if status & flag != 0 {
+            println!("  {}", name);
+        }
+    }
+    if fp_exception_policy() == FpExceptionPolicy::Halt {
+        panic!("{:#?}", stack_frame);
+    }
+    unsafe {
+        asm!("fnclex", options(nomem, nostack));
+    }
+}
+
+/// #XM, raised by the sse instruction itself. returning retries it, so for the Continue policy
+/// the exceptions that fired get masked as well, making the retry produce the default result
+extern "x86-interrupt" fn simd_floating_point_handler(stack_frame: InterruptStackFrame) {
+    let _guard = ExceptionGuard::enter(ExceptionVector::SimdFloatingPoint, &stack_frame);
+    let status = mxcsr::read();
+    let fired = status
+        & (MxCsr::INVALID_OPERATION
+            | MxCsr::DENORMAL
+            | MxCsr::DIVIDE_BY_ZERO
+            | MxCsr::OVERFLOW
+            | MxCsr::UNDERFLOW
+            | MxCsr::PRECISION);
+    println!("EXCEPTION: SIMD FLOATING POINT\n  {:?}", fired);
+    if fp_exception_policy() == FpExceptionPolicy::Halt {
+        panic!("{:#?}", stack_frame);
+    }
+    // the mask bits sit 7 bits above their flags
+    let masks = MxCsr::from_bits_truncate(fired.bits() << 7);
+    mxcsr::write((status - fired) | masks);
+}
+
 #[test_case]
 fn test_breakpoint_exception() {
     x86_64::instructions::interrupts::int3();
 }
+
+#[test_case]
+fn test_faulting_selector() {
+    // GDT index 3
+    assert_eq!(
+        faulting_selector(SelectorErrorCode::new_truncate(0x18)),
+        0x18
+    );
+    // LDT index 2, external
+    assert_eq!(
+        faulting_selector(SelectorErrorCode::new_truncate(0x15)),
+        0x14
+    );
+}
+
+#[test_case]
+fn test_page_fault_action() {
+    let user_write = PageFaultErrorCode::USER_MODE | PageFaultErrorCode::CAUSED_BY_WRITE;
+    assert_eq!(
+        page_fault_action(user_write, PrivilegeLevel::Ring3),
+        PageFaultAction::TerminateProcess
+    );
+    // the kernel touching user memory
+    assert_eq!(
+        page_fault_action(user_write, PrivilegeLevel::Ring0),
+        PageFaultAction::KernelFault
+    );
+    assert_eq!(
+        page_fault_action(PageFaultErrorCode::CAUSED_BY_WRITE, PrivilegeLevel::Ring0),
+        PageFaultAction::KernelFault
+    );
+    assert_eq!(
+        page_fault_action(PageFaultErrorCode::empty(), PrivilegeLevel::Ring3),
+        PageFaultAction::KernelFault
+    );
+}
+
+#[test_case]
+fn test_tick_callbacks() {
+    static FIRST: AtomicU64 = AtomicU64::new(0);
+    static SECOND: AtomicU64 = AtomicU64::new(0);
+    fn first(tick: u64) {
+        FIRST.store(tick, Ordering::Relaxed);
+    }
+    fn second(tick: u64) {
+        SECOND.store(tick, Ordering::Relaxed);
+    }
+
+    // the timer handler takes the callback lock, and a real tick between timer_tick and the
+    // asserts would update the callbacks again
+    interrupts::without_interrupts(|| {
+        let registered = *TICK_CALLBACKS.lock();
+        on_tick(first).unwrap();
+        on_tick(second).unwrap();
+        let tick = timer_tick();
+        assert_eq!(FIRST.load(Ordering::Relaxed), tick);
+        assert_eq!(SECOND.load(Ordering::Relaxed), tick);
+        let tick = timer_tick();
+        assert_eq!(FIRST.load(Ordering::Relaxed), tick);
+        assert_eq!(SECOND.load(Ordering::Relaxed), tick);
+
+        *TICK_CALLBACKS.lock() = registered;
+    });
+}
+
+#[test_case]
+fn test_heartbeat() {
+    assert!(boot::parse_cmdline("heartbeat").heartbeat);
+
+    // no real timer tick may beat in between
+    interrupts::without_interrupts(|| {
+        let registered = *TICK_CALLBACKS.lock();
+        enable_heartbeat().unwrap();
+        let due = time::uptime_ms() + HEARTBEAT_INTERVAL_MS;
+        NEXT_HEARTBEAT_MS.store(due, Ordering::Relaxed);
+        let before = serial::stats().tx_bytes;
+        // quiet until the interval is over, whatever the tick rate is
+        while time::uptime_ms() < due {
+            assert_eq!(serial::stats().tx_bytes, before);
+            timer_tick();
+        }
+        assert_eq!(serial::stats().tx_bytes, before + 1);
+        *TICK_CALLBACKS.lock() = registered;
+    });
+}
+
+#[test_case]
+fn test_timer_ticks() {
+    assert!(interrupts::are_enabled());
+    let start = ticks();
+    while ticks() == start {
+        x86_64::instructions::hlt();
+    }
+}
+
+#[test_case]
+fn test_print_from_timer_does_not_deadlock() {
+    fn print_on_tick(_tick: u64) {
+        crate::print!("t");
+        crate::serial_print!("");
+    }
+
+    assert!(interrupts::are_enabled());
+    let registered = interrupts::without_interrupts(|| *TICK_CALLBACKS.lock());
+    on_tick(print_on_tick).unwrap();
+    // with a tick every few prints, one of them would land while a print holds a lock
+    let start = ticks();
+    while ticks() < start + 20 {
+        crate::print!("m");
+        crate::serial_print!("");
+    }
+    interrupts::without_interrupts(|| *TICK_CALLBACKS.lock() = registered);
+    crate::println!();
+}
+
+#[test_case]
+fn test_register_default_handlers() {
+    let mut idt = InterruptDescriptorTable::new();
+    register_default_handlers(&mut idt);
+    let addr = |handler: *const ()| VirtAddr::from_ptr(handler);
+    assert_eq!(
+        idt.breakpoint.handler_addr(),
+        addr(breakpoint_handler as *const ())
+    );
+    assert_eq!(
+        idt.double_fault.handler_addr(),
+        addr(double_fault_handler as *const ())
+    );
+    assert_eq!(
+        idt.divide_error.handler_addr(),
+        addr(divide_error_handler as *const ())
+    );
+    assert_eq!(
+        idt.page_fault.handler_addr(),
+        addr(page_fault_handler as *const ())
+    );
+    // the rest is left alone
+    assert_eq!(idt.overflow.handler_addr(), VirtAddr::zero());
+
+    // and a default can be overridden
+    idt.breakpoint.set_handler_fn(divide_error_handler);
+    assert_eq!(
+        idt.breakpoint.handler_addr(),
+        addr(divide_error_handler as *const ())
+    );
+}
+
+#[test_case]
+fn test_vector_name() {
+    assert_eq!(vector_name(0), "Divide Error");
+    assert_eq!(vector_name(3), "Breakpoint");
+    assert_eq!(vector_name(8), "Double Fault");
+    assert_eq!(vector_name(14), "Page Fault");
+    assert_eq!(vector_name(15), "Reserved");
+    assert_eq!(vector_name(32), "Timer");
+    assert_eq!(vector_name(33), "Keyboard");
+    assert_eq!(vector_name(34), "Unknown");
+    assert!((0..=33).all(|vector| vector_name(vector) != "Unknown"));
+}
+
+#[test_case]
+fn test_enable_is_deferred() {
+    assert!(!may_enable(true, BootStage::Early));
+    assert!(!may_enable(true, BootStage::Descriptors));
+    assert!(!may_enable(false, BootStage::Ready));
+    assert!(may_enable(true, BootStage::Ready));
+}
+
+#[test_case]
+fn test_near_stack_pointer() {
+    let sp = VirtAddr::new(0x10_0000);
+    // a push into the guard page right under the stack
+    assert!(near_stack_pointer(sp - 8u64, sp));
+    // a store after `sub rsp` already moved rsp into the guard page
+    assert!(near_stack_pointer(sp + 16u64, sp));
+    assert!(!near_stack_pointer(sp - STACK_OVERFLOW_WINDOW, sp));
+    assert!(!near_stack_pointer(VirtAddr::new(0xdead_beef), sp));
+}
+
+#[test_case]
+fn test_eoi_goes_to_the_active_controller() {
+    use core::cell::Cell;
+
+    let pic = Cell::new(None);
+    let apic = Cell::new(0);
+    send_eoi(
+        EoiMode::Pic,
+        33,
+        |vector| pic.set(Some(vector)),
+        || apic.set(apic.get() + 1),
+    );
+    assert_eq!((pic.get(), apic.get()), (Some(33), 0));
+
+    pic.set(None);
+    send_eoi(
+        EoiMode::Apic,
+        33,
+        |vector| pic.set(Some(vector)),
+        || apic.set(apic.get() + 1),
+    );
+    assert_eq!((pic.get(), apic.get()), (None, 1));
+
+    // the kernel boots in PIC mode, the timer depends on it
+    assert_eq!(eoi_mode(), EoiMode::Pic);
+}
+
+#[cfg(feature = "invalid-opcode-return")]
+#[test_case]
+fn test_ud2_is_skipped() {
+    // getting past it at all means the handler moved RIP, it would fault forever otherwise
+    unsafe { asm!("ud2") };
+    assert_eq!(exception_depth(), 0);
+}
@@ -0,0 +1,135 @@
+// the keyboard interrupt handler only reads the scancode (see interrupts, ** Keyboard) and
+// queues it. decoding takes the keyboard state lock, so it happens here, outside of interrupt
+// context, when someone asks for a key.
+// scancodes that arrive while the queue is full push out the oldest ones, a few lost keys are
+// better than a handler that blocks.
+// async code waits with next_key instead of polling. the handler wakes the waiting task after
+// queueing a scancode, the task has to register its waker before looking at the queue one
+// last time, otherwise a scancode arriving in between would leave it asleep.
+
+use core::future::poll_fn;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Poll, Waker};
+
+use futures_util::task::AtomicWaker;
+use lazy_static::lazy_static;
+use pc_keyboard::{DecodedKey, HandleControl, Keyboard, ScancodeSet1, layouts};
+use spin::Mutex;
+
+use crate::serial_println;
+use crate::util::ByteQueue;
+
+pub const SCANCODE_QUEUE_SIZE: usize = 128;
+
+static SCANCODES: ByteQueue<SCANCODE_QUEUE_SIZE> = ByteQueue::new();
+/// set by the handler when it had to drop a scancode
+static DROPPED: AtomicBool = AtomicBool::new(false);
+static DROP_REPORTED: AtomicBool = AtomicBool::new(false);
+/// the task waiting for input
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+lazy_static! {
+    static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
+        Mutex::new(Keyboard::new(
+            ScancodeSet1::new(),
+            layouts::Us104Key,
+            HandleControl::Ignore
+        ));
+}
+
+/// queues a scancode, called by the keyboard interrupt handler. never blocks
+pub(crate) fn add_scancode(scancode: u8) {
+    if !SCANCODES.push(scancode) {
+        DROPPED.store(true, Ordering::Relaxed);
+    }
+    WAKER.wake();
+}
+
+/// `waker` is woken by the next scancode. only the last registered waker is kept
+pub fn register_waker(waker: &Waker) {
+    WAKER.register(waker);
+}
+
+/// waits for the next key
+pub async fn next_key() -> DecodedKey {
+    poll_fn(|context| {
+        if let Some(key) = try_read_key() {
+            return Poll::Ready(key);
+        }
+        register_waker(context.waker());
+        match try_read_key() {
+            Some(key) => Poll::Ready(key),
+            None => Poll::Pending,
+        }
+    })
+    .await
+}
+
+/// decodes the queued scancodes until one of them completes a key, None if the queue runs out
+/// first. doesnt wait for input, ie. a polling loop:
+/// ```ignore
+/// loop {
+///     match keyboard::try_read_key() {
+///         Some(DecodedKey::Unicode(c)) => print!("{}", c),
+///         Some(DecodedKey::RawKey(_)) => {}
+///         None => x86_64::instructions::hlt(),
+///     }
+/// }
+/// ```
+pub fn try_read_key() -> Option<DecodedKey> {
+    while let Some(scancode) = pop_scancode() {
+        if let Some(key) = decode(scancode) {
+            return Some(key);
+        }
+    }
+    None
+}
+
+/// the oldest queued scancode. the first time scancodes had to be dropped it says so on serial
+pub(crate) fn pop_scancode() -> Option<u8> {
+    if DROPPED.load(Ordering::Relaxed) && !DROP_REPORTED.swap(true, Ordering::Relaxed) {
+        serial_println!("keyboard: scancode queue full, dropping the oldest scancodes");
+    }
+    SCANCODES.pop()
+}
+
+/// feeds `scancode` to the keyboard state, returns the key if it completes one
+pub fn decode(scancode: u8) -> Option<DecodedKey> {
+    let mut keyboard = KEYBOARD.lock();
+    let key_event = keyboard.add_byte(scancode).ok()??;
+    keyboard.process_keyevent(key_event)
+}
+
+#[test_case]
+fn test_next_key_is_woken_by_scancode() {
+    use alloc::sync::Arc;
+    use alloc::task::Wake;
+    use core::pin::pin;
+    use core::sync::atomic::AtomicUsize;
+    use core::task::Context;
+
+    struct CountingWaker(AtomicUsize);
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+    let waker = Waker::from(counter.clone());
+    let mut context = Context::from_waker(&waker);
+    let mut key = pin!(next_key());
+
+    assert_eq!(key.as_mut().poll(&mut context), Poll::Pending);
+    assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+    // the A key pressed, as the interrupt handler would queue it
+    add_scancode(0x1E);
+    assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+    assert_eq!(
+        key.as_mut().poll(&mut context),
+        Poll::Ready(DecodedKey::Unicode('a'))
+    );
+    // and released, so the keyboard state is as before
+    add_scancode(0x9E);
+    assert_eq!(try_read_key(), None);
+}
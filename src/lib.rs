@@ -2,16 +2,44 @@
 #![cfg_attr(test, no_main)]
 #![feature(custom_test_frameworks)]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
 #![test_runner(crate::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
+extern crate alloc;
+
+pub mod allocator;
+pub mod apic;
+pub mod boot;
+pub mod cpu;
+pub mod driver;
 pub mod gdt;
 pub mod interrupts;
+pub mod keyboard;
+pub mod line_editor;
+pub mod link;
+pub mod logger;
+pub mod memory;
+pub mod output;
+pub mod panic;
+pub mod pic;
+pub mod selftest;
 pub mod serial;
+pub mod softirq;
+pub mod task;
+pub mod term;
+pub mod time;
+pub mod util;
 pub mod vga_buffer;
 
+use boot::KernelArgs;
+use bootloader::BootInfo;
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, Ordering};
+use x86_64::VirtAddr;
 use x86_64::instructions::port::Port;
+use x86_64::structures::idt::ExceptionVector;
+use x86_64::structures::paging::OffsetPageTable;
 
 /// uses the port mapped io bus to communicate with Qemu
 /// when (value << 1) | 1 is written in Qemu io port, it will
@@ -24,7 +52,14 @@ pub enum QemuExitCode {
     Failed = 0x11,
 }
 
+/// does nothing under other hypervisors, where port 0xf4 could belong to anything.
+/// qemu always identifies itself, as KVM or TCG (see cpu::test_detect_hypervisor), so None
+/// means real hardware. the port is still written there: nothing on a pc decodes 0xf4, and if
+/// detection ever broke under qemu the tests would hang instead of exiting
 pub fn exit_qemu(exit_code: QemuExitCode) {
+    if cpu::detect_hypervisor().is_some_and(|hypervisor| !hypervisor.is_qemu()) {
+        return;
+    }
     unsafe {
         // 0xf4 is set in cargo.toml as the io mapped port for qemu
         // as iobase
@@ -36,6 +71,8 @@ pub fn exit_qemu(exit_code: QemuExitCode) {
 
 pub trait Testable {
     fn run(&self) -> ();
+    /// the test function's path, ie. `os::vga_buffer::test_println_simple`
+    fn name(&self) -> &'static str;
 }
 
 impl<T> Testable for T
@@ -43,10 +80,23 @@ where
     T: Fn(),
 {
     fn run(&self) {
-        serial_print!("{}...\t", core::any::type_name::<T>());
+        // the name gets a line of its own and is on the wire before the test starts, so a test
+        // that takes the machine down still leaves its name as the last complete line
+        serial_println!("{}...", self.name());
+        serial::flush_tx();
         self();
         serial_println!("[Ok]");
     }
+
+    fn name(&self) -> &'static str {
+        core::any::type_name::<T>()
+    }
+}
+
+/// whether the test called `name` runs under the `test_filter` kernel parameter `filter`. like
+/// cargo's own filter it matches any part of the path
+fn test_selected(name: &str, filter: Option<&str>) -> bool {
+    filter.is_none_or(|filter| name.contains(filter))
 }
 
 // The custom test frameworks feature generates a main function that calls test_runner,
@@ -58,30 +108,216 @@ pub fn test_runner(tests: &[&dyn Testable]) {
     // println!("Running {} tests", tests.len());
     // remember to ser -serial and -stdin flags in cargo.toml for test-args
     serial_println!("Running {} tests", tests.len());
-    for test in tests {
+    let filter = boot::args().test_filter;
+    if let Some(filter) = filter {
+        serial_println!("only running tests matching `{}`", filter);
+    }
+    // a failing test panics and ends the run, so only passes are ever counted
+    let mut passed = 0;
+    let mut filtered = 0;
+    for (index, test) in tests.iter().enumerate() {
+        if !test_selected(test.name(), filter) {
+            filtered += 1;
+            continue;
+        }
+        arm_test_watchdog(TEST_WATCHDOG_SECS).expect("no tick callback slot for the test watchdog");
+        // lands in front of the name Testable::run prints
+        serial_print!("[{}/{}] ", index + 1, tests.len());
         test.run();
+        serial::flush_tx();
+        passed += 1;
     }
+    disarm_test_watchdog();
+    serial_println!("{} passed, {} filtered", passed, filtered);
+    serial::flush_tx();
     exit_qemu(QemuExitCode::Success);
 }
+
+// ** Test Watchdog
+// a test that never returns keeps qemu running until bootimage gives up (test-timeout in
+// cargo.toml, 5 minutes). the watchdog is a timer callback that panics once the uptime passes
+// a deadline, so the hang ends up in the panic handler as a failed test, with its name as the
+// last line on the serial port. test_runner re-arms it with TEST_WATCHDOG_SECS before every
+// test, so the budget is per test and a long run of quick tests never trips it. an integration
+// test without the runner arms its own with arm_test_watchdog. keep it below test-timeout or
+// bootimage wins the race.
+// it runs in the timer interrupt, so a test that hangs with interrupts disabled is still only
+// caught by bootimage.
+/// how long a single test may take
+pub const TEST_WATCHDOG_SECS: u32 = 60;
+
+static WATCHDOG_DEADLINE_MS: AtomicU64 = AtomicU64::new(u64::MAX);
+static WATCHDOG_REGISTERED: AtomicBool = AtomicBool::new(false);
+
+/// fails the running test once `secs` seconds have passed, counted from now. arming it again
+/// moves the deadline. needs interrupts enabled to ever fire
+pub fn arm_test_watchdog(secs: u32) -> Result<(), interrupts::CallbackError> {
+    let deadline = time::uptime_ms() + secs as u64 * 1000;
+    WATCHDOG_DEADLINE_MS.store(deadline, Ordering::SeqCst);
+    if !WATCHDOG_REGISTERED.swap(true, Ordering::SeqCst) {
+        interrupts::on_tick(test_watchdog).inspect_err(|_| {
+            WATCHDOG_REGISTERED.store(false, Ordering::SeqCst);
+        })?;
+    }
+    Ok(())
+}
+
+/// stops the watchdog, the callback stays registered but never fires
+pub fn disarm_test_watchdog() {
+    WATCHDOG_DEADLINE_MS.store(u64::MAX, Ordering::SeqCst);
+}
+
+fn test_watchdog(_tick: u64) {
+    if time::uptime_ms() < WATCHDOG_DEADLINE_MS.load(Ordering::SeqCst) {
+        return;
+    }
+    disarm_test_watchdog();
+    // the hung test could have been interrupted in the middle of a print, and it is never
+    // going to release the port
+    unsafe { serial::SERIAL1.force_unlock() };
+    panic!("test watchdog expired, the test hung");
+}
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
+    panic::run_hooks();
     serial_println!("[failed]\n");
     serial_println!("Error: {}\n", info);
     exit_qemu(QemuExitCode::Failed);
-    loop {}
+    hlt_loop();
+}
+
+static EXPECTED_FAULT: AtomicU8 = AtomicU8::new(0);
+
+/// the body of an integration test checking that a fault reaches the kernel's handler: prints
+/// `name`, loads the kernel's gdt and idt and runs `trigger`. the handlers halt instead of
+/// returning but run the panic hooks first, so a hook passes the test if the first exception
+/// being handled is `expected`. fails if `trigger` returns
+pub fn test_fault_exit(name: &str, expected: ExceptionVector, trigger: impl FnOnce()) -> ! {
+    serial_print!("{}...\t", name);
+
+    gdt::init();
+    interrupts::init_idt();
+    EXPECTED_FAULT.store(expected as u8, Ordering::SeqCst);
+    panic::on_panic(after_expected_fault).expect("no panic hook slot for the fault test");
+
+    trigger();
+
+    serial_println!("[no exception]");
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop();
+}
+
+fn after_expected_fault() {
+    match interrupts::first_exception() {
+        Some((vector, _)) if vector as u8 == EXPECTED_FAULT.load(Ordering::SeqCst) => {
+            serial_println!("[ok]");
+            exit_qemu(QemuExitCode::Success);
+        }
+        other => {
+            serial_println!("[failed]\nhooks ran while handling {:?}", other);
+            exit_qemu(QemuExitCode::Failed);
+        }
+    }
+}
+
+/// halts the cpu until the next interrupt, forever. unlike an empty loop it doesnt keep a
+/// (host) core busy. the interrupt flag isnt touched: once init enabled interrupts, the timer
+/// and the keyboard wake it up and their handlers run as usual. with interrupts disabled (ie. in
+/// an exception handler) the cpu stays halted
+pub fn hlt_loop() -> ! {
+    loop {
+        x86_64::instructions::hlt();
+    }
 }
 
 pub fn init() {
+    boot::init(boot::BUILTIN_CMDLINE);
+    match boot::args().serial_baud.map(serial::divisor_for) {
+        Some(Some(divisor)) => serial::init_with_baud(divisor),
+        Some(None) => {
+            serial::init();
+            serial_println!("serial: unsupported baud rate, keeping the default");
+        }
+        None => serial::init(),
+    }
+    logger::init();
+    if boot::args().heartbeat {
+        // only fails when every callback slot is taken, there is just no heartbeat then
+        let _ = interrupts::enable_heartbeat();
+    }
+    if !boot::args().quiet {
+        serial_print!("kernel layout:\n{}", memory::kernel_sections());
+        serial_print!("address space:\n{}", memory::AddressSpaceLayout);
+        serial_print!("cpu:\n{}", cpu::CpuSummary);
+    }
+    cpu::enable_global_pages();
+    cpu::enable_write_protect();
     gdt::init();
     interrupts::init_idt();
+    pic::init_pics();
+    // the irqs come from the PICs until something reroutes them through the IO APIC
+    interrupts::set_eoi_mode(interrupts::EoiMode::Pic);
+    interrupts::defer_enable();
+    boot::advance_stage(boot::BootStage::Descriptors);
+
+    if boot::args().selftest {
+        let report = selftest::run();
+        if report.critical_failure() {
+            panic!("critical boot self-test failed: {:#?}", report);
+        }
+    }
+
+    boot::advance_stage(boot::BootStage::Ready);
+    interrupts::enable_when_ready();
 }
 
+/// everything init_all brought up
+pub struct Kernel {
+    pub mapper: OffsetPageTable<'static>,
+    pub frame_allocator: memory::BootInfoFrameAllocator,
+    pub executor: task::executor::Executor,
+}
+
+static INIT_ALL_DONE: AtomicBool = AtomicBool::new(false);
+
+/// brings up the whole kernel in order: `args` instead of the built in command line, then init
+/// (serial, descriptors, PIC, interrupts), paging, the frame allocator, the heap and the guarded
+/// irq stack. init alone is still there for tests that only need part of it.
+/// can only be called once, a second mapper over the same page tables would alias the first
+pub fn init_all(boot_info: &'static BootInfo, args: &KernelArgs<'static>) -> Kernel {
+    assert!(
+        !INIT_ALL_DONE.swap(true, Ordering::SeqCst),
+        "init_all called twice"
+    );
+    boot::init_with_args(*args);
+    init();
+
+    let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    // the offset and the memory map come from the bootloader, and init_all only runs once
+    let mut mapper = unsafe { memory::init(physical_memory_offset) };
+    let mut frame_allocator =
+        unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    gdt::init_guarded_irq_stack(&mut mapper, &mut frame_allocator)
+        .expect("irq stack initialization failed");
+    boot::advance_stage(boot::BootStage::Memory);
+
+    Kernel {
+        mapper,
+        frame_allocator,
+        executor: task::executor::Executor::new(),
+    }
+}
+
+#[cfg(test)]
+bootloader::entry_point!(test_kernel_main);
+
 // entry point for cargo test
 #[cfg(test)]
-#[unsafe(no_mangle)]
-pub extern "C" fn _start() -> ! {
-    init();
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
+    let args = boot::parse_cmdline(boot::BUILTIN_CMDLINE);
+    init_all(boot_info, &args);
     test_main();
-    loop {}
+    hlt_loop();
 }
 
 #[cfg(test)]
@@ -89,3 +325,23 @@ pub extern "C" fn _start() -> ! {
 fn panic(info: &PanicInfo) -> ! {
     test_panic_handler(info)
 }
+
+#[test_case]
+fn test_init_all_brought_everything_up() {
+    use alloc::boxed::Box;
+
+    // the test entry point went through init_all
+    assert_eq!(boot::stage(), boot::BootStage::Memory);
+    assert!(x86_64::instructions::interrupts::are_enabled());
+    let value = Box::new(41);
+    assert_eq!(*value + 1, 42);
+}
+
+#[test_case]
+fn test_test_filter_matches_part_of_the_path() {
+    let name = "os::vga_buffer::test_println_simple";
+    assert!(test_selected(name, None));
+    assert!(test_selected(name, Some("vga_buffer")));
+    assert!(test_selected(name, Some("println")));
+    assert!(!test_selected(name, Some("serial")));
+}
@@ -0,0 +1,241 @@
+// a tiny readline for the serial console. bytes received from the terminal are fed one by one,
+// decoded into keys by the term module, and the line is redrawn in place after every edit.
+//
+// redrawing uses ANSI escape sequences:
+//  \r          moves the terminal cursor to column 0
+//  ESC [ K     erases from the cursor to the end of the line
+//  ESC [ n D   moves the cursor n columns to the left
+//
+// there is no heap, so the line and the history are fixed size arrays.
+
+use core::fmt::{self, Write};
+
+use crate::term::{Decoder, TermKey};
+
+pub const MAX_LINE_LEN: usize = 128;
+/// how many of the last entered lines can be recalled with up/down
+pub const HISTORY_LEN: usize = 16;
+
+#[derive(Clone, Copy)]
+struct Line {
+    bytes: [u8; MAX_LINE_LEN],
+    len: usize,
+}
+
+impl Line {
+    const fn empty() -> Line {
+        Line {
+            bytes: [0; MAX_LINE_LEN],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        // only printable ascii is ever stored
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+}
+
+pub struct LineEditor {
+    prompt: &'static str,
+    line: Line,
+    /// position of the cursor inside the line
+    cursor: usize,
+    decoder: Decoder,
+    /// ring buffer of entered lines, `history_next` is where the next one goes
+    history: [Line; HISTORY_LEN],
+    history_next: usize,
+    history_count: usize,
+    /// how far back we are while browsing the history, 0 means editing a fresh line
+    history_pos: usize,
+}
+
+impl LineEditor {
+    pub const fn new(prompt: &'static str) -> LineEditor {
+        LineEditor {
+            prompt,
+            line: Line::empty(),
+            cursor: 0,
+            decoder: Decoder::new(),
+            history: [Line::empty(); HISTORY_LEN],
+            history_next: 0,
+            history_count: 0,
+            history_pos: 0,
+        }
+    }
+
+    /// the line as it is being edited
+    pub fn line(&self) -> &str {
+        self.line.as_str()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// handles a single received byte, echoing the result to `out`.
+    /// returns the finished line once enter is pressed
+    pub fn feed(&mut self, byte: u8, out: &mut impl Write) -> Option<&str> {
+        match self.decoder.feed(byte)? {
+            TermKey::Enter => {
+                let _ = out.write_str("\r\n");
+                let empty = self.line.len == 0;
+                self.push_history();
+                self.line = Line::empty();
+                self.cursor = 0;
+                self.history_pos = 0;
+                if empty {
+                    return Some("");
+                }
+                // push_history just saved the line (or it repeated the previous one),
+                // so we can hand out the newest entry while `line` is reset for the next one
+                let last = (self.history_next + HISTORY_LEN - 1) % HISTORY_LEN;
+                return Some(self.history[last].as_str());
+            }
+            TermKey::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.remove_at_cursor();
+            }
+            TermKey::Delete if self.cursor < self.line.len => self.remove_at_cursor(),
+            TermKey::Char(c) if (' '..='~').contains(&c) && self.line.len < MAX_LINE_LEN => {
+                self.line
+                    .bytes
+                    .copy_within(self.cursor..self.line.len, self.cursor + 1);
+                self.line.bytes[self.cursor] = c as u8;
+                self.line.len += 1;
+                self.cursor += 1;
+            }
+            TermKey::Up => self.history_older(),
+            TermKey::Down => self.history_newer(),
+            TermKey::Right => self.cursor = (self.cursor + 1).min(self.line.len),
+            TermKey::Left => self.cursor = self.cursor.saturating_sub(1),
+            TermKey::Home => self.cursor = 0,
+            TermKey::End => self.cursor = self.line.len,
+            // keys we dont support, a backspace at the start of the line or a char that
+            // doesnt fit anymore
+            _ => return None,
+        }
+        let _ = self.redraw(out);
+        None
+    }
+
+    fn remove_at_cursor(&mut self) {
+        self.line
+            .bytes
+            .copy_within(self.cursor + 1..self.line.len, self.cursor);
+        self.line.len -= 1;
+    }
+
+    /// prints the prompt and the line again and puts the terminal cursor where ours is
+    pub fn redraw(&self, out: &mut impl Write) -> fmt::Result {
+        write!(out, "\r{}{}\x1b[K", self.prompt, self.line.as_str())?;
+        let back = self.line.len - self.cursor;
+        if back > 0 {
+            write!(out, "\x1b[{}D", back)?;
+        }
+        Ok(())
+    }
+
+    /// empty lines and repeats of the previous line are not worth remembering
+    fn push_history(&mut self) {
+        if self.line.len == 0 {
+            return;
+        }
+        if self.history_count > 0 {
+            let last = (self.history_next + HISTORY_LEN - 1) % HISTORY_LEN;
+            if self.history[last].as_str() == self.line.as_str() {
+                return;
+            }
+        }
+        self.history[self.history_next] = self.line;
+        self.history_next = (self.history_next + 1) % HISTORY_LEN;
+        self.history_count = (self.history_count + 1).min(HISTORY_LEN);
+    }
+
+    /// loads the entry `history_pos` steps back, or a fresh line for 0
+    fn load_history(&mut self) {
+        self.line = if self.history_pos == 0 {
+            Line::empty()
+        } else {
+            self.history[(self.history_next + HISTORY_LEN - self.history_pos) % HISTORY_LEN]
+        };
+        self.cursor = self.line.len;
+    }
+
+    fn history_older(&mut self) {
+        if self.history_pos < self.history_count {
+            self.history_pos += 1;
+            self.load_history();
+        }
+    }
+
+    fn history_newer(&mut self) {
+        if self.history_pos > 0 {
+            self.history_pos -= 1;
+            self.load_history();
+        }
+    }
+}
+
+#[cfg(test)]
+struct Discard;
+
+#[cfg(test)]
+impl Write for Discard {
+    fn write_str(&mut self, _s: &str) -> fmt::Result {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn feed_all(editor: &mut LineEditor, input: &[u8]) {
+    for &byte in input {
+        editor.feed(byte, &mut Discard);
+    }
+}
+
+#[test_case]
+fn test_cursor_movement() {
+    let mut editor = LineEditor::new("> ");
+    // type "ac", go left, insert "b", then Home and End
+    feed_all(&mut editor, b"ac\x1b[Db");
+    assert_eq!(editor.line(), "abc");
+    assert_eq!(editor.cursor(), 2);
+
+    feed_all(&mut editor, b"\x1b[H");
+    assert_eq!(editor.cursor(), 0);
+    feed_all(&mut editor, b"\x1b[C\x08");
+    assert_eq!(editor.line(), "bc");
+    feed_all(&mut editor, b"\x1b[4~");
+    assert_eq!(editor.cursor(), 2);
+    // right arrow at the end of the line stays put
+    feed_all(&mut editor, b"\x1b[C");
+    assert_eq!(editor.cursor(), 2);
+}
+
+#[test_case]
+fn test_history_recall() {
+    let mut editor = LineEditor::new("> ");
+    feed_all(&mut editor, b"first");
+    assert_eq!(editor.feed(b'\r', &mut Discard), Some("first"));
+    feed_all(&mut editor, b"second\r");
+
+    feed_all(&mut editor, b"\x1b[A");
+    assert_eq!(editor.line(), "second");
+    feed_all(&mut editor, b"\x1b[A");
+    assert_eq!(editor.line(), "first");
+    // there is nothing older than the first line
+    feed_all(&mut editor, b"\x1b[A");
+    assert_eq!(editor.line(), "first");
+    feed_all(&mut editor, b"\x1b[B\x1b[B");
+    assert_eq!(editor.line(), "");
+
+    // only the last HISTORY_LEN lines are kept
+    for i in 0..HISTORY_LEN + 1 {
+        feed_all(&mut editor, &[b'a' + i as u8, b'\r']);
+    }
+    for _ in 0..HISTORY_LEN + 1 {
+        feed_all(&mut editor, b"\x1b[A");
+    }
+    assert_eq!(editor.line(), "b");
+}
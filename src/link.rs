@@ -0,0 +1,378 @@
+// a reliable channel between two kernels over COM2, ie. two qemu instances whose second serial
+// ports are cross connected (a virtual null modem):
+//      qemu ... -serial stdio -serial unix:/tmp/link,server
+//      qemu ... -serial stdio -serial unix:/tmp/link
+//
+// every message goes out in a frame:
+// Byte	    Field
+// 0	    0x7E, start of a frame
+// 1	    kind, DATA or ACK
+// 2	    sequence number
+// 3	    payload length (0 for an ACK)
+// 4..	    payload, at most MAX_PAYLOAD bytes
+// last	    checksum, the wrapping sum of kind, sequence, length and payload
+//
+// it is a stop-and-wait ARQ (automatic repeat request): the sender sends one DATA frame and waits
+// for the ACK with the same sequence number, sending it again if none arrives in time. that
+// handles lost frames, and lost ACKs cause a retransmission of a frame the receiver already has.
+// the receiver spots those because the sequence number is the previous one, ACKs them again and
+// drops them instead of delivering the message twice.
+// the timeouts are measured with time::uptime_ms, so the timer has to be ticking (interrupts
+// enabled) for send and recv to ever give up.
+//
+// the 16550's receive FIFO holds 16 bytes. while a frame goes out the other side can answer, and
+// in loopback the frame comes straight back to us, so send_byte moves whatever arrived into
+// RX_PENDING while it waits for the transmitter. otherwise anything longer than the FIFO would
+// overrun it and the frame would never pass its checksum.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+use crate::serial::{COM2_BASE, SERIAL2};
+use crate::util::ByteQueue;
+use crate::{cpu, time};
+
+const LINE_STATUS_OFFSET: u16 = 5;
+const LSR_DATA_READY: u8 = 1;
+const LSR_TRANSMIT_EMPTY: u8 = 1 << 5;
+
+const FRAME_START: u8 = 0x7E;
+const KIND_DATA: u8 = 1;
+const KIND_ACK: u8 = 2;
+
+pub const MAX_PAYLOAD: usize = 64;
+/// how many times a frame is sent before giving up
+const MAX_ATTEMPTS: usize = 8;
+/// how long to wait for an ACK before sending the frame again
+const ACK_TIMEOUT_MS: u64 = 100;
+/// how long recv waits for a message
+const RECV_TIMEOUT_MS: u64 = 1000;
+/// room for a full frame and an ACK, with plenty to spare
+const RX_PENDING_SIZE: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkError {
+    /// no ACK (send) or no message (recv) arrived in time
+    Timeout,
+    /// the message is longer than MAX_PAYLOAD
+    TooLong,
+    /// the received message doesnt fit, it is kept for the next recv
+    BufferTooSmall,
+}
+
+#[derive(Clone, Copy)]
+struct Frame {
+    kind: u8,
+    seq: u8,
+    len: usize,
+    payload: [u8; MAX_PAYLOAD],
+}
+
+impl Frame {
+    const fn empty() -> Frame {
+        Frame {
+            kind: 0,
+            seq: 0,
+            len: 0,
+            payload: [0; MAX_PAYLOAD],
+        }
+    }
+
+    fn checksum(&self) -> u8 {
+        self.payload[..self.len].iter().fold(
+            self.kind
+                .wrapping_add(self.seq)
+                .wrapping_add(self.len as u8),
+            |sum, &b| sum.wrapping_add(b),
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+enum ParseState {
+    Start,
+    Kind,
+    Seq,
+    Len,
+    /// `n` payload bytes read so far
+    Payload(usize),
+    Checksum,
+}
+
+/// reassembles frames from the received bytes, dropping broken ones
+struct Parser {
+    state: ParseState,
+    frame: Frame,
+}
+
+impl Parser {
+    fn feed(&mut self, byte: u8) -> Option<Frame> {
+        self.state = match self.state {
+            ParseState::Start if byte == FRAME_START => ParseState::Kind,
+            ParseState::Start => ParseState::Start,
+            ParseState::Kind => {
+                self.frame.kind = byte;
+                ParseState::Seq
+            }
+            ParseState::Seq => {
+                self.frame.seq = byte;
+                ParseState::Len
+            }
+            ParseState::Len if byte as usize > MAX_PAYLOAD => ParseState::Start,
+            ParseState::Len => {
+                self.frame.len = byte as usize;
+                if byte == 0 {
+                    ParseState::Checksum
+                } else {
+                    ParseState::Payload(0)
+                }
+            }
+            ParseState::Payload(n) => {
+                self.frame.payload[n] = byte;
+                if n + 1 == self.frame.len {
+                    ParseState::Checksum
+                } else {
+                    ParseState::Payload(n + 1)
+                }
+            }
+            ParseState::Checksum => {
+                self.state = ParseState::Start;
+                return (byte == self.frame.checksum()).then_some(self.frame);
+            }
+        };
+        None
+    }
+}
+
+struct Link {
+    /// sequence number of the next DATA frame we send
+    tx_seq: u8,
+    /// sequence number of the next DATA frame we accept
+    rx_seq: u8,
+    /// a delivered message waiting for recv
+    received: Option<Frame>,
+    parser: Parser,
+}
+
+static LINK: Mutex<Link> = Mutex::new(Link {
+    tx_seq: 0,
+    rx_seq: 0,
+    received: None,
+    parser: Parser {
+        state: ParseState::Start,
+        frame: Frame::empty(),
+    },
+});
+static RETRANSMISSIONS: AtomicU64 = AtomicU64::new(0);
+/// bytes taken out of the receive FIFO while sending, handed out before the FIFO's own
+static RX_PENDING: ByteQueue<RX_PENDING_SIZE> = ByteQueue::new();
+
+fn init_port() {
+    lazy_static::initialize(&SERIAL2);
+}
+
+fn line_status() -> u8 {
+    let mut lsr: Port<u8> = Port::new(COM2_BASE + LINE_STATUS_OFFSET);
+    unsafe { lsr.read() }
+}
+
+fn send_byte(byte: u8) {
+    loop {
+        drain_receiver();
+        if line_status() & LSR_TRANSMIT_EMPTY != 0 {
+            break;
+        }
+        cpu::spin_hint();
+    }
+    let mut data: Port<u8> = Port::new(COM2_BASE);
+    unsafe { data.write(byte) };
+    drain_receiver();
+}
+
+/// moves everything in the receive FIFO to RX_PENDING
+fn drain_receiver() {
+    while let Some(byte) = read_receiver() {
+        RX_PENDING.push(byte);
+    }
+}
+
+fn read_receiver() -> Option<u8> {
+    if line_status() & LSR_DATA_READY == 0 {
+        return None;
+    }
+    let mut data: Port<u8> = Port::new(COM2_BASE);
+    Some(unsafe { data.read() })
+}
+
+fn try_receive_byte() -> Option<u8> {
+    RX_PENDING.pop().or_else(read_receiver)
+}
+
+/// whether `ms` milliseconds have passed since `start`
+fn timed_out(start: u64, ms: u64) -> bool {
+    time::uptime_ms() - start >= ms
+}
+
+fn send_frame(frame: &Frame) {
+    send_byte(FRAME_START);
+    send_byte(frame.kind);
+    send_byte(frame.seq);
+    send_byte(frame.len as u8);
+    for &byte in &frame.payload[..frame.len] {
+        send_byte(byte);
+    }
+    send_byte(frame.checksum());
+}
+
+fn send_ack(seq: u8) {
+    send_frame(&Frame {
+        kind: KIND_ACK,
+        seq,
+        ..Frame::empty()
+    });
+}
+
+impl Link {
+    /// handles everything that arrived so far, returns the sequence number of the last ACK seen
+    fn poll(&mut self) -> Option<u8> {
+        let mut ack = None;
+        while let Some(byte) = try_receive_byte() {
+            if let Some(frame) = self.parser.feed(byte) {
+                match frame.kind {
+                    KIND_ACK => ack = Some(frame.seq),
+                    KIND_DATA => self.handle_data(frame),
+                    _ => {}
+                }
+            }
+        }
+        ack
+    }
+
+    fn handle_data(&mut self, frame: Frame) {
+        if frame.seq == self.rx_seq && self.received.is_none() {
+            self.received = Some(frame);
+            self.rx_seq = self.rx_seq.wrapping_add(1);
+            send_ack(frame.seq);
+        } else if frame.seq == self.rx_seq.wrapping_sub(1) {
+            // we already have it, our ACK got lost
+            send_ack(frame.seq);
+        }
+        // otherwise we have no room for it yet, not ACKing makes the sender try again later
+    }
+}
+
+/// sends `data` to the other side, returns once it has been acknowledged.
+/// interrupts have to be enabled, otherwise the timeout would never run out
+pub fn send(data: &[u8]) -> Result<(), LinkError> {
+    if data.len() > MAX_PAYLOAD {
+        return Err(LinkError::TooLong);
+    }
+    assert!(
+        interrupts::are_enabled(),
+        "link::send with interrupts disabled would never time out"
+    );
+    init_port();
+    let mut link = LINK.lock();
+    let mut frame = Frame {
+        kind: KIND_DATA,
+        seq: link.tx_seq,
+        len: data.len(),
+        ..Frame::empty()
+    };
+    frame.payload[..data.len()].copy_from_slice(data);
+
+    for attempt in 0..MAX_ATTEMPTS {
+        if attempt > 0 {
+            RETRANSMISSIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        send_frame(&frame);
+        let start = time::uptime_ms();
+        while !timed_out(start, ACK_TIMEOUT_MS) {
+            // ACKs for an older frame are late duplicates, they dont count
+            if link.poll() == Some(frame.seq) {
+                link.tx_seq = link.tx_seq.wrapping_add(1);
+                return Ok(());
+            }
+            cpu::spin_hint();
+        }
+    }
+    Err(LinkError::Timeout)
+}
+
+/// waits for the next message and copies it into `buf`, returns its length.
+/// interrupts have to be enabled, otherwise the timeout would never run out
+pub fn recv(buf: &mut [u8]) -> Result<usize, LinkError> {
+    assert!(
+        interrupts::are_enabled(),
+        "link::recv with interrupts disabled would never time out"
+    );
+    init_port();
+    let mut link = LINK.lock();
+    let start = time::uptime_ms();
+    while !timed_out(start, RECV_TIMEOUT_MS) {
+        link.poll();
+        if let Some(frame) = &link.received {
+            let len = frame.len;
+            let Some(dest) = buf.get_mut(..len) else {
+                return Err(LinkError::BufferTooSmall);
+            };
+            dest.copy_from_slice(&frame.payload[..len]);
+            link.received = None;
+            return Ok(len);
+        }
+        cpu::spin_hint();
+    }
+    Err(LinkError::Timeout)
+}
+
+/// how many frames had to be sent again
+pub fn retransmissions() -> u64 {
+    RETRANSMISSIONS.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+const MODEM_CONTROL_OFFSET: u16 = 4;
+#[cfg(test)]
+const MCR_LOOPBACK: u8 = 1 << 4;
+
+#[test_case]
+fn test_loopback_link() {
+    init_port();
+    let mut mcr: Port<u8> = Port::new(COM2_BASE + MODEM_CONTROL_OFFSET);
+    let saved_mcr = unsafe { mcr.read() };
+    unsafe { mcr.write(saved_mcr | MCR_LOOPBACK) };
+
+    // in loopback our own DATA frame comes back, gets delivered and ACKed to ourselves
+    let sent = send(b"hello over the link");
+    let mut buf = [0u8; MAX_PAYLOAD];
+    let received = recv(&mut buf);
+
+    // the same frame again, as if our ACK had been lost: it is ACKed but not delivered twice
+    let duplicate = {
+        let mut link = LINK.lock();
+        let mut frame = Frame {
+            kind: KIND_DATA,
+            seq: link.rx_seq.wrapping_sub(1),
+            len: 5,
+            ..Frame::empty()
+        };
+        frame.payload[..5].copy_from_slice(b"hello");
+        link.handle_data(frame);
+        let start = time::uptime_ms();
+        let mut ack = None;
+        while ack.is_none() && !timed_out(start, ACK_TIMEOUT_MS) {
+            ack = link.poll();
+        }
+        (ack == Some(frame.seq), link.received.is_none())
+    };
+    unsafe { mcr.write(saved_mcr) };
+
+    assert_eq!(sent, Ok(()));
+    assert_eq!(
+        received.map(|len| &buf[..len]),
+        Ok(&b"hello over the link"[..])
+    );
+    assert_eq!(duplicate, (true, true));
+}
@@ -0,0 +1,144 @@
+// leveled kernel log on the serial port. the level starts out as the `log_level` kernel parameter
+// and can be changed while running, so a host reproducing a bug can turn on debug output and
+// drop back to info afterwards without a reboot. every record checks the level when it is
+// logged, a change applies to the very next one.
+//
+// there are two ways to change it at runtime:
+//  - the `loglevel <level>` command, `command` implements it for the shell
+//  - a control sequence on the serial line: DLE (0x10) followed by the level digit,
+//    0: error, 1: warn, 2: info, 3: debug, 4: trace. ie. `printf '\x103'` turns on debug.
+//    the serial receive path hands every byte to `handle_serial_byte` first
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use crate::boot::{self, LogLevel};
+use crate::serial_println;
+
+const CONTROL_PREFIX: u8 = 0x10;
+
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+/// a DLE came in, the next byte is the level
+static CONTROL_PENDING: AtomicBool = AtomicBool::new(false);
+
+fn level_from_u8(value: u8) -> Option<LogLevel> {
+    match value {
+        0 => Some(LogLevel::Error),
+        1 => Some(LogLevel::Warn),
+        2 => Some(LogLevel::Info),
+        3 => Some(LogLevel::Debug),
+        4 => Some(LogLevel::Trace),
+        _ => None,
+    }
+}
+
+/// takes the level from the kernel parameters
+pub fn init() {
+    set_level(boot::args().log_level);
+}
+
+pub fn set_level(level: LogLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn level() -> LogLevel {
+    level_from_u8(LEVEL.load(Ordering::Relaxed)).unwrap_or(LogLevel::Info)
+}
+
+/// true if a record at `level` would be printed
+pub fn enabled(level: LogLevel) -> bool {
+    level <= self::level()
+}
+
+/// prints the record if its level is enabled, returns whether it was
+#[doc(hidden)]
+pub fn _log(level: LogLevel, args: fmt::Arguments) -> bool {
+    if !enabled(level) {
+        return false;
+    }
+    serial_println!("[{}] {}", level.as_str(), args);
+    true
+}
+
+/// logs to serial at a boot::LogLevel, ie. `log!(LogLevel::Debug, "mapped {:?}", page)`
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {
+        $crate::logger::_log($level, format_args!($($arg)*))
+    };
+}
+
+/// the `loglevel` shell command. without an argument it prints the current level
+pub fn command(arg: &str, out: &mut impl Write) -> fmt::Result {
+    let arg = arg.trim();
+    if arg.is_empty() {
+        return writeln!(out, "log level is {}", level().as_str());
+    }
+    match LogLevel::parse(arg) {
+        Some(level) => {
+            set_level(level);
+            writeln!(out, "log level set to {}", level.as_str())
+        }
+        None => writeln!(
+            out,
+            "loglevel: unknown level `{}`, expected error, warn, info, debug or trace",
+            arg
+        ),
+    }
+}
+
+/// looks for the level control sequence in received serial bytes. returns true if `byte` was
+/// part of it and shouldnt be handed on as input
+pub fn handle_serial_byte(byte: u8) -> bool {
+    if CONTROL_PENDING.swap(false, Ordering::Relaxed) {
+        match byte.checked_sub(b'0').and_then(level_from_u8) {
+            Some(level) => {
+                set_level(level);
+                serial_println!("log level set to {}", level.as_str());
+            }
+            None => {
+                serial_println!("logger: invalid level byte {:#04x} after DLE", byte);
+            }
+        }
+        return true;
+    }
+    if byte == CONTROL_PREFIX {
+        CONTROL_PENDING.store(true, Ordering::Relaxed);
+        return true;
+    }
+    false
+}
+
+#[test_case]
+fn test_level_filters_records() {
+    let saved = level();
+    set_level(LogLevel::Info);
+    assert!(!crate::log!(LogLevel::Debug, "suppressed"));
+    assert!(crate::log!(LogLevel::Warn, "logged at info"));
+
+    // raised through the serial control sequence
+    assert!(handle_serial_byte(CONTROL_PREFIX));
+    assert!(handle_serial_byte(b'3'));
+    assert_eq!(level(), LogLevel::Debug);
+    assert!(crate::log!(LogLevel::Debug, "now it goes through"));
+    assert!(!crate::log!(LogLevel::Trace, "but not this"));
+    assert!(!handle_serial_byte(b'a'));
+
+    set_level(saved);
+}
+
+#[test_case]
+fn test_loglevel_command() {
+    let saved = level();
+    let mut out = crate::util::FixedString::<128>::new();
+    command("debug", &mut out).unwrap();
+    assert_eq!(level(), LogLevel::Debug);
+    assert_eq!(out.as_str(), "log level set to debug\n");
+
+    out.clear();
+    command("loud", &mut out).unwrap();
+    assert_eq!(level(), LogLevel::Debug);
+    assert!(out.as_str().starts_with("loglevel: unknown level `loud`"));
+
+    set_level(saved);
+}
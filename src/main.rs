@@ -10,8 +10,10 @@
 #![test_runner(os::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
+use bootloader::{BootInfo, entry_point};
 use core::panic::PanicInfo;
 use os::println;
+use os::task::{Task, keyboard};
 // most languages need a runtime system which is responsible for
 // tasks like gc in java or goroutines in go. this runtime will be called
 // before main
@@ -25,11 +27,16 @@ use os::println;
 // _start which is entry point will never return because it will not be called by any function
 // instead, it will be invoked directly by bootloader or the OS.
 // so instead of returning, it will call the exit() syscall
-#[unsafe(no_mangle)]
-pub extern "C" fn _start() -> ! {
+//
+// the bootloader passes a BootInfo to _start. entry_point! defines _start for us and checks
+// that kernel_main has the right signature, since nothing would check an extern "C" fn
+entry_point!(kernel_main);
+
+fn kernel_main(boot_info: &'static BootInfo) -> ! {
     println!("Hello World!");
-    // start the idt
-    os::init();
+    // the idt, interrupts, paging and the heap
+    let args = os::boot::parse_cmdline(os::boot::BUILTIN_CMDLINE);
+    let mut kernel = os::init_all(boot_info, &args);
     // invoke a breakpoint exception
     // unsafe {
     //     // triggers a page fault
@@ -46,7 +53,21 @@ pub extern "C" fn _start() -> ! {
     test_main();
 
     println!("it did not crash!");
-    loop {}
+    kernel.executor.spawn(Task::new(example_task()));
+    // echo what is typed, the task only runs when the keyboard interrupt wakes it
+    kernel
+        .executor
+        .spawn(Task::new(keyboard::print_keypresses()));
+    kernel.executor.run();
+}
+
+async fn async_number() -> u32 {
+    42
+}
+
+async fn example_task() {
+    let number = async_number().await;
+    println!("async number: {}", number);
 }
 
 // panic info contains the file and the line where the panic has occured
@@ -59,8 +80,9 @@ pub extern "C" fn _start() -> ! {
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("{}", info);
-    loop {}
+    os::panic::run_hooks();
+    os::panic::print_panic(info);
+    os::hlt_loop();
 }
 
 #[cfg(test)]
@@ -0,0 +1,1013 @@
+// ** TLB (Translation Lookaside Buffer)
+// translating a virtual address walks 4 levels of page tables, which is expensive. so the cpu
+// caches the translations in the TLB. the TLB is not updated automatically when we modify a page
+// table, so after changing or removing a mapping we have to flush the stale entry ourselves.
+//
+// there are two ways of doing this:
+//  1. invlpg: invalidates the translation of a single page
+//  2. reloading CR3: invalidates every translation, except for the pages marked GLOBAL
+//      (see cpu::enable_global_pages)
+// the kernel's own mappings (create_mapping, the heap, guarded stacks) use KERNEL_PAGE_FLAGS,
+// which includes GLOBAL. they look the same in every address space, so switching spaces doesnt
+// lose them.
+//
+// ** freeing frames
+// BootInfoFrameAllocator walks the usable regions of the bootloader's memory map and hands out
+// one frame after the other, it has no way to take a frame back. wrapping it in a
+// ReusingFrameAllocator adds a small stack of returned frames that is emptied before the inner
+// allocator is asked for a new one, so unmapping a page (see unmap) makes its frame available again.
+// BitmapFrameAllocator is the real fix: one bit per physical frame, every frame can be freed and
+// handed out again. both implement FrameAllocator, so whoever sets up paging picks one.
+//
+// ** Page Protection
+// the flags of a page table entry decide what can be done with the page:
+// Flag	        Bit	    Meaning when set
+// PRESENT	    0	    the mapping is valid
+// WRITABLE	    1	    writes are allowed (enforced in ring 0 too once CR0.WP is set)
+// NO_EXECUTE	63	    instructions cant be fetched from the page (needs EFER.NXE)
+// W^X means no page is both writable and executable: .text is read only + executable, .data and
+// .bss are writable + NO_EXECUTE. protect_range changes the flags of pages that are already
+// mapped, the frames stay the same.
+//
+// ** Kernel Sections
+// there is no linker script that could export __text_start and friends, but the linker (lld)
+// always defines __ehdr_start, the address of the ELF header. the header is loaded along with the
+// first segment, and so are the program headers right behind it. the loadable segments are
+// split by permissions, so they tell us where the sections are:
+// Segment flags	Sections
+// R	            ELF headers, .rodata
+// R X	            .text
+// R W	            .data, then .bss (the part of the segment that isnt in the file, memsz > filesz)
+//
+// ** Address Space Split
+// virtual addresses are 48 bits, sign extended to 64. that leaves two canonical halves with a
+// hole in between:
+// Range	                                    Owner
+// 0x0000_0000_0000_0000 - 0x0000_7fff_ffff_ffff	user space, different for every process
+// 0x0000_8000_0000_0000 - 0xffff_7fff_ffff_ffff	not canonical, any access faults
+// 0xffff_8000_0000_0000 - 0xffff_ffff_ffff_ffff	kernel space, the same in every process
+// user processes will get the lower half to themselves, so the kernel should live in the upper
+// one. bootloader 0.9 loads the kernel wherever it was linked and without a linker script that
+// is still the lower half, and so is the rest of what the kernel owns: the heap, the boot stack
+// and the physical memory window the bootloader picked. until it is relinked, is_user_addr
+// carves all of it out of user space: every level 4 slot present in the kernel's table (the
+// same ones an AddressSpace shares, see Address Spaces), and the image itself before init.
+// a slot covers 512GiB, so that takes more than the kernel strictly needs.
+//
+// ** Physical Memory Offset
+// page table entries hold physical addresses, but once paging is on the kernel can only access
+// virtual ones. to read and modify the page tables we need every physical frame mapped
+// somewhere. the bootloader does that when its map_physical_memory feature is on:
+//      bootloader = { version = "0.9", features = ["map_physical_memory"] }
+// all of physical memory then shows up at a fixed virtual offset, which it passes to the kernel
+// in BootInfo::physical_memory_offset:
+//      virtual = physical_memory_offset + physical
+// init builds an OffsetPageTable on top of that, which implements Mapper and Translate.
+//
+// ** Address Spaces
+// every process gets its own level 4 table, and loading it into CR3 switches address spaces.
+// the kernel has to stay mapped in all of them (the interrupt handlers, the stack, the heap),
+// so a new table starts as a copy of the kernel's level 4 entries. the copies point to the same
+// level 3 tables, so the kernel mappings below them are shared, not duplicated.
+// the kernel isnt in the higher half yet (see Address Space Split), so "the kernel's entries"
+// are all the ones present in its table when the address space is created, in either half.
+// an AddressSpace only maps pages in the slots that were empty then, mapping into a shared one
+// would change the kernel's tables and show up in every address space. kernel mappings that
+// get a new level 4 entry later on are missing from address spaces created before, so the
+// kernel sets its mappings up (init_all) before creating any.
+// the page tables an address space allocates are never freed yet.
+//
+// ** Copy On Write
+// a COW page is mapped read only, possibly in several places, to a frame nobody may change. the
+// first write to it faults (PROTECTION_VIOLATION | CAUSED_BY_WRITE, with CR0.WP set for ring 0)
+// and the page fault handler gives the page a private copy instead of crashing:
+//  1. allocate a new frame and copy the old one into it
+//  2. map the page to the new frame, writable
+//  3. flush the page from the TLB and return, the cpu retries the write
+// the old frame is left alone. register_cow_region marks the virtual ranges this applies to,
+// a write fault anywhere else is still a bug. the handler cant wait for a lock, so if the
+// frame allocator or the region table is busy the fault is treated as a bug too.
+
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::fmt;
+use core::ops::Range;
+use spin::{Mutex, Once};
+use x86_64::instructions::tlb;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::mapper::{
+    FlagUpdateError, MapToError, MappedFrame, TranslateResult, UnmapError,
+};
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable,
+    PageTableFlags, PhysFrame, Size4KiB, Translate,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::boot::{self, PhysRange};
+use crate::util::BitMap;
+
+/// the first address past user space
+pub const USER_SPACE_END: u64 = 0x0000_8000_0000_0000;
+/// the first address of kernel space
+pub const KERNEL_SPACE_START: u64 = 0xffff_8000_0000_0000;
+
+static PHYSICAL_MEMORY_OFFSET: Once<VirtAddr> = Once::new();
+/// the level 4 table the bootloader left us with, the kernel's address space
+static KERNEL_LEVEL_4: Once<PhysFrame> = Once::new();
+
+/// gives access to the active page tables, through the bootloader's mapping of physical memory
+/// at `physical_memory_offset`
+///
+/// # Safety
+/// all of physical memory has to be mapped at `physical_memory_offset`. it must only be called
+/// once, the returned table is a `&mut` to the active level 4 table
+pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.call_once(|| physical_memory_offset);
+    KERNEL_LEVEL_4.call_once(|| Cr3::read().0);
+    let level_4_table = unsafe { &mut *table_at(Cr3::read().0, physical_memory_offset) };
+    unsafe { OffsetPageTable::new(level_4_table, physical_memory_offset) }
+}
+
+/// where physical memory is mapped, None before init
+pub fn physical_memory_offset() -> Option<VirtAddr> {
+    PHYSICAL_MEMORY_OFFSET.get().copied()
+}
+
+/// the flags create_mapping and init_heap map kernel pages with
+pub const KERNEL_PAGE_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::GLOBAL);
+
+/// the page table in `frame`
+fn table_at(frame: PhysFrame, physical_memory_offset: VirtAddr) -> *mut PageTable {
+    (physical_memory_offset + frame.start_address().as_u64()).as_mut_ptr()
+}
+
+/// walks the active page tables to find the physical address `addr` maps to, None if it isnt
+/// mapped. huge pages are handled too
+///
+/// # Safety
+/// all of physical memory has to be mapped at `physical_memory_offset`
+pub unsafe fn translate_addr(addr: VirtAddr, physical_memory_offset: VirtAddr) -> Option<PhysAddr> {
+    let indexes = [
+        addr.p4_index(),
+        addr.p3_index(),
+        addr.p2_index(),
+        addr.p1_index(),
+    ];
+    let mut frame = Cr3::read().0;
+    for (level, index) in indexes.into_iter().enumerate() {
+        let table = unsafe { &*table_at(frame, physical_memory_offset) };
+        let entry = &table[index];
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+        if entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            // a huge level 3 entry maps 1GiB, a level 2 one 2MiB
+            let page_size: u64 = match level {
+                1 => 1 << 30,
+                2 => 1 << 21,
+                _ => return None,
+            };
+            return Some(entry.addr() + (addr.as_u64() & (page_size - 1)));
+        }
+        frame = PhysFrame::containing_address(entry.addr());
+    }
+    Some(frame.start_address() + u64::from(addr.page_offset()))
+}
+
+/// whether `addr` is mapped in the active page tables, false before init
+pub fn is_mapped(addr: VirtAddr) -> bool {
+    physical_memory_offset().is_some_and(|offset| unsafe { translate_addr(addr, offset) }.is_some())
+}
+
+/// whether `addr` belongs to user space: the lower half, minus every level 4 slot the kernel
+/// uses
+pub fn is_user_addr(addr: VirtAddr) -> bool {
+    addr.as_u64() < USER_SPACE_END && !in_kernel_image(addr) && !in_kernel_slot(addr)
+}
+
+/// whether the level 4 entry covering `addr` is present in the kernel's table. false before
+/// init, there is no table to look at yet
+fn in_kernel_slot(addr: VirtAddr) -> bool {
+    let (Some(offset), Some(&frame)) = (physical_memory_offset(), KERNEL_LEVEL_4.get()) else {
+        return false;
+    };
+    let table = unsafe { &*table_at(frame, offset) };
+    table[addr.p4_index()]
+        .flags()
+        .contains(PageTableFlags::PRESENT)
+}
+
+/// flushes the TLB entry of the page containing `addr` (invlpg)
+pub fn flush_tlb(addr: VirtAddr) {
+    tlb::flush(addr);
+}
+
+/// flushes every non GLOBAL TLB entry by reloading CR3
+pub fn flush_tlb_all() {
+    tlb::flush_all();
+}
+
+/// how many returned frames a ReusingFrameAllocator remembers
+pub const MAX_FREE_FRAMES: usize = 64;
+
+/// hands out frames that were given back before asking `inner` for new ones
+pub struct ReusingFrameAllocator<A> {
+    inner: A,
+    free: [Option<PhysFrame>; MAX_FREE_FRAMES],
+    free_count: usize,
+}
+
+impl<A> ReusingFrameAllocator<A> {
+    pub const fn new(inner: A) -> ReusingFrameAllocator<A> {
+        ReusingFrameAllocator {
+            inner,
+            free: [None; MAX_FREE_FRAMES],
+            free_count: 0,
+        }
+    }
+
+    /// frames waiting to be handed out again
+    pub fn free_frames(&self) -> usize {
+        self.free_count
+    }
+}
+
+unsafe impl<A: FrameAllocator<Size4KiB>> FrameAllocator<Size4KiB> for ReusingFrameAllocator<A> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if self.free_count > 0 {
+            self.free_count -= 1;
+            return self.free[self.free_count].take();
+        }
+        self.inner.allocate_frame()
+    }
+}
+
+impl<A> FrameDeallocator<Size4KiB> for ReusingFrameAllocator<A> {
+    /// once the free list is full the frame is leaked, it is still better than handing it out twice
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        if self.free_count < MAX_FREE_FRAMES {
+            self.free[self.free_count] = Some(frame);
+            self.free_count += 1;
+        }
+    }
+}
+
+const FRAME_SIZE: u64 = 4096;
+
+/// hands out the usable frames of the bootloader's memory map in order, skipping those reserved
+/// with boot::reserve. `next` counts the frames handed out so far, so every allocation walks the
+/// map from the start: slow, but it needs no memory of its own. reservations made after the
+/// first allocation shift the count, they have to be done before
+pub struct BootInfoFrameAllocator {
+    memory_map: &'static MemoryMap,
+    next: usize,
+}
+
+impl BootInfoFrameAllocator {
+    /// # Safety
+    /// the usable regions of `memory_map` must really be unused
+    pub unsafe fn init(memory_map: &'static MemoryMap) -> BootInfoFrameAllocator {
+        BootInfoFrameAllocator {
+            memory_map,
+            next: 0,
+        }
+    }
+
+    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> + use<> {
+        self.memory_map
+            .iter()
+            .filter(|region| region.region_type == MemoryRegionType::Usable)
+            .flat_map(|region| {
+                (region.range.start_addr()..region.range.end_addr()).step_by(FRAME_SIZE as usize)
+            })
+            .filter(|&start| !boot::is_reserved(PhysRange::new(start, start + FRAME_SIZE)))
+            .map(|start| PhysFrame::containing_address(PhysAddr::new(start)))
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let frame = self.usable_frames().nth(self.next);
+        if frame.is_some() {
+            self.next += 1;
+        }
+        frame
+    }
+}
+
+/// tracks every physical frame with a bit, 1 means in use. frame n is bit n, so the bitmap needs
+/// a bit for every frame up to the end of the highest usable region. usable frames past the end
+/// of the storage it is given are never handed out
+pub struct BitmapFrameAllocator<'a> {
+    frames: BitMap<'a>,
+    free: usize,
+}
+
+impl<'a> BitmapFrameAllocator<'a> {
+    /// frees the usable frames of the bootloader's memory map, minus those reserved with
+    /// boot::reserve
+    ///
+    /// # Safety
+    /// the usable regions of `memory_map` must really be unused
+    pub unsafe fn init(memory_map: &MemoryMap, words: &'a mut [u64]) -> BitmapFrameAllocator<'a> {
+        let usable = memory_map
+            .iter()
+            .filter(|region| region.region_type == MemoryRegionType::Usable)
+            .map(|region| region.range.start_frame_number..region.range.end_frame_number);
+        Self::with_usable(usable, words)
+    }
+
+    /// `usable` are ranges of frame numbers
+    fn with_usable(
+        usable: impl Iterator<Item = Range<u64>> + Clone,
+        words: &'a mut [u64],
+    ) -> BitmapFrameAllocator<'a> {
+        let end = usable.clone().map(|range| range.end).max().unwrap_or(0);
+        let len = (end as usize).min(words.len() * 64);
+        // everything starts out used, only the usable frames are cleared
+        words.fill(u64::MAX);
+        let mut frames = BitMap::new(words, len);
+        let mut free = 0;
+        for range in usable {
+            for frame in range.start as usize..(range.end as usize).min(len) {
+                let start = frame as u64 * FRAME_SIZE;
+                if boot::is_reserved(PhysRange::new(start, start + FRAME_SIZE)) {
+                    continue;
+                }
+                if frames.get(frame) {
+                    frames.clear(frame);
+                    free += 1;
+                }
+            }
+        }
+        BitmapFrameAllocator { frames, free }
+    }
+
+    /// frames that can still be allocated
+    pub fn free_frames(&self) -> usize {
+        self.free
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator<'_> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let frame = self.frames.find_first_zero()?;
+        self.frames.set(frame);
+        self.free -= 1;
+        Some(PhysFrame::containing_address(PhysAddr::new(
+            frame as u64 * FRAME_SIZE,
+        )))
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator<'_> {
+    /// frames this allocator doesnt track and frames that are already free are ignored
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let frame = (frame.start_address().as_u64() / FRAME_SIZE) as usize;
+        if frame < self.frames.len() && self.frames.get(frame) {
+            self.frames.clear(frame);
+            self.free += 1;
+        }
+    }
+}
+
+/// maps `page` to `frame` with KERNEL_PAGE_FLAGS and flushes it from the TLB. `allocator` provides
+/// the frames for page tables that dont exist yet. fails if the page is already mapped or no
+/// frame is left for a page table
+///
+/// # Safety
+/// `frame` may be in use already, ie. mapping the vga buffer twice is fine, but nothing may
+/// assume it is the only way to reach the frame then
+pub unsafe fn create_mapping(
+    page: Page,
+    frame: PhysFrame,
+    mapper: &mut impl Mapper<Size4KiB>,
+    allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    unsafe { mapper.map_to(page, frame, KERNEL_PAGE_FLAGS, allocator)? }.flush();
+    Ok(())
+}
+
+/// removes the mapping of `page`, flushes it from the TLB and gives its frame back to `deallocator`.
+///
+/// # Safety
+/// nothing may use the page or the frame anymore, the frame will be handed out again
+pub unsafe fn unmap(
+    page: Page,
+    mapper: &mut impl Mapper<Size4KiB>,
+    deallocator: &mut impl FrameDeallocator<Size4KiB>,
+) -> Result<PhysFrame, UnmapError> {
+    let (frame, flush) = mapper.unmap(page)?;
+    flush.flush();
+    unsafe { deallocator.deallocate_frame(frame) };
+    Ok(frame)
+}
+
+/// sets the flags of every page overlapping `range` to `flags` and flushes them from the TLB.
+/// the range doesnt have to be page aligned, partially covered pages are changed as a whole.
+/// stops at the first page that isnt mapped
+///
+/// # Safety
+/// nothing may rely on the old permissions anymore, ie. making the current stack read only
+/// faults on the next push
+pub unsafe fn protect_range(
+    range: Range<VirtAddr>,
+    flags: PageTableFlags,
+    mapper: &mut impl Mapper<Size4KiB>,
+) -> Result<(), FlagUpdateError> {
+    if range.start >= range.end {
+        return Ok(());
+    }
+    let first = Page::<Size4KiB>::containing_address(range.start);
+    let last = Page::<Size4KiB>::containing_address(range.end - 1u64);
+    for page in Page::range_inclusive(first, last) {
+        unsafe { mapper.update_flags(page, flags)? }.flush();
+    }
+    Ok(())
+}
+
+/// a stack on fresh frames with an unmapped guard page right below it, so running off its end
+/// is a page fault instead of a silent write into whatever comes next
+#[derive(Debug, Clone)]
+pub struct GuardedStack {
+    guard: Page,
+    stack: Range<VirtAddr>,
+}
+
+impl GuardedStack {
+    /// leaves `guard` unmapped and maps the `pages` pages above it to newly allocated frames.
+    /// fails if the guard page or any of the stack pages is already mapped
+    pub fn map(
+        guard: Page,
+        pages: u64,
+        mapper: &mut impl Mapper<Size4KiB>,
+        frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    ) -> Result<GuardedStack, MapToError<Size4KiB>> {
+        if let Ok(frame) = mapper.translate_page(guard) {
+            return Err(MapToError::PageAlreadyMapped(frame));
+        }
+        for page in Page::range(guard + 1, guard + 1 + pages) {
+            let frame = frame_allocator
+                .allocate_frame()
+                .ok_or(MapToError::FrameAllocationFailed)?;
+            unsafe { mapper.map_to(page, frame, KERNEL_PAGE_FLAGS, frame_allocator)? }.flush();
+        }
+        let start = (guard + 1).start_address();
+        Ok(GuardedStack {
+            guard,
+            stack: start..start + pages * Size4KiB::SIZE,
+        })
+    }
+
+    /// the page below the stack that is never mapped
+    pub fn guard_page(&self) -> Page {
+        self.guard
+    }
+
+    /// the mapped part, the stack starts at `end` and grows down
+    pub fn range(&self) -> Range<VirtAddr> {
+        self.stack.clone()
+    }
+}
+
+#[derive(Debug)]
+pub enum AddressSpaceError {
+    /// no frame left for a page table
+    FrameAllocationFailed,
+    /// the page is under a level 4 entry shared with the kernel
+    SharedEntry(Page),
+    Map(MapToError<Size4KiB>),
+    Unmap(UnmapError),
+}
+
+/// a level 4 table of its own, with the kernel's entries shared
+pub struct AddressSpace {
+    level_4_frame: PhysFrame,
+    /// one bit per level 4 entry copied from the kernel's table
+    shared: [u64; 8],
+}
+
+impl AddressSpace {
+    /// a new address space that only has the kernel mapped. memory::init has to have run
+    pub fn new(
+        frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    ) -> Result<AddressSpace, AddressSpaceError> {
+        let offset = physical_memory_offset().expect("memory::init wasnt called");
+        let kernel_frame = *KERNEL_LEVEL_4.get().expect("memory::init wasnt called");
+        let level_4_frame = frame_allocator
+            .allocate_frame()
+            .ok_or(AddressSpaceError::FrameAllocationFailed)?;
+
+        let kernel_table = unsafe { &*table_at(kernel_frame, offset) };
+        let table = unsafe { &mut *table_at(level_4_frame, offset) };
+        table.zero();
+        let mut shared = [0u64; 8];
+        for (index, entry) in kernel_table.iter().enumerate() {
+            if entry.flags().contains(PageTableFlags::PRESENT) {
+                table[index] = entry.clone();
+                shared[index / 64] |= 1 << (index % 64);
+            }
+        }
+        Ok(AddressSpace {
+            level_4_frame,
+            shared,
+        })
+    }
+
+    /// whether `page` is under a level 4 entry shared with the kernel
+    pub fn is_shared(&self, page: Page) -> bool {
+        let index = usize::from(page.p4_index());
+        self.shared[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    /// whether this is the address space in CR3
+    pub fn is_active(&self) -> bool {
+        Cr3::read().0 == self.level_4_frame
+    }
+
+    fn mapper(&mut self) -> OffsetPageTable<'_> {
+        let offset = physical_memory_offset().expect("memory::init wasnt called");
+        // the table belongs to this address space, and &mut self keeps it from being handed out
+        // twice
+        unsafe { OffsetPageTable::new(&mut *table_at(self.level_4_frame, offset), offset) }
+    }
+
+    /// maps `page` to `frame` in this address space only. pages under a shared entry are
+    /// refused, see the top of this file
+    ///
+    /// # Safety
+    /// same as create_mapping
+    pub unsafe fn map(
+        &mut self,
+        page: Page,
+        frame: PhysFrame,
+        flags: PageTableFlags,
+        frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    ) -> Result<(), AddressSpaceError> {
+        if self.is_shared(page) {
+            return Err(AddressSpaceError::SharedEntry(page));
+        }
+        let active = self.is_active();
+        // the parent tables are private as well, ring 3 needs USER_ACCESSIBLE on every level
+        let parent_flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | (flags & PageTableFlags::USER_ACCESSIBLE);
+        let flush = unsafe {
+            self.mapper().map_to_with_table_flags(
+                page,
+                frame,
+                flags | PageTableFlags::PRESENT,
+                parent_flags,
+                frame_allocator,
+            )
+        }
+        .map_err(AddressSpaceError::Map)?;
+        // an inactive address space has nothing in the TLB
+        if active {
+            flush.flush()
+        } else {
+            flush.ignore()
+        }
+        Ok(())
+    }
+
+    /// removes the mapping of `page` and returns its frame, which the caller now owns
+    pub fn unmap(&mut self, page: Page) -> Result<PhysFrame, AddressSpaceError> {
+        if self.is_shared(page) {
+            return Err(AddressSpaceError::SharedEntry(page));
+        }
+        let active = self.is_active();
+        let (frame, flush) = self
+            .mapper()
+            .unmap(page)
+            .map_err(AddressSpaceError::Unmap)?;
+        if active {
+            flush.flush()
+        } else {
+            flush.ignore()
+        }
+        Ok(frame)
+    }
+
+    /// loads this address space into CR3
+    ///
+    /// # Safety
+    /// nothing may hold a reference into the private part of the current address space
+    pub unsafe fn switch_to(&self) {
+        let (_, flags) = Cr3::read();
+        unsafe { Cr3::write(self.level_4_frame, flags) };
+    }
+}
+
+/// goes back to the kernel's own address space
+///
+/// # Safety
+/// same as AddressSpace::switch_to
+pub unsafe fn switch_to_kernel() {
+    let frame = *KERNEL_LEVEL_4.get().expect("memory::init wasnt called");
+    let (_, flags) = Cr3::read();
+    unsafe { Cr3::write(frame, flags) };
+}
+
+pub const MAX_COW_REGIONS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CowError {
+    /// all MAX_COW_REGIONS slots are taken
+    Full,
+}
+
+type CowFrameAllocator = &'static Mutex<dyn FrameAllocator<Size4KiB> + Send>;
+
+struct CowState {
+    regions: [Option<(VirtAddr, VirtAddr)>; MAX_COW_REGIONS],
+    frame_allocator: Option<CowFrameAllocator>,
+}
+
+static COW: Mutex<CowState> = Mutex::new(CowState {
+    regions: [None; MAX_COW_REGIONS],
+    frame_allocator: None,
+});
+
+/// where the page fault handler takes the frames for private copies from
+pub fn set_cow_frame_allocator(frame_allocator: CowFrameAllocator) {
+    COW.lock().frame_allocator = Some(frame_allocator);
+}
+
+/// write faults on the pages in `range` get a private copy, see the top of this file. the pages
+/// have to be mapped read only
+pub fn register_cow_region(range: Range<VirtAddr>) -> Result<(), CowError> {
+    let mut cow = COW.lock();
+    let slot = cow
+        .regions
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or(CowError::Full)?;
+    *slot = Some((range.start, range.end));
+    Ok(())
+}
+
+/// gives the page containing `addr` a private writable copy if it is in a COW region, returns
+/// whether it did. called by the page fault handler on write faults to present pages
+pub(crate) fn handle_cow_fault(addr: VirtAddr) -> bool {
+    let Some(offset) = physical_memory_offset() else {
+        return false;
+    };
+    let Some(cow) = COW.try_lock() else {
+        return false;
+    };
+    let in_region = cow
+        .regions
+        .iter()
+        .flatten()
+        .any(|&(start, end)| (start..end).contains(&addr));
+    let Some(frame_allocator) = cow.frame_allocator.filter(|_| in_region) else {
+        return false;
+    };
+    let Some(mut frame_allocator) = frame_allocator.try_lock() else {
+        return false;
+    };
+
+    // the active table, not the kernel's: the fault happened in whatever is in CR3
+    let level_4_table = unsafe { &mut *table_at(Cr3::read().0, offset) };
+    let mut mapper = unsafe { OffsetPageTable::new(level_4_table, offset) };
+    let page = Page::<Size4KiB>::containing_address(addr);
+    let TranslateResult::Mapped {
+        frame: MappedFrame::Size4KiB(old_frame),
+        flags,
+        ..
+    } = mapper.translate(page.start_address())
+    else {
+        return false;
+    };
+    if flags.contains(PageTableFlags::WRITABLE) {
+        // not ours, something else is wrong with the page
+        return false;
+    }
+    let Some(new_frame) = frame_allocator.allocate_frame() else {
+        return false;
+    };
+    unsafe {
+        let from = (offset + old_frame.start_address().as_u64()).as_ptr::<u8>();
+        let to = (offset + new_frame.start_address().as_u64()).as_mut_ptr::<u8>();
+        core::ptr::copy_nonoverlapping(from, to, Size4KiB::SIZE as usize);
+    }
+    // the tables down to level 1 exist already, so map_to wont allocate
+    let Ok((_, flush)) = mapper.unmap(page) else {
+        return false;
+    };
+    flush.ignore();
+    let flags = flags | PageTableFlags::WRITABLE;
+    match unsafe { mapper.map_to(page, new_frame, flags, &mut *frame_allocator) } {
+        Ok(flush) => flush.flush(),
+        Err(_) => return false,
+    }
+    true
+}
+
+unsafe extern "C" {
+    static __ehdr_start: u8;
+}
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+/// an entry of the ELF program header table
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct ProgramHeader {
+    kind: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    paddr: u64,
+    file_size: u64,
+    mem_size: u64,
+    align: u64,
+}
+
+/// where the sections of the kernel image are loaded. a range is empty if there is no such section
+#[derive(Debug, Clone)]
+pub struct KernelSections {
+    pub text: Range<VirtAddr>,
+    pub rodata: Range<VirtAddr>,
+    pub data: Range<VirtAddr>,
+    pub bss: Range<VirtAddr>,
+}
+
+impl fmt::Display for KernelSections {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sections = [
+            (".text", &self.text),
+            (".rodata", &self.rodata),
+            (".data", &self.data),
+            (".bss", &self.bss),
+        ];
+        for (name, range) in sections {
+            writeln!(
+                f,
+                "{:<8}{:#x} - {:#x} ({} KiB)",
+                name,
+                range.start.as_u64(),
+                range.end.as_u64(),
+                (range.end - range.start) / 1024
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// grows `range` to cover `start..end`
+fn extend(range: &mut Range<VirtAddr>, start: u64, end: u64) {
+    if start >= end {
+        return;
+    }
+    let (start, end) = (VirtAddr::new(start), VirtAddr::new(end));
+    if range.start == range.end {
+        *range = start..end;
+    } else {
+        *range = range.start.min(start)..range.end.max(end);
+    }
+}
+
+pub fn kernel_sections() -> KernelSections {
+    let empty = VirtAddr::zero()..VirtAddr::zero();
+    let mut sections = KernelSections {
+        text: empty.clone(),
+        rodata: empty.clone(),
+        data: empty.clone(),
+        bss: empty,
+    };
+    unsafe {
+        let header = &raw const __ehdr_start;
+        // e_phoff, e_phentsize and e_phnum of the ELF header
+        let table_offset = header.add(32).cast::<u64>().read_unaligned();
+        let entry_size = header.add(54).cast::<u16>().read_unaligned() as usize;
+        let count = header.add(56).cast::<u16>().read_unaligned() as usize;
+
+        for i in 0..count {
+            let entry = header
+                .add(table_offset as usize + i * entry_size)
+                .cast::<ProgramHeader>()
+                .read_unaligned();
+            if entry.kind != PT_LOAD {
+                continue;
+            }
+            let file_end = entry.vaddr + entry.file_size;
+            let mem_end = entry.vaddr + entry.mem_size;
+            if entry.flags & PF_X != 0 {
+                extend(&mut sections.text, entry.vaddr, mem_end);
+            } else if entry.flags & PF_W != 0 {
+                extend(&mut sections.data, entry.vaddr, file_end);
+                extend(&mut sections.bss, file_end, mem_end);
+            } else {
+                extend(&mut sections.rodata, entry.vaddr, mem_end);
+            }
+        }
+    }
+    sections
+}
+
+/// whether `addr` is part of the loaded kernel image, which is always mapped and readable
+pub fn in_kernel_image(addr: VirtAddr) -> bool {
+    let sections = kernel_sections();
+    [sections.text, sections.rodata, sections.data, sections.bss]
+        .iter()
+        .any(|range| range.contains(&addr))
+}
+
+/// whether every section of the kernel image is in kernel space
+pub fn kernel_in_higher_half() -> bool {
+    let sections = kernel_sections();
+    [sections.text, sections.rodata, sections.data, sections.bss]
+        .iter()
+        .filter(|range| range.start != range.end)
+        .all(|range| range.start.as_u64() >= KERNEL_SPACE_START)
+}
+
+/// the address space split and which side of it the kernel is on
+pub struct AddressSpaceLayout;
+
+impl fmt::Display for AddressSpaceLayout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "user    {:#x} - {:#x}", 0, USER_SPACE_END - 1)?;
+        writeln!(f, "kernel  {:#x} - {:#x}", KERNEL_SPACE_START, u64::MAX)?;
+        if kernel_in_higher_half() {
+            writeln!(f, "kernel image is in the higher half")
+        } else {
+            writeln!(
+                f,
+                "kernel image is in the lower half, reserved out of user space"
+            )
+        }
+    }
+}
+
+#[test_case]
+fn test_flush_tlb_all_keeps_mappings() {
+    static VALUE: u64 = 42;
+    let addr = VirtAddr::from_ptr(&raw const VALUE);
+
+    flush_tlb(addr);
+    flush_tlb_all();
+    // the translation has to be walked again, but it must still resolve to the same memory
+    assert_eq!(
+        unsafe { core::ptr::read_volatile(addr.as_ptr::<u64>()) },
+        42
+    );
+}
+
+#[cfg(test)]
+struct CountingAllocator {
+    next: u64,
+}
+
+#[cfg(test)]
+unsafe impl FrameAllocator<Size4KiB> for CountingAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let frame = PhysFrame::containing_address(x86_64::PhysAddr::new(self.next * 4096));
+        self.next += 1;
+        Some(frame)
+    }
+}
+
+#[test_case]
+fn test_freed_frame_is_reused() {
+    let mut allocator = ReusingFrameAllocator::new(CountingAllocator { next: 1 });
+    let first = allocator.allocate_frame().unwrap();
+    let second = allocator.allocate_frame().unwrap();
+    assert_ne!(first, second);
+
+    unsafe { allocator.deallocate_frame(first) };
+    assert_eq!(allocator.free_frames(), 1);
+    assert_eq!(allocator.allocate_frame(), Some(first));
+    // the free list is empty again, so the inner allocator continues where it stopped
+    let third = allocator.allocate_frame().unwrap();
+    assert_eq!(third.start_address().as_u64(), 3 * 4096);
+}
+
+#[test_case]
+fn test_boot_info_frame_allocator() {
+    use bootloader::bootinfo::{FrameRange, MemoryRegion};
+
+    static MEMORY_MAP: Once<MemoryMap> = Once::new();
+    let memory_map = MEMORY_MAP.call_once(|| {
+        let mut map = MemoryMap::new();
+        let regions = [
+            (0x0000, 0x1000, MemoryRegionType::FrameZero),
+            (0x1000, 0x4000, MemoryRegionType::Usable),
+            (0x4000, 0x8000, MemoryRegionType::InUse),
+            (0x8000, 0xa000, MemoryRegionType::Usable),
+        ];
+        for (start, end, region_type) in regions {
+            map.add_region(MemoryRegion {
+                range: FrameRange::new(start, end),
+                region_type,
+            });
+        }
+        map
+    });
+    let mut allocator = unsafe { BootInfoFrameAllocator::init(memory_map) };
+
+    let mut frames = [0; 5];
+    for frame in frames.iter_mut() {
+        *frame = allocator.allocate_frame().unwrap().start_address().as_u64();
+    }
+    assert_eq!(frames, [0x1000, 0x2000, 0x3000, 0x8000, 0x9000]);
+    assert!(frames.iter().all(|frame| frame.is_multiple_of(FRAME_SIZE)));
+    assert_eq!(allocator.allocate_frame(), None);
+    assert_eq!(allocator.allocate_frame(), None);
+}
+
+#[test_case]
+fn test_bitmap_frame_allocator_reuses_frames() {
+    let mut words = [0u64; 2];
+    // frames 16..24 and 60..68, the second region crosses a word boundary
+    let usable = [16..24, 60..68];
+    let mut allocator = BitmapFrameAllocator::with_usable(usable.iter().cloned(), &mut words);
+    assert_eq!(allocator.free_frames(), 16);
+
+    let mut frames = [None; 16];
+    for frame in frames.iter_mut() {
+        *frame = allocator.allocate_frame();
+    }
+    assert!(frames.iter().all(Option::is_some));
+    assert_eq!(frames[0].unwrap().start_address().as_u64(), 16 * FRAME_SIZE);
+    assert_eq!(
+        frames[15].unwrap().start_address().as_u64(),
+        67 * FRAME_SIZE
+    );
+    assert_eq!(allocator.allocate_frame(), None);
+
+    unsafe {
+        allocator.deallocate_frame(frames[12].unwrap());
+        allocator.deallocate_frame(frames[3].unwrap());
+    }
+    assert_eq!(allocator.free_frames(), 2);
+    // lowest first
+    assert_eq!(allocator.allocate_frame(), frames[3]);
+    assert_eq!(allocator.allocate_frame(), frames[12]);
+    assert_eq!(allocator.allocate_frame(), None);
+}
+
+#[test_case]
+fn test_kernel_sections() {
+    let sections = kernel_sections();
+    assert!(sections.text.start < sections.text.end);
+    assert!(sections.rodata.start < sections.rodata.end);
+    assert!(sections.bss.start < sections.bss.end);
+    assert!(sections.text.end <= sections.bss.start);
+
+    // a function and a string literal have to be where we think they are
+    let function = VirtAddr::from_ptr(kernel_sections as *const ());
+    let string = VirtAddr::from_ptr("kernel sections".as_ptr());
+    assert!(sections.text.contains(&function));
+    assert!(sections.rodata.contains(&string));
+}
+
+#[test_case]
+fn test_is_user_addr() {
+    assert!(is_user_addr(VirtAddr::new(USER_SPACE_END / 2)));
+    assert!(is_user_addr(VirtAddr::new(USER_SPACE_END - 1)));
+    assert!(!is_user_addr(VirtAddr::new(KERNEL_SPACE_START)));
+    assert!(!is_user_addr(VirtAddr::new(0xffff_ffff_8000_0000)));
+
+    // wherever the image was linked, its code and data are never user space
+    static VALUE: u64 = 42;
+    assert!(!is_user_addr(VirtAddr::from_ptr(
+        kernel_in_higher_half as *const ()
+    )));
+    assert!(!is_user_addr(VirtAddr::from_ptr(&raw const VALUE)));
+
+    // neither is the rest of what the kernel owns in the lower half
+    let heap = VirtAddr::new(crate::allocator::HEAP_START as u64);
+    assert!(!is_user_addr(heap));
+    let offset = physical_memory_offset().expect("memory::init wasnt called");
+    assert!(!is_user_addr(offset));
+    let stack = 0u64;
+    assert!(!is_user_addr(VirtAddr::from_ptr(&raw const stack)));
+}
+
+#[test_case]
+fn test_translate_addr() {
+    let offset = physical_memory_offset().expect("memory::init wasnt called");
+    let vga = PhysAddr::new(0xb8000);
+    let through_offset = offset + vga.as_u64();
+    assert_eq!(unsafe { translate_addr(through_offset, offset) }, Some(vga));
+    assert_eq!(
+        unsafe { translate_addr(through_offset + 0x123u64, offset) },
+        Some(vga + 0x123u64)
+    );
+    // the bootloader identity maps the vga buffer as well
+    assert_eq!(
+        unsafe { translate_addr(VirtAddr::new(0xb8000), offset) },
+        Some(vga)
+    );
+    assert_eq!(
+        unsafe { translate_addr(VirtAddr::new(0x5ead_b000_0000), offset) },
+        None
+    );
+}
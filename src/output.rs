@@ -0,0 +1,127 @@
+// colored output has to be negotiated per target: the vga text buffer always has colors, but the
+// serial port may be a dumb pipe (ie. a CI log) where ANSI escape sequences only show up as
+// garbage. the color mode decides:
+// Mode	    VGA	    Serial
+// Auto	    yes	    only with the `serial_color` kernel parameter
+// Always	yes	    yes
+// Never	no	    no
+//
+// on serial a color is an SGR (Select Graphic Rendition) sequence, ESC [ n m, where n is 30-37
+// for the normal and 90-97 for the bright colors, and ESC [ 0 m resets it.
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::boot;
+use crate::serial::SERIAL1;
+use crate::vga_buffer::Color;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ColorMode {
+    Auto = 0,
+    Always = 1,
+    Never = 2,
+}
+
+static COLOR_MODE: AtomicU8 = AtomicU8::new(ColorMode::Auto as u8);
+
+pub fn set_color_mode(mode: ColorMode) {
+    COLOR_MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+pub fn color_mode() -> ColorMode {
+    match COLOR_MODE.load(Ordering::Relaxed) {
+        0 => ColorMode::Auto,
+        1 => ColorMode::Always,
+        _ => ColorMode::Never,
+    }
+}
+
+pub fn vga_colors() -> bool {
+    color_mode() != ColorMode::Never
+}
+
+pub fn serial_colors() -> bool {
+    match color_mode() {
+        ColorMode::Auto => boot::args().serial_color,
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+    }
+}
+
+/// the SGR foreground code closest to a vga color
+fn ansi_code(color: Color) -> u8 {
+    // vga has blue in bit 0 and red in bit 2, ANSI the other way around. bit 3 is bright in both
+    let vga = color as u8;
+    let rgb = (vga & 0b010) | (vga & 0b001) << 2 | (vga & 0b100) >> 2;
+    if vga & 0b1000 != 0 {
+        90 + rgb
+    } else {
+        30 + rgb
+    }
+}
+
+/// writes `args` in `color` if the serial color mode allows it, plain otherwise
+pub fn write_colored(out: &mut impl Write, color: Color, args: fmt::Arguments) -> fmt::Result {
+    if !serial_colors() {
+        return out.write_fmt(args);
+    }
+    write!(out, "\x1b[{}m", ansi_code(color))?;
+    out.write_fmt(args)?;
+    out.write_str("\x1b[0m")
+}
+
+#[doc(hidden)]
+pub fn _serial_print_colored(color: Color, args: fmt::Arguments) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        write_colored(&mut *SERIAL1.lock(), color, args).expect("priting to serial failed");
+    });
+}
+
+/// like serial_println, in a vga color, if the color mode allows it
+#[macro_export]
+macro_rules! serial_cprintln {
+    ($color:expr, $($arg:tt)*) => {
+        $crate::output::_serial_print_colored($color, format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+#[cfg(test)]
+struct Captured {
+    bytes: [u8; 64],
+    len: usize,
+}
+
+#[cfg(test)]
+impl Write for Captured {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.bytes
+            .get_mut(self.len..end)
+            .ok_or(fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_never_emits_escapes() {
+    let mut never = Captured {
+        bytes: [0; 64],
+        len: 0,
+    };
+    let mut always = Captured {
+        bytes: [0; 64],
+        len: 0,
+    };
+    set_color_mode(ColorMode::Never);
+    write_colored(&mut never, Color::LightRed, format_args!("error {}", 42)).unwrap();
+    set_color_mode(ColorMode::Always);
+    write_colored(&mut always, Color::LightRed, format_args!("error {}", 42)).unwrap();
+    set_color_mode(ColorMode::Auto);
+
+    assert_eq!(&never.bytes[..never.len], b"error 42");
+    assert_eq!(&always.bytes[..always.len], b"\x1b[91merror 42\x1b[0m");
+}
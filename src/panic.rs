@@ -0,0 +1,107 @@
+// subsystems that buffer output (the log ring, a vga back buffer, a serial tx ring...) would lose
+// whatever is still buffered when the kernel panics. they can register a hook with on_panic,
+// the panic handler runs all of them before printing the message and halting.
+//
+// there is no unwinding (panic = "abort"), so a hook that panics itself cant be caught. instead
+// the nested panic enters the panic handler again, and run_hooks picks up after the hook that
+// failed. every hook runs at most once, so a broken one can't loop forever.
+//
+// ** Printing the Panic
+// print_panic writes the message to the screen (in red) and to serial, so it also ends up in
+// the log of an automated run. the panic may have happened while this cpu held the WRITER or
+// SERIAL1 lock, halfway through a print. nobody is ever going to release them, so they are
+// forced open first. with a single cpu there is no one else who could be using them, the worst
+// case is the interrupted line getting mixed into the panic message. (another cpu printing at
+// the same time would be a real race, which has to be solved once there is SMP)
+
+use core::fmt;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::serial::SERIAL1;
+use crate::vga_buffer::{Color, WRITER};
+
+pub const MAX_PANIC_HOOKS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookError {
+    /// all MAX_PANIC_HOOKS slots are taken
+    Full,
+}
+
+struct Hooks {
+    hooks: [Option<fn()>; MAX_PANIC_HOOKS],
+    len: usize,
+}
+
+static HOOKS: Mutex<Hooks> = Mutex::new(Hooks {
+    hooks: [None; MAX_PANIC_HOOKS],
+    len: 0,
+});
+/// index of the next hook to run, shared by nested panics
+static NEXT_HOOK: AtomicUsize = AtomicUsize::new(0);
+
+/// registers `hook` to run when the kernel panics, in registration order
+pub fn on_panic(hook: fn()) -> Result<(), HookError> {
+    let mut hooks = HOOKS.lock();
+    if hooks.len == MAX_PANIC_HOOKS {
+        return Err(HookError::Full);
+    }
+    let len = hooks.len;
+    hooks.hooks[len] = Some(hook);
+    hooks.len += 1;
+    Ok(())
+}
+
+/// runs the hooks that havent run yet, called by the panic handlers
+pub fn run_hooks() {
+    // the panic may have happened while on_panic held the lock, dont wait for it
+    let Some(hooks) = HOOKS.try_lock().map(|hooks| hooks.hooks) else {
+        return;
+    };
+    loop {
+        let i = NEXT_HOOK.fetch_add(1, Ordering::SeqCst);
+        match hooks.get(i).copied().flatten() {
+            Some(hook) => hook(),
+            None => break,
+        }
+    }
+}
+
+/// prints `info` to vga and serial, even if the panic happened in the middle of a print
+pub fn print_panic(info: &PanicInfo) {
+    print_fatal(info);
+}
+
+fn print_fatal(message: &dyn fmt::Display) {
+    // no interrupt handler may take the locks back while we print, we are not returning anyway
+    interrupts::disable();
+    unsafe {
+        WRITER.force_unlock();
+        SERIAL1.force_unlock();
+    }
+    crate::serial_println!("{}", message);
+    crate::cprintln!(Color::Red, Color::Black, "{}", message);
+}
+
+#[test_case]
+fn test_print_fatal_with_locks_held() {
+    let was_enabled = interrupts::are_enabled();
+    // as if the panic had hit in the middle of a print. an interrupt handler printing now would
+    // spin forever
+    interrupts::disable();
+    core::mem::forget(WRITER.lock());
+    core::mem::forget(SERIAL1.lock());
+    print_fatal(&"fatal message");
+    if was_enabled {
+        interrupts::enable();
+    }
+
+    let writer = WRITER.try_lock().expect("WRITER is still locked");
+    let found = (0..crate::vga_buffer::BUFFER_HEIGHT)
+        .any(|row| writer.read_string_row(row).starts_with(b"fatal message"));
+    assert!(found);
+    assert!(SERIAL1.try_lock().is_some());
+}
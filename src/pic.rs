@@ -0,0 +1,66 @@
+// ** 8259 PIC (Programmable Interrupt Controller)
+// the legacy interrupt controller: two chained 8259s with 8 IRQ lines each. the secondary one
+// is connected to line 2 of the primary:
+//                      ____________                          ____________
+// Real Time Clock --> |            |   Timer -------------> |            |
+// ACPI -------------> |            |   Keyboard-----------> |            |      _____
+// Available --------> | Secondary  |----------------------> | Primary    |     |     |
+// Available --------> | Interrupt  |   Serial Port 2 -----> | Interrupt  |---> | CPU |
+// Mouse ------------> | Controller |   Serial Port 1 -----> | Controller |     |_____|
+// Co-Processor -----> |            |   Parallel Port 2/3 -> |            |
+// Primary ATA ------> |            |   Floppy disk -------> |            |
+// Secondary ATA ----> |____________|   Parallel Port 1----> |____________|
+//
+// by default the PICs send IRQ 0-15 as vectors 0-15, which are the cpu exceptions (IRQ 0, the
+// timer, would look like a divide error). so they are remapped to the first free vectors:
+// 32-39 for the primary and 40-47 for the secondary.
+// every handled interrupt has to be acknowledged with an EOI (end of interrupt) command,
+// otherwise the PIC doesnt send the next one. handlers go through interrupts::eoi for that,
+// which knows whether the PIC or the local apic is delivering the irqs.
+
+use pic8259::ChainedPics;
+use spin::Mutex;
+
+pub const PIC_1_OFFSET: u8 = 32;
+pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+
+pub static PICS: Mutex<ChainedPics> =
+    Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+
+/// the vectors the PIC lines end up on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum InterruptIndex {
+    Timer = PIC_1_OFFSET,
+    Keyboard,
+}
+
+impl InterruptIndex {
+    pub const fn as_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub fn as_usize(self) -> usize {
+        usize::from(self.as_u8())
+    }
+
+    /// the PIC line, 0-15
+    pub const fn irq(self) -> u8 {
+        self.as_u8() - PIC_1_OFFSET
+    }
+}
+
+/// remaps the PICs to PIC_1_OFFSET and PIC_2_OFFSET. interrupts stay disabled, they are only
+/// turned on once the boot is done (see interrupts::defer_enable)
+pub fn init_pics() {
+    unsafe { PICS.lock().initialize() };
+}
+
+#[test_case]
+fn test_interrupt_index() {
+    assert_eq!(InterruptIndex::Timer.as_u8(), 32);
+    assert_eq!(InterruptIndex::Keyboard.as_u8(), 33);
+    assert_eq!(InterruptIndex::Timer.irq(), 0);
+    assert_eq!(InterruptIndex::Keyboard.irq(), 1);
+    assert_eq!(InterruptIndex::Keyboard.as_usize(), 33);
+}
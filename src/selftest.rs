@@ -0,0 +1,140 @@
+// quick sanity checks of the hardware we rely on, run at boot when the `selftest` kernel
+// parameter is set. every check reports pass/fail over serial. failing checks only warn,
+// unless they are marked critical, in which case there is no point in booting further.
+
+use crate::serial::{self, SERIAL1};
+use crate::{serial_println, vga_buffer};
+
+pub const MAX_CHECKS: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Check {
+    pub name: &'static str,
+    pub critical: bool,
+    pub run: fn() -> bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub passed: bool,
+    pub critical: bool,
+}
+
+#[derive(Debug)]
+pub struct SelfTestReport {
+    results: [Option<CheckResult>; MAX_CHECKS],
+    len: usize,
+}
+
+impl SelfTestReport {
+    pub fn results(&self) -> impl Iterator<Item = &CheckResult> {
+        self.results[..self.len].iter().flatten()
+    }
+
+    pub fn all_passed(&self) -> bool {
+        self.results().all(|result| result.passed)
+    }
+
+    pub fn critical_failure(&self) -> bool {
+        self.results()
+            .any(|result| result.critical && !result.passed)
+    }
+}
+
+/// the pit and heap round-trip checks will join these once the kernel has a timer and a heap
+const CHECKS: [Check; 2] = [
+    Check {
+        name: "serial loopback",
+        critical: false,
+        run: serial_loopback,
+    },
+    Check {
+        name: "vga read-back",
+        critical: true,
+        run: vga_read_back,
+    },
+];
+
+pub fn run() -> SelfTestReport {
+    run_checks(&CHECKS)
+}
+
+fn run_checks(checks: &[Check]) -> SelfTestReport {
+    let mut report = SelfTestReport {
+        results: [None; MAX_CHECKS],
+        len: 0,
+    };
+    for check in checks.iter().take(MAX_CHECKS) {
+        let passed = (check.run)();
+        serial_println!(
+            "selftest: {}...\t{}",
+            check.name,
+            if passed { "[ok]" } else { "[failed]" }
+        );
+        report.results[report.len] = Some(CheckResult {
+            name: check.name,
+            passed,
+            critical: check.critical,
+        });
+        report.len += 1;
+    }
+    report
+}
+
+fn serial_loopback() -> bool {
+    // keep everyone else off the port while it talks to itself
+    let _serial = SERIAL1.lock();
+    serial::loopback_round_trip(0xAE)
+}
+
+fn vga_read_back() -> bool {
+    vga_buffer::offscreen_round_trip(0x5A5A)
+}
+
+#[cfg(test)]
+fn pass() -> bool {
+    true
+}
+
+#[cfg(test)]
+fn fail() -> bool {
+    false
+}
+
+#[test_case]
+fn test_report_reflects_checks() {
+    let report = run_checks(&[
+        Check {
+            name: "mock ok",
+            critical: true,
+            run: pass,
+        },
+        Check {
+            name: "mock broken",
+            critical: false,
+            run: fail,
+        },
+    ]);
+    let mut results = report.results();
+    assert_eq!(
+        results.next(),
+        Some(&CheckResult {
+            name: "mock ok",
+            passed: true,
+            critical: true
+        })
+    );
+    assert_eq!(
+        results.next(),
+        Some(&CheckResult {
+            name: "mock broken",
+            passed: false,
+            critical: false
+        })
+    );
+    assert_eq!(results.next(), None);
+    assert!(!report.all_passed());
+    // the only failing check isnt critical
+    assert!(!report.critical_failure());
+}
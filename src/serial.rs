@@ -1,26 +1,569 @@
+// ** Ports
+// the PC has room for 4 serial ports at fixed io addresses, only the first two are common:
+// Port	Base	QEMU flag
+// COM1	0x3F8	the first -serial, ie. -serial stdio
+// COM2	0x2F8	the second -serial, ie. -serial file:trace.log
+// COM1 carries the kernel (and test) output. COM2 is free for a second stream like debug
+// tracing (serial2_print!), or for the link to another kernel (see link.rs), but not both at
+// once. each port is initialized the first time it is used, so a missing COM2 doesnt get in the
+// way of COM1 and the other way around.
+
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
 use lazy_static::lazy_static;
 use spin::Mutex;
 use uart_16550::SerialPort;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+use crate::{cpu, serial_println};
+
+const COM1_BASE: u16 = 0x3F8;
+pub(crate) const COM2_BASE: u16 = 0x2F8;
 
 lazy_static! {
-    pub static ref SERIAL1: Mutex<SerialPort> = {
-        // this method will need the address of the first io port
-        // of the UART as an argument. it will then calculate the rest of needed
-        // ports from this address
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
-        serial_port.init();
-        Mutex::new(serial_port)
+    pub static ref SERIAL1: Mutex<SerialPort> = open_port(COM1_BASE);
+    pub static ref SERIAL2: Mutex<SerialPort> = open_port(COM2_BASE);
+}
+
+fn open_port(base: u16) -> Mutex<SerialPort> {
+    // this method will need the address of the first io port
+    // of the UART as an argument. it will then calculate the rest of needed
+    // ports from this address
+    let mut serial_port = unsafe { SerialPort::new(base) };
+    serial_port.init();
+    Mutex::new(serial_port)
+}
+
+// ** Line Control Register (LCR), base + 3
+// uart_16550 initializes the port as 8N1 (8 data bits, no parity, 1 stop bit).
+// the frame format is set through the LCR:
+// Bits	Name	        Description
+// 0-1	Data bits	    00: 5, 01: 6, 10: 7, 11: 8
+// 2	Stop bits	    0: 1 stop bit, 1: 1.5 stop bits with 5 data bits, 2 stop bits otherwise
+// 3-5	Parity	        000: none, 001: odd, 011: even, 101: mark, 111: space
+// 6	Break enable
+// 7	DLAB	        Divisor Latch Access Bit, switches base+0/base+1 to the baud divisor
+const LINE_CONTROL_OFFSET: u16 = 3;
+const LCR_BREAK: u8 = 1 << 6;
+const LCR_DLAB: u8 = 1 << 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum DataBits {
+    Five = 0b00,
+    Six = 0b01,
+    Seven = 0b10,
+    Eight = 0b11,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Parity {
+    None = 0b000,
+    Odd = 0b001,
+    Even = 0b011,
+    Mark = 0b101,
+    Space = 0b111,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    /// only valid with 5 data bits
+    OnePointFive,
+    /// not valid with 5 data bits
+    Two,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataFormatError {
+    /// the stop bits cant be used with the requested word length
+    InvalidStopBits,
+}
+
+/// encodes a frame format as the lower 6 bits of the LCR
+fn line_control_bits(
+    bits: DataBits,
+    parity: Parity,
+    stop: StopBits,
+) -> Result<u8, DataFormatError> {
+    // the same stop bit means 1.5 stop bits for 5 bit words and 2 stop bits for the rest
+    let stop_bit = match (stop, bits) {
+        (StopBits::One, _) => 0,
+        (StopBits::OnePointFive, DataBits::Five) => 1,
+        (StopBits::Two, DataBits::Five) | (StopBits::OnePointFive, _) => {
+            return Err(DataFormatError::InvalidStopBits);
+        }
+        (StopBits::Two, _) => 1,
+    };
+    Ok((parity as u8) << 3 | stop_bit << 2 | bits as u8)
+}
+
+/// sets the word length, parity and stop bits of COM1 (ie. 7E1 instead of 8N1).
+/// the DLAB bit is preserved so this can be called in the middle of a baud rate change, and so
+/// is the break bit, a break that is being sent goes on
+pub fn set_data_format(
+    bits: DataBits,
+    parity: Parity,
+    stop: StopBits,
+) -> Result<(), DataFormatError> {
+    let format = line_control_bits(bits, parity, stop)?;
+    // holding the lock makes sure nobody is halfway through sending a byte
+    let _serial = SERIAL1.lock();
+    let mut lcr: Port<u8> = Port::new(COM1_BASE + LINE_CONTROL_OFFSET);
+    unsafe {
+        let kept = lcr.read() & (LCR_DLAB | LCR_BREAK);
+        lcr.write(kept | format);
+    }
+    let mut config = CONFIG.lock();
+    config.data_bits = bits;
+    config.parity = parity;
+    config.stop_bits = stop;
+    Ok(())
+}
+
+// ** Modem Control Register (MCR), base + 4
+// bit 4 puts the UART in loopback mode: whatever is sent comes right back into the receive
+// buffer instead of going out on the wire. bit 0 of the Line Status Register (base + 5)
+// tells us when a received byte is waiting in the data register.
+const MODEM_CONTROL_OFFSET: u16 = 4;
+const LINE_STATUS_OFFSET: u16 = 5;
+const MCR_LOOPBACK: u8 = 1 << 4;
+const LSR_DATA_READY: u8 = 1;
+
+/// sends `byte` to COM1 in loopback mode and checks that it comes back.
+/// the caller has to hold the SERIAL1 lock
+pub(crate) fn loopback_round_trip(byte: u8) -> bool {
+    let mut data: Port<u8> = Port::new(COM1_BASE);
+    let mut mcr: Port<u8> = Port::new(COM1_BASE + MODEM_CONTROL_OFFSET);
+    unsafe {
+        let saved_mcr = mcr.read();
+        mcr.write(saved_mcr | MCR_LOOPBACK);
+        data.write(byte);
+        // the byte should be back almost immediately, dont wait forever on broken hardware
+        let mut received = None;
+        for _ in 0..100_000 {
+            if read_line_status() & LSR_DATA_READY != 0 {
+                received = Some(data.read());
+                RX_BYTES.fetch_add(1, Ordering::Relaxed);
+                break;
+            }
+            cpu::spin_hint();
+        }
+        mcr.write(saved_mcr);
+        received == Some(byte)
+    }
+}
+
+// ** Runtime Reconfiguration
+// changing the baud rate or the frame format while a byte is still being shifted out corrupts
+// that byte (and confuses the other side). so before touching anything we wait until the
+// transmitter is completely empty: bit 6 of the LSR is set once both the transmit FIFO and the
+// shift register are empty (bit 5 only says the FIFO has room).
+// the baud rate is 115200 / divisor, the divisor is written to base+0 (low byte) and base+1
+// (high byte) while DLAB is set. base+1 is the Interrupt Enable Register (IER) otherwise, which
+// is turned off while reconfiguring so the port cant raise an interrupt halfway through.
+// the `serial_baud` kernel parameter picks the baud rate at boot, see init_with_baud.
+const INTERRUPT_ENABLE_OFFSET: u16 = 1;
+const LSR_TRANSMITTER_EMPTY: u8 = 1 << 6;
+
+/// divisors of common baud rates
+pub const BAUD_115200: u16 = 1;
+pub const BAUD_57600: u16 = 2;
+pub const BAUD_38400: u16 = 3;
+pub const BAUD_19200: u16 = 6;
+pub const BAUD_9600: u16 = 12;
+
+/// the divisor for `baud`, None if 115200 isnt a multiple of it
+pub fn divisor_for(baud: u32) -> Option<u16> {
+    if baud == 0 || !115_200u32.is_multiple_of(baud) {
+        return None;
+    }
+    u16::try_from(115_200 / baud).ok()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialConfig {
+    /// baud rate = 115200 / divisor
+    pub divisor: u16,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+/// what uart_16550 sets up: 38400 baud 8N1
+static CONFIG: Mutex<SerialConfig> = Mutex::new(SerialConfig {
+    divisor: BAUD_38400,
+    data_bits: DataBits::Eight,
+    parity: Parity::None,
+    stop_bits: StopBits::One,
+});
+
+pub fn config() -> SerialConfig {
+    *CONFIG.lock()
+}
+
+/// waits until every byte handed to COM1 has left the wire
+pub fn flush_tx() {
+    let _serial = SERIAL1.lock();
+    wait_transmitter_empty();
+}
+
+fn wait_transmitter_empty() {
+    while read_line_status() & LSR_TRANSMITTER_EMPTY == 0 {
+        cpu::spin_hint();
+    }
+}
+
+/// drains the pending output, then applies the changes `f` makes to the current config in
+/// one go. nothing is changed if the resulting frame format is invalid. `f` runs with COM1
+/// locked, so it must not print to serial
+pub fn reconfigure(f: impl FnOnce(&mut SerialConfig)) -> Result<(), DataFormatError> {
+    // no one can send while we hold the lock, and no interrupt handler can try to print
+    interrupts::without_interrupts(|| {
+        let _serial = SERIAL1.lock();
+        // read under the lock, or a reconfigure in between would be undone by ours
+        let mut config = config();
+        f(&mut config);
+        let format = line_control_bits(config.data_bits, config.parity, config.stop_bits)?;
+        wait_transmitter_empty();
+        let mut data: Port<u8> = Port::new(COM1_BASE);
+        let mut ier: Port<u8> = Port::new(COM1_BASE + INTERRUPT_ENABLE_OFFSET);
+        let mut lcr: Port<u8> = Port::new(COM1_BASE + LINE_CONTROL_OFFSET);
+        unsafe {
+            let saved_ier = ier.read();
+            ier.write(0);
+            lcr.write(LCR_DLAB);
+            data.write(config.divisor as u8);
+            ier.write((config.divisor >> 8) as u8);
+            // clearing DLAB here gives base+0 and base+1 their normal meaning back
+            lcr.write(format);
+            ier.write(saved_ier);
+        }
+        *CONFIG.lock() = config;
+        Ok(())
+    })
+}
+
+// ** UART Identification
+// the 16550 family grew a FIFO over time, and the only way to know which chip we got is poking
+// at the FIFO Control Register (FCR, base + 2, write only) and looking at what the
+// Interrupt Identification Register (IIR, base + 2, read only) reports back:
+// IIR bits 6-7	    Chip
+// 11	            16550A (working 16 byte FIFO), 16750 if bit 5 is set too (64 byte FIFO)
+// 10	            16550 (FIFO is buggy and must not be used)
+// 00	            no FIFO: 16450 if the scratch register (base + 7) works, 8250 otherwise
+// an absent port floats the bus, so every register reads 0xFF.
+const FIFO_CONTROL_OFFSET: u16 = 2;
+const SCRATCH_OFFSET: u16 = 7;
+/// enable + clear both FIFOs + 64 byte mode (16750) + 14 byte trigger level
+const FCR_PROBE: u8 = 0xE7;
+/// what uart_16550 init writes: enable + clear both FIFOs + 14 byte trigger level
+const FCR_DEFAULT: u8 = 0xC7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UartChip {
+    NotPresent,
+    Uart8250,
+    Uart16450,
+    Uart16550,
+    Uart16550A,
+    Uart16750,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UartInfo {
+    pub chip: UartChip,
+    /// 0 if there is no usable FIFO
+    pub fifo_depth: usize,
+}
+
+fn classify_uart(iir: u8, scratch_works: bool) -> UartInfo {
+    let (chip, fifo_depth) = if iir == 0xFF && !scratch_works {
+        (UartChip::NotPresent, 0)
+    } else {
+        match iir >> 6 {
+            0b11 if iir & (1 << 5) != 0 => (UartChip::Uart16750, 64),
+            0b11 => (UartChip::Uart16550A, 16),
+            0b10 => (UartChip::Uart16550, 0),
+            _ if scratch_works => (UartChip::Uart16450, 0),
+            _ => (UartChip::Uart8250, 0),
+        }
+    };
+    UartInfo { chip, fifo_depth }
+}
+
+/// figures out which UART is behind COM1 and how deep its FIFO is
+pub fn probe_uart() -> UartInfo {
+    let _serial = SERIAL1.lock();
+    probe_port(COM1_BASE)
+}
+
+/// the caller has to hold the lock of the port at `base`
+fn probe_port(base: u16) -> UartInfo {
+    let mut fcr: Port<u8> = Port::new(base + FIFO_CONTROL_OFFSET);
+    let mut iir: Port<u8> = Port::new(base + FIFO_CONTROL_OFFSET);
+    let mut scratch: Port<u8> = Port::new(base + SCRATCH_OFFSET);
+    let mut lsr: Port<u8> = Port::new(base + LINE_STATUS_OFFSET);
+    unsafe {
+        // both FCR writes clear the transmit FIFO, whatever is still queued there would be lost.
+        // an absent port reads 0xFF, which has the bit set
+        while lsr.read() & LSR_TRANSMITTER_EMPTY == 0 {
+            cpu::spin_hint();
+        }
+        fcr.write(FCR_PROBE);
+        let iir_value = iir.read();
+        fcr.write(FCR_DEFAULT);
+
+        let saved = scratch.read();
+        scratch.write(0x5A);
+        let scratch_works = scratch.read() == 0x5A;
+        scratch.write(saved);
+
+        classify_uart(iir_value, scratch_works)
+    }
+}
+
+// ** RS-485 Direction Control
+// RS-485 is half duplex: the transceiver either drives the bus (DE, driver enable) or listens to
+// it (RE, receiver enable), and something has to switch between the two around every
+// transmission. usually that is a GPIO or one of the MCR lines (RTS), which depends on the board,
+// so the switching is left to a callback.
+// the direction must not flip back before the stop bit of the last byte is on the wire, otherwise
+// that byte is cut off. LSR bit 6 (transmitter empty) is only set once the shift register is
+// empty too, so we wait for it before telling the callback to go back to receiving.
+static DIRECTION_CONTROL: Mutex<Option<fn(bool)>> = Mutex::new(None);
+
+/// `f(true)` is called before COM1 starts sending and `f(false)` once the last bit left.
+/// it runs with the SERIAL1 lock held, so it must not print to serial
+pub fn set_direction_control(f: fn(tx: bool)) {
+    *DIRECTION_CONTROL.lock() = Some(f);
+}
+
+pub fn clear_direction_control() {
+    *DIRECTION_CONTROL.lock() = None;
+}
+
+// ** Statistics
+// besides the data ready and transmitter empty bits, the LSR reports receive errors. they are
+// cleared by reading the register:
+// Bit	Error
+// 1	Overrun, a byte arrived before the previous one was read and got lost
+// 2	Parity, the parity bit didnt match
+// 3	Framing, no stop bit where one was expected (usually mismatched baud rates)
+// every LSR read in here goes through read_line_status so the errors get counted. uart_16550
+// also reads the LSR while sending, whatever it sees there is lost.
+const LSR_OVERRUN_ERROR: u8 = 1 << 1;
+const LSR_PARITY_ERROR: u8 = 1 << 2;
+const LSR_FRAMING_ERROR: u8 = 1 << 3;
+/// the throughput is measured over windows of this many timer ticks
+pub const THROUGHPUT_WINDOW_TICKS: u64 = 100;
+
+static TX_BYTES: AtomicU64 = AtomicU64::new(0);
+static RX_BYTES: AtomicU64 = AtomicU64::new(0);
+static OVERRUN_ERRORS: AtomicU64 = AtomicU64::new(0);
+static PARITY_ERRORS: AtomicU64 = AtomicU64::new(0);
+static FRAMING_ERRORS: AtomicU64 = AtomicU64::new(0);
+/// TX_BYTES when the current throughput window started
+static WINDOW_START_TX_BYTES: AtomicU64 = AtomicU64::new(0);
+static RECENT_TX_BYTES: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialStats {
+    pub tx_bytes: u64,
+    pub rx_bytes: u64,
+    pub overrun_errors: u64,
+    pub parity_errors: u64,
+    pub framing_errors: u64,
+    /// bytes sent during the last full throughput window
+    pub recent_tx_bytes: u64,
+}
+
+impl fmt::Display for SerialStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "tx {} bytes, rx {} bytes", self.tx_bytes, self.rx_bytes)?;
+        writeln!(
+            f,
+            "errors: {} overrun, {} parity, {} framing",
+            self.overrun_errors, self.parity_errors, self.framing_errors
+        )?;
+        write!(
+            f,
+            "throughput: {} bytes per {} ticks",
+            self.recent_tx_bytes, THROUGHPUT_WINDOW_TICKS
+        )
+    }
+}
+
+pub fn stats() -> SerialStats {
+    SerialStats {
+        tx_bytes: TX_BYTES.load(Ordering::Relaxed),
+        rx_bytes: RX_BYTES.load(Ordering::Relaxed),
+        overrun_errors: OVERRUN_ERRORS.load(Ordering::Relaxed),
+        parity_errors: PARITY_ERRORS.load(Ordering::Relaxed),
+        framing_errors: FRAMING_ERRORS.load(Ordering::Relaxed),
+        recent_tx_bytes: RECENT_TX_BYTES.load(Ordering::Relaxed),
+    }
+}
+
+/// reads the LSR of COM1, counting the errors it reports
+fn read_line_status() -> u8 {
+    let mut lsr: Port<u8> = Port::new(COM1_BASE + LINE_STATUS_OFFSET);
+    let status = unsafe { lsr.read() };
+    let errors = [
+        (LSR_OVERRUN_ERROR, &OVERRUN_ERRORS),
+        (LSR_PARITY_ERROR, &PARITY_ERRORS),
+        (LSR_FRAMING_ERROR, &FRAMING_ERRORS),
+    ];
+    for (bit, counter) in errors {
+        if status & bit != 0 {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    status
+}
+
+/// timer callback closing a throughput window every THROUGHPUT_WINDOW_TICKS ticks
+fn sample_throughput(tick: u64) {
+    if tick.is_multiple_of(THROUGHPUT_WINDOW_TICKS) {
+        let total = TX_BYTES.load(Ordering::Relaxed);
+        let start = WINDOW_START_TX_BYTES.swap(total, Ordering::Relaxed);
+        RECENT_TX_BYTES.store(total - start, Ordering::Relaxed);
+    }
+}
+
+/// counts what goes through it as transmitted
+struct CountingWriter<'a>(&'a mut SerialPort);
+
+impl fmt::Write for CountingWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_str(s)?;
+        TX_BYTES.fetch_add(s.len() as u64, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// returns the next byte received on COM1, None if nothing is waiting. never blocks.
+/// bytes that are part of the logger's control sequence are handled here and not returned.
+/// echoing whatever the host terminal sends:
+/// ```ignore
+/// loop {
+///     let byte = serial::read_byte();
+///     serial_print!("{}", byte as char);
+/// }
+/// ```
+pub fn try_read_byte() -> Option<u8> {
+    loop {
+        let byte = {
+            // only held for the two port reads, the logger below prints
+            let _serial = SERIAL1.lock();
+            if read_line_status() & LSR_DATA_READY == 0 {
+                return None;
+            }
+            let mut data: Port<u8> = Port::new(COM1_BASE);
+            unsafe { data.read() }
+        };
+        RX_BYTES.fetch_add(1, Ordering::Relaxed);
+        if !crate::logger::handle_serial_byte(byte) {
+            return Some(byte);
+        }
+    }
+}
+
+/// waits for the next byte received on COM1, see try_read_byte
+pub fn read_byte() -> u8 {
+    loop {
+        if let Some(byte) = try_read_byte() {
+            return byte;
+        }
+        cpu::spin_hint();
+    }
+}
+
+/// sends `byte` unless someone else is printing right now. safe to call from interrupt context,
+/// returns whether the byte was sent
+pub fn try_write_byte(byte: u8) -> bool {
+    let Some(mut serial) = SERIAL1.try_lock() else {
+        return false;
     };
+    serial.send(byte);
+    TX_BYTES.fetch_add(1, Ordering::Relaxed);
+    true
+}
+
+/// like init, but COM1 runs at 115200 / `divisor` baud instead of the uart_16550 default.
+/// a divisor of 0 is invalid and keeps the default
+pub fn init_with_baud(divisor: u16) {
+    // uart_16550 programs its own divisor when the port is first used, so ours has to come after
+    lazy_static::initialize(&SERIAL1);
+    if divisor != 0 {
+        set_divisor(divisor);
+    }
+    init();
+}
+
+fn set_divisor(divisor: u16) {
+    // the current frame format is always a valid one
+    reconfigure(|config| config.divisor = divisor).expect("invalid serial data format");
+}
+
+/// brings up COM1 and COM2 and reports which UARTs they are
+pub fn init() {
+    // only fails when every callback slot is taken, the throughput then just stays at 0
+    let _ = crate::interrupts::on_tick(sample_throughput);
+    let com1 = probe_uart();
+    let com2 = {
+        let _serial = SERIAL2.lock();
+        probe_port(COM2_BASE)
+    };
+    if com1.chip == UartChip::NotPresent {
+        // nobody is listening anyway
+        return;
+    }
+    for (name, info) in [("COM1", com1), ("COM2", com2)] {
+        if info.chip != UartChip::NotPresent {
+            serial_println!(
+                "serial: {} is a {:?} with a {} byte FIFO",
+                name,
+                info.chip,
+                info.fifo_depth
+            );
+        }
+    }
 }
 
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
     use core::fmt::Write;
 
-    SERIAL1
-        .lock()
-        .write_fmt(args)
-        .expect("priting to serial failed");
+    // an interrupt handler that prints while we hold the lock would spin on it forever
+    interrupts::without_interrupts(|| {
+        let mut serial = SERIAL1.lock();
+        let direction_control = *DIRECTION_CONTROL.lock();
+        if let Some(set_tx) = direction_control {
+            set_tx(true);
+        }
+        CountingWriter(&mut serial)
+            .write_fmt(args)
+            .expect("priting to serial failed");
+        if let Some(set_tx) = direction_control {
+            wait_transmitter_empty();
+            set_tx(false);
+        }
+    });
+}
+
+#[doc(hidden)]
+pub fn _print2(args: ::core::fmt::Arguments) {
+    use core::fmt::Write;
+
+    interrupts::without_interrupts(|| {
+        SERIAL2
+            .lock()
+            .write_fmt(args)
+            .expect("priting to serial failed");
+    });
 }
 
 #[macro_export]
@@ -42,3 +585,206 @@ macro_rules! serial_println {
         $crate::serial_print!(concat!($fmt,"\n"),$($arg)*);
     }
 }
+
+/// prints to COM2
+#[macro_export]
+macro_rules! serial2_print {
+    ($($arg:tt)*) => {
+        $crate::serial::_print2(format_args!($($arg)*));
+    };
+}
+
+#[macro_export]
+macro_rules! serial2_println {
+    () => {
+        $crate::serial2_print!("\n");
+    };
+    ($fmt:expr) => {
+        $crate::serial2_print!(concat!($fmt, "\n"))
+    };
+    ($fmt:expr, $($arg:tt)*)=>{
+        $crate::serial2_print!(concat!($fmt,"\n"),$($arg)*);
+    }
+}
+
+#[test_case]
+fn test_serial2_print_loopback() {
+    let mut data: Port<u8> = Port::new(COM2_BASE);
+    let mut mcr: Port<u8> = Port::new(COM2_BASE + MODEM_CONTROL_OFFSET);
+    let mut lsr: Port<u8> = Port::new(COM2_BASE + LINE_STATUS_OFFSET);
+
+    lazy_static::initialize(&SERIAL2);
+    let saved_mcr = unsafe { mcr.read() };
+    unsafe { mcr.write(saved_mcr | MCR_LOOPBACK) };
+    crate::serial2_println!("{}2", "com");
+
+    let mut received = [0u8; 5];
+    for byte in received.iter_mut() {
+        for _ in 0..100_000 {
+            if unsafe { lsr.read() } & LSR_DATA_READY != 0 {
+                *byte = unsafe { data.read() };
+                break;
+            }
+            cpu::spin_hint();
+        }
+    }
+    unsafe { mcr.write(saved_mcr) };
+
+    assert_eq!(&received, b"com2\n");
+}
+
+#[test_case]
+fn test_set_data_format() {
+    let mut lcr: Port<u8> = Port::new(COM1_BASE + LINE_CONTROL_OFFSET);
+
+    set_data_format(DataBits::Seven, Parity::Even, StopBits::One).unwrap();
+    let seven_e_one = unsafe { lcr.read() };
+    set_data_format(DataBits::Eight, Parity::None, StopBits::Two).unwrap();
+    let eight_n_two = unsafe { lcr.read() };
+    let invalid = set_data_format(DataBits::Eight, Parity::None, StopBits::OnePointFive);
+    unsafe {
+        let value = lcr.read();
+        lcr.write(value | LCR_BREAK);
+    }
+    set_data_format(DataBits::Seven, Parity::Even, StopBits::One).unwrap();
+    let during_break = unsafe { lcr.read() };
+    unsafe { lcr.write(during_break & !LCR_BREAK) };
+    // back to 8N1 before asserting, otherwise the test output gets garbled
+    set_data_format(DataBits::Eight, Parity::None, StopBits::One).unwrap();
+
+    assert_eq!(seven_e_one, 0b0001_1010);
+    assert_eq!(eight_n_two, 0b0000_0111);
+    assert_eq!(invalid, Err(DataFormatError::InvalidStopBits));
+    assert_eq!(during_break, LCR_BREAK | 0b0001_1010);
+}
+
+#[test_case]
+fn test_reconfigure_keeps_pending_bytes() {
+    let mut data: Port<u8> = Port::new(COM1_BASE);
+    let mut mcr: Port<u8> = Port::new(COM1_BASE + MODEM_CONTROL_OFFSET);
+    let mut lsr: Port<u8> = Port::new(COM1_BASE + LINE_STATUS_OFFSET);
+    let sent = *b"baud";
+
+    let saved_mcr = unsafe { mcr.read() };
+    unsafe {
+        mcr.write(saved_mcr | MCR_LOOPBACK);
+        for byte in sent {
+            data.write(byte);
+        }
+    }
+    reconfigure(|config| config.divisor = 1).unwrap();
+    let fast = config();
+
+    // a byte that got lost would leave us waiting forever, give up after a while like
+    // loopback_round_trip does
+    let mut received = [0u8; 4];
+    for byte in received.iter_mut() {
+        for _ in 0..100_000 {
+            if unsafe { lsr.read() } & LSR_DATA_READY != 0 {
+                *byte = unsafe { data.read() };
+                break;
+            }
+            cpu::spin_hint();
+        }
+    }
+    reconfigure(|config| config.divisor = 3).unwrap();
+    unsafe { mcr.write(saved_mcr) };
+
+    assert_eq!(fast.divisor, 1);
+    assert_eq!(received, sent);
+}
+
+#[test_case]
+fn test_classify_uart() {
+    assert_eq!(classify_uart(0xC1, true).chip, UartChip::Uart16550A);
+    assert_eq!(classify_uart(0xC1, true).fifo_depth, 16);
+    assert_eq!(classify_uart(0xE1, true).chip, UartChip::Uart16750);
+    assert_eq!(classify_uart(0xE1, true).fifo_depth, 64);
+    assert_eq!(classify_uart(0x81, true).chip, UartChip::Uart16550);
+    assert_eq!(classify_uart(0x01, true).chip, UartChip::Uart16450);
+    assert_eq!(classify_uart(0x01, false).chip, UartChip::Uart8250);
+    assert_eq!(classify_uart(0xFF, false).chip, UartChip::NotPresent);
+}
+
+#[test_case]
+fn test_direction_control_order() {
+    use core::sync::atomic::{AtomicU8, Ordering};
+
+    // 1: switched to tx, 2: switched back to rx with the transmitter empty, 3: switched back too early
+    static EVENTS: [AtomicU8; 2] = [AtomicU8::new(0), AtomicU8::new(0)];
+    static COUNT: AtomicU8 = AtomicU8::new(0);
+    fn record(tx: bool) {
+        let event = if tx {
+            1
+        } else {
+            let mut lsr: Port<u8> = Port::new(COM1_BASE + LINE_STATUS_OFFSET);
+            if unsafe { lsr.read() } & LSR_TRANSMITTER_EMPTY != 0 {
+                2
+            } else {
+                3
+            }
+        };
+        let i = COUNT.fetch_add(1, Ordering::SeqCst) as usize;
+        if let Some(slot) = EVENTS.get(i) {
+            slot.store(event, Ordering::SeqCst);
+        }
+    }
+
+    set_direction_control(record);
+    crate::serial_print!(" ");
+    clear_direction_control();
+
+    assert_eq!(COUNT.load(Ordering::SeqCst), 2);
+    assert_eq!(EVENTS[0].load(Ordering::SeqCst), 1);
+    assert_eq!(EVENTS[1].load(Ordering::SeqCst), 2);
+}
+
+#[test_case]
+fn test_stats_count_tx_bytes() {
+    let before = stats().tx_bytes;
+    crate::serial_print!("     ");
+    assert_eq!(stats().tx_bytes, before + 5);
+}
+
+#[test_case]
+fn test_try_read_byte_loopback() {
+    let mut data: Port<u8> = Port::new(COM1_BASE);
+    let mut mcr: Port<u8> = Port::new(COM1_BASE + MODEM_CONTROL_OFFSET);
+
+    let saved_mcr = unsafe { mcr.read() };
+    unsafe {
+        mcr.write(saved_mcr | MCR_LOOPBACK);
+        data.write(b'o');
+        data.write(b'k');
+    }
+    flush_tx();
+    let first = try_read_byte();
+    let second = read_byte();
+    let empty = try_read_byte();
+    unsafe { mcr.write(saved_mcr) };
+
+    assert_eq!(first, Some(b'o'));
+    assert_eq!(second, b'k');
+    assert_eq!(empty, None);
+}
+
+#[test_case]
+fn test_baud_presets() {
+    assert_eq!(divisor_for(115_200), Some(BAUD_115200));
+    assert_eq!(divisor_for(9600), Some(BAUD_9600));
+    assert_eq!(divisor_for(1200), Some(96));
+    assert_eq!(divisor_for(1000), None);
+    assert_eq!(divisor_for(0), None);
+
+    set_divisor(BAUD_9600);
+    let (lcr, round_trip) = {
+        let _serial = SERIAL1.lock();
+        let mut lcr: Port<u8> = Port::new(COM1_BASE + LINE_CONTROL_OFFSET);
+        (unsafe { lcr.read() }, loopback_round_trip(0x5A))
+    };
+    set_divisor(BAUD_38400);
+
+    // DLAB is cleared again, base+0 is the data register and bytes go through
+    assert_eq!(lcr & LCR_DLAB, 0);
+    assert!(round_trip);
+}
@@ -0,0 +1,111 @@
+// interrupts are disabled while an interrupt handler runs (interrupt gates), so a long handler
+// delays every other interrupt. the classic fix is splitting the work in two halves:
+//  1. top half: the handler itself. it only does what cant wait (reading the device,
+//      sending the EOI) and schedules the rest
+//  2. bottom half (softirq): the deferred work, which runs later from the main loop with
+//      interrupts enabled
+//
+// there is no heap, so work items are plain function pointers kept in a fixed size ring buffer.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+pub const QUEUE_SIZE: usize = 32;
+
+struct WorkQueue {
+    items: [Option<fn()>; QUEUE_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl WorkQueue {
+    fn push(&mut self, work: fn()) -> bool {
+        if self.len == QUEUE_SIZE {
+            return false;
+        }
+        self.items[(self.head + self.len) % QUEUE_SIZE] = Some(work);
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<fn()> {
+        if self.len == 0 {
+            return None;
+        }
+        let work = self.items[self.head].take();
+        self.head = (self.head + 1) % QUEUE_SIZE;
+        self.len -= 1;
+        work
+    }
+}
+
+static QUEUE: Mutex<WorkQueue> = Mutex::new(WorkQueue {
+    items: [None; QUEUE_SIZE],
+    head: 0,
+    len: 0,
+});
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// queues `work` to run on the next `run_pending`. safe to call from interrupt handlers.
+/// when the queue is full the work is dropped (and counted) instead of running it inside
+/// the handler, returns false in that case
+pub fn schedule(work: fn()) -> bool {
+    // the queue lock is only ever taken with interrupts disabled, so a handler can never
+    // spin on a lock held by the code it interrupted
+    let queued = interrupts::without_interrupts(|| QUEUE.lock().push(work));
+    if !queued {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+    queued
+}
+
+/// runs all the deferred work, returns how many items ran.
+/// meant for the main loop, each item runs with the interrupt flag as the caller left it
+pub fn run_pending() -> usize {
+    let mut ran = 0;
+    // pop one by one so the lock isnt held while the work runs
+    while let Some(work) = interrupts::without_interrupts(|| QUEUE.lock().pop()) {
+        work();
+        ran += 1;
+    }
+    ran
+}
+
+/// how many work items were dropped because the queue was full
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+static WORK_DONE: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(test)]
+fn count_work() {
+    WORK_DONE.fetch_add(1, Ordering::Relaxed);
+}
+
+#[test_case]
+fn test_deferred_work_runs_later() {
+    WORK_DONE.store(0, Ordering::Relaxed);
+    assert!(schedule(count_work));
+    assert!(schedule(count_work));
+    // nothing runs until the bottom half is processed
+    assert_eq!(WORK_DONE.load(Ordering::Relaxed), 0);
+    assert_eq!(run_pending(), 2);
+    assert_eq!(WORK_DONE.load(Ordering::Relaxed), 2);
+    assert_eq!(run_pending(), 0);
+}
+
+#[test_case]
+fn test_full_queue_drops_work() {
+    WORK_DONE.store(0, Ordering::Relaxed);
+    let dropped_before = dropped();
+    for _ in 0..QUEUE_SIZE {
+        assert!(schedule(count_work));
+    }
+    assert!(!schedule(count_work));
+    assert_eq!(dropped(), dropped_before + 1);
+    assert_eq!(run_pending(), QUEUE_SIZE);
+    assert_eq!(WORK_DONE.load(Ordering::Relaxed), QUEUE_SIZE as u64);
+}
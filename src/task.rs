@@ -0,0 +1,57 @@
+// ** Async Tasks
+// a task is a future the kernel runs to completion, ie. an `async fn` that waits for keyboard
+// input. an executor polls its tasks: a task that cant go on yet returns Poll::Pending and is
+// polled again later, so a single thread of execution can switch between many tasks at their
+// await points (cooperative multitasking, a task that never awaits never gives the cpu back).
+// the futures are moved to the heap, the executor only needs to know they produce `()`.
+// Pin promises they wont move anymore, which is what lets an async fn keep references into
+// its own state across awaits.
+//
+// ** Wakers
+// polling a task that is still waiting is wasted work. when a future returns Pending it keeps
+// the Waker from its Context, and whatever it waits for (ie. the keyboard interrupt) calls
+// wake once it can go on. Executor only polls tasks that were woken and halts the cpu while
+// there are none, SimpleExecutor ignores wakeups and polls everything over and over.
+
+pub mod executor;
+pub mod keyboard;
+pub mod simple_executor;
+
+use alloc::boxed::Box;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll};
+
+/// unique for every task ever created
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    fn new() -> TaskId {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+pub struct Task {
+    id: TaskId,
+    future: Pin<Box<dyn Future<Output = ()>>>,
+}
+
+impl Task {
+    pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
+        Task {
+            id: TaskId::new(),
+            future: Box::pin(future),
+        }
+    }
+
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    fn poll(&mut self, context: &mut Context) -> Poll<()> {
+        self.future.as_mut().poll(context)
+    }
+}
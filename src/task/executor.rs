@@ -0,0 +1,157 @@
+// keeps the tasks in a map and the ids of the woken ones in a queue:
+//  tasks:       {0: Task, 1: Task, 2: Task}
+//  task_queue:  [2, 0]      <- pushed by TaskWaker::wake, possibly from an interrupt handler
+// only the tasks in the queue are polled. the queue is lock-free and fixed size, so waking
+// never allocates or blocks, which is what an interrupt handler needs.
+//
+// when the queue is empty the cpu halts until the next interrupt. checking the queue and halting
+// has to happen with interrupts disabled: an interrupt waking a task right after the check
+// would otherwise go unnoticed and we would sleep with a task ready. enable_and_hlt enables
+// them and halts in one go (sti only takes effect after the next instruction), so a pending
+// interrupt still wakes the hlt.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use core::task::{Context, Poll, Waker};
+use crossbeam_queue::ArrayQueue;
+use x86_64::instructions::interrupts;
+
+use super::{Task, TaskId};
+
+/// how many woken tasks can wait to be polled
+pub const TASK_QUEUE_SIZE: usize = 100;
+
+pub struct Executor {
+    tasks: BTreeMap<TaskId, Task>,
+    task_queue: Arc<ArrayQueue<TaskId>>,
+    /// one waker per task, so polling doesnt allocate a new one every time
+    waker_cache: BTreeMap<TaskId, Waker>,
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Executor {
+    pub fn new() -> Executor {
+        Executor {
+            tasks: BTreeMap::new(),
+            task_queue: Arc::new(ArrayQueue::new(TASK_QUEUE_SIZE)),
+            waker_cache: BTreeMap::new(),
+        }
+    }
+
+    /// adds `task`, it is polled for the first time on the next round
+    pub fn spawn(&mut self, task: Task) {
+        let id = task.id;
+        if self.tasks.insert(id, task).is_some() {
+            panic!("task with the same id already spawned");
+        }
+        self.task_queue.push(id).expect("task queue full");
+    }
+
+    /// runs the tasks forever, halting while none of them is ready
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.run_ready_tasks();
+            self.sleep_if_idle();
+        }
+    }
+
+    /// polls every woken task once
+    fn run_ready_tasks(&mut self) {
+        while let Some(id) = self.task_queue.pop() {
+            // woken after it completed already
+            let Some(task) = self.tasks.get_mut(&id) else {
+                continue;
+            };
+            let waker = self
+                .waker_cache
+                .entry(id)
+                .or_insert_with(|| TaskWaker::waker(id, self.task_queue.clone()));
+            let mut context = Context::from_waker(waker);
+            match task.poll(&mut context) {
+                Poll::Ready(()) => {
+                    self.tasks.remove(&id);
+                    self.waker_cache.remove(&id);
+                }
+                Poll::Pending => {}
+            }
+        }
+    }
+
+    fn sleep_if_idle(&self) {
+        interrupts::disable();
+        if self.task_queue.is_empty() {
+            interrupts::enable_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+/// wakes a task by queueing its id
+struct TaskWaker {
+    id: TaskId,
+    task_queue: Arc<ArrayQueue<TaskId>>,
+}
+
+impl TaskWaker {
+    fn waker(id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
+        Waker::from(Arc::new(TaskWaker { id, task_queue }))
+    }
+
+    fn wake_task(&self) {
+        // a full queue means the executor is hopelessly behind, dont let the waker block
+        if self.task_queue.push(self.id).is_err() {
+            crate::serial_println!("executor: task queue full, dropping a wakeup");
+        }
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
+
+#[test_case]
+fn test_executor_polls_woken_tasks() {
+    use core::future::poll_fn;
+    use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use spin::Mutex;
+
+    static READY: AtomicBool = AtomicBool::new(false);
+    static POLLS: AtomicU32 = AtomicU32::new(0);
+    static WAKER: Mutex<Option<Waker>> = Mutex::new(None);
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(poll_fn(|context| {
+        POLLS.fetch_add(1, Ordering::SeqCst);
+        if READY.load(Ordering::SeqCst) {
+            return Poll::Ready(());
+        }
+        *WAKER.lock() = Some(context.waker().clone());
+        Poll::Pending
+    })));
+
+    executor.run_ready_tasks();
+    assert_eq!(POLLS.load(Ordering::SeqCst), 1);
+    // nothing woke it, so it isnt polled again
+    executor.run_ready_tasks();
+    assert_eq!(POLLS.load(Ordering::SeqCst), 1);
+
+    READY.store(true, Ordering::SeqCst);
+    WAKER.lock().take().unwrap().wake();
+    executor.run_ready_tasks();
+    assert_eq!(POLLS.load(Ordering::SeqCst), 2);
+    assert!(executor.tasks.is_empty());
+    assert!(executor.waker_cache.is_empty());
+}
@@ -0,0 +1,118 @@
+// the scancodes queued by the keyboard interrupt as a Stream, so a task can
+// `while let Some(scancode) = scancodes.next().await` and sleep until a key is pressed.
+// the handler wakes the registered waker after every scancode it queues (see
+// crate::keyboard). there is only one queue, so there can only be one stream at a time.
+
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll};
+use futures_util::stream::{Stream, StreamExt};
+use pc_keyboard::{DecodedKey, KeyCode};
+
+use crate::{keyboard, print, vga_buffer};
+
+static STREAM_TAKEN: AtomicBool = AtomicBool::new(false);
+
+pub struct ScancodeStream {
+    _private: (),
+}
+
+impl ScancodeStream {
+    /// panics if another ScancodeStream is alive, they would steal each other's scancodes
+    pub fn new() -> ScancodeStream {
+        assert!(
+            !STREAM_TAKEN.swap(true, Ordering::SeqCst),
+            "ScancodeStream::new called while another one exists"
+        );
+        ScancodeStream { _private: () }
+    }
+}
+
+impl Default for ScancodeStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for ScancodeStream {
+    fn drop(&mut self) {
+        STREAM_TAKEN.store(false, Ordering::SeqCst);
+    }
+}
+
+impl Stream for ScancodeStream {
+    type Item = u8;
+
+    /// never ends, Ready(None) is never returned
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<u8>> {
+        // fast path, no need to register anything
+        if let Some(scancode) = keyboard::pop_scancode() {
+            return Poll::Ready(Some(scancode));
+        }
+        // registering first and checking again afterwards means a scancode queued in between
+        // is either seen here or wakes the new waker
+        keyboard::register_waker(context.waker());
+        match keyboard::pop_scancode() {
+            Some(scancode) => Poll::Ready(Some(scancode)),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// prints the characters typed on the keyboard, forever. the other keys scroll the memview
+/// viewer if one is open
+pub async fn print_keypresses() {
+    let mut scancodes = ScancodeStream::new();
+    while let Some(scancode) = scancodes.next().await {
+        match keyboard::decode(scancode) {
+            // the layouts decode escape to its ascii code
+            Some(DecodedKey::Unicode('\u{1b}')) => {
+                vga_buffer::memview_key(KeyCode::Escape);
+            }
+            Some(DecodedKey::Unicode(c)) => print!("{}", c),
+            Some(DecodedKey::RawKey(key)) => {
+                vga_buffer::memview_key(key);
+            }
+            None => {}
+        }
+    }
+}
+
+#[test_case]
+fn test_scancode_stream_wakes_on_scancode() {
+    use alloc::sync::Arc;
+    use alloc::task::Wake;
+    use core::sync::atomic::AtomicUsize;
+    use core::task::Waker;
+
+    struct CountingWaker(AtomicUsize);
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+    let waker = Waker::from(counter.clone());
+    let mut context = Context::from_waker(&waker);
+    let mut stream = ScancodeStream::new();
+
+    assert_eq!(stream.poll_next_unpin(&mut context), Poll::Pending);
+    // what the interrupt handler does for the A key being pressed and released
+    keyboard::add_scancode(0x1E);
+    keyboard::add_scancode(0x9E);
+    assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+    assert_eq!(
+        stream.poll_next_unpin(&mut context),
+        Poll::Ready(Some(0x1E))
+    );
+    assert_eq!(
+        stream.poll_next_unpin(&mut context),
+        Poll::Ready(Some(0x9E))
+    );
+    assert_eq!(stream.poll_next_unpin(&mut context), Poll::Pending);
+
+    // and the decoding print_keypresses does
+    assert_eq!(keyboard::decode(0x1E), Some(DecodedKey::Unicode('a')));
+    assert_eq!(keyboard::decode(0x9E), None);
+}
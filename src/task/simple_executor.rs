@@ -0,0 +1,98 @@
+// polls every task in turn until all of them are done. nothing ever wakes a task, the waker is
+// a dummy that does nothing: a pending task just goes to the back of the queue and is polled
+// again on the next round, whether it can make progress or not. simple, but it keeps the cpu
+// busy while every task is waiting.
+
+use alloc::collections::VecDeque;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+use super::Task;
+
+pub struct SimpleExecutor {
+    task_queue: VecDeque<Task>,
+}
+
+impl Default for SimpleExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimpleExecutor {
+    pub fn new() -> SimpleExecutor {
+        SimpleExecutor {
+            task_queue: VecDeque::new(),
+        }
+    }
+
+    pub fn spawn(&mut self, task: Task) {
+        self.task_queue.push_back(task)
+    }
+
+    /// returns once every task has completed
+    pub fn run(&mut self) {
+        let waker = dummy_waker();
+        let mut context = Context::from_waker(&waker);
+        while let Some(mut task) = self.task_queue.pop_front() {
+            match task.poll(&mut context) {
+                Poll::Ready(()) => {}
+                Poll::Pending => self.task_queue.push_back(task),
+            }
+        }
+    }
+}
+
+fn dummy_raw_waker() -> RawWaker {
+    fn no_op(_: *const ()) {}
+    fn clone(_: *const ()) -> RawWaker {
+        dummy_raw_waker()
+    }
+
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, no_op, no_op, no_op);
+    RawWaker::new(core::ptr::null(), &VTABLE)
+}
+
+fn dummy_waker() -> Waker {
+    // the vtable functions dont touch the data pointer, so any pointer is fine
+    unsafe { Waker::from_raw(dummy_raw_waker()) }
+}
+
+#[cfg(test)]
+/// pending on the first poll, ready on the second
+struct YieldOnce(bool);
+
+#[cfg(test)]
+impl core::future::Future for YieldOnce {
+    type Output = ();
+
+    fn poll(mut self: core::pin::Pin<&mut Self>, _context: &mut Context) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        Poll::Pending
+    }
+}
+
+#[test_case]
+fn test_simple_executor_runs_all_tasks() {
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    static FIRST: AtomicU32 = AtomicU32::new(0);
+    static SECOND: AtomicU32 = AtomicU32::new(0);
+
+    async fn count(counter: &'static AtomicU32) {
+        counter.fetch_add(1, Ordering::SeqCst);
+        YieldOnce(false).await;
+        counter.fetch_add(1, Ordering::SeqCst);
+    }
+
+    let mut executor = SimpleExecutor::new();
+    executor.spawn(Task::new(count(&FIRST)));
+    executor.spawn(Task::new(count(&SECOND)));
+    executor.run();
+
+    assert_eq!(FIRST.load(Ordering::SeqCst), 2);
+    assert_eq!(SECOND.load(Ordering::SeqCst), 2);
+    assert!(executor.task_queue.is_empty());
+}
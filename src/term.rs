@@ -0,0 +1,247 @@
+// decodes what a terminal sends over serial into keys.
+//
+// printable keys arrive as their utf-8 bytes, everything else is either a control byte or an
+// escape sequence starting with ESC (0x1b):
+// Sequence	        Key
+// ESC [ A/B/C/D	    Up/Down/Right/Left
+// ESC [ H, ESC O H	Home
+// ESC [ F, ESC O F	End
+// ESC O P/Q/R/S	    F1-F4
+// ESC [ n ~	        n = 1/7: Home, 2: Insert, 3: Delete, 4/8: End, 5: PageUp, 6: PageDown,
+//                  11-15: F1-F5, 17-21: F6-F10, 23-24: F11-F12,
+//                  200/201: start/end of a bracketed paste
+// modifiers come as extra parameters (ESC [ 1 ; 5 C is ctrl+right), they are ignored.
+//
+// a sequence can be split across reads, so the decoder is a state machine fed one byte at a
+// time that only returns a key once the sequence is complete.
+
+const ESC: u8 = 0x1b;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TermKey {
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+    /// F1 to F12
+    Function(u8),
+    PasteStart,
+    PasteEnd,
+    /// a control byte or escape sequence we dont know
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    /// got ESC
+    Escape,
+    /// got ESC [, `param` is the first parameter so far, `extra` is set after a ';'
+    Csi {
+        param: u16,
+        extra: bool,
+    },
+    /// got ESC O
+    Ss3,
+    /// in the middle of a utf-8 char, `needed` more continuation bytes to go
+    Utf8 {
+        value: u32,
+        needed: u8,
+    },
+}
+
+pub struct Decoder {
+    state: State,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder {
+    pub const fn new() -> Decoder {
+        Decoder {
+            state: State::Ground,
+        }
+    }
+
+    /// feeds one received byte, returns the key once a whole one has arrived
+    pub fn feed(&mut self, byte: u8) -> Option<TermKey> {
+        match self.state {
+            State::Ground => self.ground(byte),
+            State::Escape => {
+                self.state = match byte {
+                    b'[' => State::Csi {
+                        param: 0,
+                        extra: false,
+                    },
+                    b'O' => State::Ss3,
+                    _ => {
+                        self.state = State::Ground;
+                        return Some(TermKey::Unknown);
+                    }
+                };
+                None
+            }
+            State::Csi { param, extra } => match byte {
+                b'0'..=b'9' if !extra => {
+                    let digit = (byte - b'0') as u16;
+                    self.state = State::Csi {
+                        param: param.saturating_mul(10).saturating_add(digit),
+                        extra,
+                    };
+                    None
+                }
+                b'0'..=b'9' | b';' => {
+                    self.state = State::Csi { param, extra: true };
+                    None
+                }
+                // final byte of the sequence
+                0x40..=0x7e => {
+                    self.state = State::Ground;
+                    Some(csi_key(param, byte))
+                }
+                _ => {
+                    self.state = State::Ground;
+                    Some(TermKey::Unknown)
+                }
+            },
+            State::Ss3 => {
+                self.state = State::Ground;
+                Some(match byte {
+                    b'P'..=b'S' => TermKey::Function(byte - b'P' + 1),
+                    b'H' => TermKey::Home,
+                    b'F' => TermKey::End,
+                    _ => TermKey::Unknown,
+                })
+            }
+            State::Utf8 { value, needed } => {
+                if byte & 0xC0 != 0x80 {
+                    // not a continuation byte, drop the broken char and start over
+                    self.state = State::Ground;
+                    return self.ground(byte);
+                }
+                let value = value << 6 | (byte & 0x3F) as u32;
+                if needed > 1 {
+                    self.state = State::Utf8 {
+                        value,
+                        needed: needed - 1,
+                    };
+                    return None;
+                }
+                self.state = State::Ground;
+                Some(char::from_u32(value).map_or(TermKey::Unknown, TermKey::Char))
+            }
+        }
+    }
+
+    fn ground(&mut self, byte: u8) -> Option<TermKey> {
+        match byte {
+            ESC => {
+                self.state = State::Escape;
+                None
+            }
+            b'\r' | b'\n' => Some(TermKey::Enter),
+            b'\t' => Some(TermKey::Tab),
+            0x08 | 0x7f => Some(TermKey::Backspace),
+            0x20..=0x7e => Some(TermKey::Char(byte as char)),
+            // utf-8 lead bytes: 110xxxxx, 1110xxxx, 11110xxx
+            0xC0..=0xDF => self.start_utf8(byte & 0x1F, 1),
+            0xE0..=0xEF => self.start_utf8(byte & 0x0F, 2),
+            0xF0..=0xF7 => self.start_utf8(byte & 0x07, 3),
+            _ => Some(TermKey::Unknown),
+        }
+    }
+
+    fn start_utf8(&mut self, bits: u8, needed: u8) -> Option<TermKey> {
+        self.state = State::Utf8 {
+            value: bits as u32,
+            needed,
+        };
+        None
+    }
+}
+
+fn csi_key(param: u16, final_byte: u8) -> TermKey {
+    match (final_byte, param) {
+        (b'A', _) => TermKey::Up,
+        (b'B', _) => TermKey::Down,
+        (b'C', _) => TermKey::Right,
+        (b'D', _) => TermKey::Left,
+        (b'H', _) => TermKey::Home,
+        (b'F', _) => TermKey::End,
+        (b'~', 1 | 7) => TermKey::Home,
+        (b'~', 2) => TermKey::Insert,
+        (b'~', 3) => TermKey::Delete,
+        (b'~', 4 | 8) => TermKey::End,
+        (b'~', 5) => TermKey::PageUp,
+        (b'~', 6) => TermKey::PageDown,
+        (b'~', 11..=15) => TermKey::Function((param - 10) as u8),
+        (b'~', 17..=21) => TermKey::Function((param - 11) as u8),
+        (b'~', 23..=24) => TermKey::Function((param - 12) as u8),
+        (b'~', 200) => TermKey::PasteStart,
+        (b'~', 201) => TermKey::PasteEnd,
+        _ => TermKey::Unknown,
+    }
+}
+
+#[cfg(test)]
+fn decode(bytes: &[u8]) -> Option<TermKey> {
+    let mut decoder = Decoder::new();
+    let mut key = None;
+    for (i, &byte) in bytes.iter().enumerate() {
+        key = decoder.feed(byte);
+        // nothing may come out before the last byte
+        if i + 1 < bytes.len() {
+            assert_eq!(key, None);
+        }
+    }
+    key
+}
+
+#[test_case]
+fn test_decode_keys() {
+    assert_eq!(decode(b"a"), Some(TermKey::Char('a')));
+    assert_eq!(decode("°".as_bytes()), Some(TermKey::Char('°')));
+    assert_eq!(decode(b"\r"), Some(TermKey::Enter));
+    assert_eq!(decode(b"\x7f"), Some(TermKey::Backspace));
+    assert_eq!(decode(b"\x1b[A"), Some(TermKey::Up));
+    assert_eq!(decode(b"\x1b[B"), Some(TermKey::Down));
+    assert_eq!(decode(b"\x1b[1;5C"), Some(TermKey::Right));
+    assert_eq!(decode(b"\x1b[D"), Some(TermKey::Left));
+    assert_eq!(decode(b"\x1bOH"), Some(TermKey::Home));
+    assert_eq!(decode(b"\x1b[4~"), Some(TermKey::End));
+    assert_eq!(decode(b"\x1b[2~"), Some(TermKey::Insert));
+    assert_eq!(decode(b"\x1b[3~"), Some(TermKey::Delete));
+    assert_eq!(decode(b"\x1b[5~"), Some(TermKey::PageUp));
+    assert_eq!(decode(b"\x1b[6~"), Some(TermKey::PageDown));
+    assert_eq!(decode(b"\x1bOP"), Some(TermKey::Function(1)));
+    assert_eq!(decode(b"\x1b[15~"), Some(TermKey::Function(5)));
+    assert_eq!(decode(b"\x1b[24~"), Some(TermKey::Function(12)));
+    assert_eq!(decode(b"\x1b[200~"), Some(TermKey::PasteStart));
+    assert_eq!(decode(b"\x1b[201~"), Some(TermKey::PasteEnd));
+    assert_eq!(decode(b"\x1b[99~"), Some(TermKey::Unknown));
+}
+
+#[test_case]
+fn test_decode_split_sequence() {
+    let mut decoder = Decoder::new();
+    // as if "\x1b[3" and "~x" came in two separate reads
+    assert_eq!(decoder.feed(0x1b), None);
+    assert_eq!(decoder.feed(b'['), None);
+    assert_eq!(decoder.feed(b'3'), None);
+    assert_eq!(decoder.feed(b'~'), Some(TermKey::Delete));
+    assert_eq!(decoder.feed(b'x'), Some(TermKey::Char('x')));
+}
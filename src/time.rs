@@ -0,0 +1,377 @@
+// ** PIT (Programmable Interval Timer, 8253/8254)
+// the PIT counts down from a reload value at 1.193182 MHz and raises IRQ 0 every time it hits
+// zero, so the tick rate is 1193182 / reload. the reload value is 16 bits, 0 means 65536, which
+// is what the BIOS leaves behind: about 18.2 ticks a second.
+// Port	Description
+// 0x40	channel 0 data, the reload value is written low byte then high byte
+// 0x43	mode/command register
+// the command byte we use, 0x36:
+// Bits	Value	Meaning
+// 6-7	00	    channel 0
+// 4-5	11	    access mode lobyte/hibyte
+// 1-3	011	    mode 3, square wave generator
+// 0	0	    16 bit binary counting
+//
+// set_frequency takes any rate and clamps the reload value to 1..=65535. set_tick_hz is the
+// checked front end to it: it only accepts the rates that make sense for a kernel tick and
+// reports the rest as an error.
+//
+// uptime is counted in ticks, so changing the rate would change what a tick is worth. the clock
+// remembers the uptime and the tick count at the last change and only converts the ticks since
+// then with the current reload value.
+//
+// ** Drift Check
+// the PIT's ticks and the tsc (see cpu::rdtsc) are two independent clocks. check_drift runs both
+// over the same window and reports how far apart they end up. the tsc only counts cycles, they
+// are turned into time with the rate measured against the PIT the first time it is needed
+// (tsc_hz), so a bad estimate shows up as drift, and so do ticks that got lost, ie. because
+// interrupts were disabled for longer than a tick. both ends of the window are right after a
+// tick, where the PIT's uptime is exact.
+
+use spin::{Mutex, Once};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+use crate::boot::LogLevel;
+use crate::cpu;
+use crate::interrupts::ticks;
+
+/// the frequency of the PIT's input clock
+pub const PIT_FREQUENCY: u32 = 1_193_182;
+/// slowest and fastest tick rate set_tick_hz accepts
+pub const MIN_TICK_HZ: u32 = 18;
+pub const MAX_TICK_HZ: u32 = 1000;
+
+const PIT_CHANNEL0_PORT: u16 = 0x40;
+const PIT_COMMAND_PORT: u16 = 0x43;
+const PIT_COMMAND_SQUARE_WAVE: u8 = 0x36;
+/// the largest reload value, written as 0
+const MAX_RELOAD: u32 = 0x10000;
+/// the largest reload value set_frequency programs
+const MAX_DIVISOR: u32 = 0xFFFF;
+
+/// how long check_drift compares the clocks for
+pub const DRIFT_WINDOW_MS: u64 = 100;
+/// drift above this, 1% of the window, is logged as a warning
+pub const DRIFT_WARN_US: u64 = 1000;
+/// how long the tsc rate is measured for
+const TSC_CALIBRATION_MS: u64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TickRateError {
+    /// outside MIN_TICK_HZ..=MAX_TICK_HZ
+    OutOfRange(u32),
+}
+
+struct Clock {
+    reload: u32,
+    /// the tick count and uptime when the reload value was last changed
+    base_ticks: u64,
+    base_us: u64,
+}
+
+impl Clock {
+    fn uptime_us(&self, ticks: u64) -> u64 {
+        let elapsed = ticks - self.base_ticks;
+        self.base_us + elapsed * self.reload as u64 * 1_000_000 / PIT_FREQUENCY as u64
+    }
+}
+
+static CLOCK: Mutex<Clock> = Mutex::new(Clock {
+    reload: MAX_RELOAD,
+    base_ticks: 0,
+    base_us: 0,
+});
+
+/// reprograms the PIT to tick `hz` times a second. 18 Hz is really the BIOS rate of 18.2 Hz,
+/// anything slower doesnt fit the 16 bit reload value
+pub fn set_tick_hz(hz: u32) -> Result<(), TickRateError> {
+    if !(MIN_TICK_HZ..=MAX_TICK_HZ).contains(&hz) {
+        return Err(TickRateError::OutOfRange(hz));
+    }
+    set_frequency(hz);
+    Ok(())
+}
+
+/// reprograms the PIT to tick as close to `hz` times a second as it can and returns the rate
+/// it really ticks at. rates the reload value cant express are clamped to the slowest (18 Hz)
+/// or fastest (1193182 Hz) one. a fast rate leaves the cpu no time for anything but the
+/// timer interrupt
+pub fn set_frequency(hz: u32) -> u32 {
+    let divisor = pit_divisor(hz);
+    load_reload(divisor);
+    PIT_FREQUENCY / divisor
+}
+
+/// the reload value for `hz`, in 1..=MAX_DIVISOR
+fn pit_divisor(hz: u32) -> u32 {
+    (PIT_FREQUENCY / hz.max(1)).clamp(1, MAX_DIVISOR)
+}
+
+fn load_reload(reload: u32) {
+    // a tick between rebasing the clock and loading the new value would be counted at the
+    // wrong rate, and one between the two data bytes would see a half written reload value
+    interrupts::without_interrupts(|| {
+        let mut clock = CLOCK.lock();
+        let now = ticks();
+        clock.base_us = clock.uptime_us(now);
+        clock.base_ticks = now;
+        clock.reload = reload;
+
+        let mut command: Port<u8> = Port::new(PIT_COMMAND_PORT);
+        let mut data: Port<u8> = Port::new(PIT_CHANNEL0_PORT);
+        unsafe {
+            command.write(PIT_COMMAND_SQUARE_WAVE);
+            data.write(reload as u8);
+            data.write((reload >> 8) as u8);
+        }
+    });
+}
+
+/// the current tick rate, rounded down
+pub fn tick_hz() -> u32 {
+    // timer callbacks read the clock too
+    interrupts::without_interrupts(|| PIT_FREQUENCY / CLOCK.lock().reload)
+}
+
+/// milliseconds since the timer started ticking
+pub fn uptime_ms() -> u64 {
+    uptime_us() / 1000
+}
+
+/// microseconds since the timer started ticking. it still only moves once a tick
+pub fn uptime_us() -> u64 {
+    interrupts::without_interrupts(|| CLOCK.lock().uptime_us(ticks()))
+}
+
+/// waits until `ms` milliseconds of uptime have passed, halting the cpu in between. uptime moves
+/// a tick at a time, so the real time slept can be up to one tick shorter or longer.
+/// interrupts have to be enabled, otherwise no tick would ever end the wait
+pub fn sleep_ms(ms: u64) {
+    assert!(
+        interrupts::are_enabled(),
+        "sleep_ms with interrupts disabled would never wake up"
+    );
+    let end = uptime_ms() + ms;
+    while uptime_ms() < end {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// the two clocks the drift check compares, the tests feed it made up ones
+trait ClockSources {
+    /// uptime by the PIT, in microseconds
+    fn pit_us(&mut self) -> u64;
+    fn tsc(&mut self) -> u64;
+    /// returns right after the next tick
+    fn wait_for_tick(&mut self);
+}
+
+struct HardwareClocks;
+
+impl ClockSources for HardwareClocks {
+    fn pit_us(&mut self) -> u64 {
+        uptime_us()
+    }
+
+    fn tsc(&mut self) -> u64 {
+        cpu::rdtsc()
+    }
+
+    fn wait_for_tick(&mut self) {
+        let start = ticks();
+        while ticks() == start {
+            x86_64::instructions::hlt();
+        }
+    }
+}
+
+/// the PIT microseconds and tsc cycles that pass over at least `ms`, from one tick to another
+fn measure(clocks: &mut impl ClockSources, ms: u64) -> (u64, u64) {
+    clocks.wait_for_tick();
+    let (pit_start, tsc_start) = (clocks.pit_us(), clocks.tsc());
+    let end = pit_start + ms * 1000;
+    while clocks.pit_us() < end {
+        clocks.wait_for_tick();
+    }
+    (clocks.pit_us() - pit_start, clocks.tsc() - tsc_start)
+}
+
+/// how far the PIT got ahead of the tsc over `ms`, in microseconds. negative if it fell behind
+fn drift_us(clocks: &mut impl ClockSources, tsc_hz: u64, ms: u64) -> i64 {
+    let (pit_us, cycles) = measure(clocks, ms);
+    let tsc_us = cycles * 1_000_000 / tsc_hz.max(1);
+    pit_us as i64 - tsc_us as i64
+}
+
+static TSC_HZ: Once<u64> = Once::new();
+
+/// the tsc's rate in cycles per second, measured against the PIT the first time it is asked
+/// for. interrupts have to be enabled
+pub fn tsc_hz() -> u64 {
+    *TSC_HZ.call_once(|| {
+        let (pit_us, cycles) = measure(&mut HardwareClocks, TSC_CALIBRATION_MS);
+        cycles * 1_000_000 / pit_us.max(1)
+    })
+}
+
+/// compares the PIT and the tsc over DRIFT_WINDOW_MS and returns how far the PIT got ahead, in
+/// microseconds (negative if it fell behind). more than DRIFT_WARN_US either way is logged as
+/// a warning. interrupts have to be enabled, otherwise the PIT never moves
+pub fn check_drift() -> i64 {
+    assert!(
+        interrupts::are_enabled(),
+        "check_drift with interrupts disabled would never see a tick"
+    );
+    let drift = drift_us(&mut HardwareClocks, tsc_hz(), DRIFT_WINDOW_MS);
+    if drift.unsigned_abs() > DRIFT_WARN_US {
+        crate::log!(
+            LogLevel::Warn,
+            "time: PIT and tsc drifted {}us apart over {}ms",
+            drift,
+            DRIFT_WINDOW_MS
+        );
+    }
+    drift
+}
+
+#[test_case]
+fn test_sleep_ms() {
+    let start = uptime_ms();
+    sleep_ms(50);
+    assert!(uptime_ms() - start >= 50);
+}
+
+#[test_case]
+fn test_uptime_survives_rate_change() {
+    use crate::interrupts::timer_tick;
+
+    let previous = tick_hz();
+    assert_eq!(set_tick_hz(5), Err(TickRateError::OutOfRange(5)));
+    assert_eq!(set_tick_hz(2000), Err(TickRateError::OutOfRange(2000)));
+
+    // a real timer interrupt in between would throw off the tick counts below
+    interrupts::without_interrupts(|| {
+        set_tick_hz(100).unwrap();
+        let start = uptime_ms();
+        for _ in 0..10 {
+            timer_tick();
+        }
+        let slow = uptime_ms();
+        // 10 ticks at 100 Hz, give or take the rounding of the reload value
+        assert!((99..=101).contains(&(slow - start)));
+
+        set_tick_hz(1000).unwrap();
+        assert!(uptime_ms() >= slow);
+        for _ in 0..10 {
+            timer_tick();
+        }
+        let fast = uptime_ms();
+        assert!((9..=11).contains(&(fast - slow)));
+
+        set_tick_hz(previous.max(MIN_TICK_HZ)).unwrap();
+        assert!(uptime_ms() >= fast);
+    });
+}
+
+#[test_case]
+fn test_pit_divisor_clamps() {
+    assert_eq!(pit_divisor(1000), 1193);
+    assert_eq!(pit_divisor(0), MAX_DIVISOR);
+    assert_eq!(pit_divisor(1), MAX_DIVISOR);
+    assert_eq!(pit_divisor(PIT_FREQUENCY), 1);
+    assert_eq!(pit_divisor(u32::MAX), 1);
+}
+
+/// the tsc cycles `count` timer ticks take, starting at a tick boundary
+#[cfg(test)]
+fn tsc_cycles_for(count: u64) -> u64 {
+    HardwareClocks.wait_for_tick();
+    let start = cpu::rdtsc();
+    for _ in 0..count {
+        HardwareClocks.wait_for_tick();
+    }
+    cpu::rdtsc() - start
+}
+
+#[test_case]
+fn test_set_frequency_1000_hz() {
+    assert!(interrupts::are_enabled());
+    let previous = tick_hz();
+
+    // the tsc is the independent clock: 10 ticks at 100 Hz and 100 ticks at 1000 Hz should
+    // both take 100ms
+    assert_eq!(set_frequency(100), 100);
+    assert_eq!(tick_hz(), 100);
+    let slow = tsc_cycles_for(10);
+    assert_eq!(set_frequency(1000), 1000);
+    assert_eq!(tick_hz(), 1000);
+    let fast = tsc_cycles_for(100);
+    set_tick_hz(previous.max(MIN_TICK_HZ)).unwrap();
+
+    // qemu without kvm falls behind at 1000 Hz and delivers ticks late or merged, so this only
+    // catches a rate that is off by a whole factor (ie. the reload value for the wrong rate)
+    let ratio = fast * 100 / slow;
+    assert!(
+        (33..=300).contains(&ratio),
+        "1000 Hz ran at {}% of the expected rate",
+        ratio
+    );
+}
+
+/// ticks every `tick_us` while the tsc runs `cycles_per_tick` ahead
+#[cfg(test)]
+struct MockClocks {
+    pit_us: u64,
+    tsc: u64,
+    tick_us: u64,
+    cycles_per_tick: u64,
+}
+
+#[cfg(test)]
+impl ClockSources for MockClocks {
+    fn pit_us(&mut self) -> u64 {
+        self.pit_us
+    }
+
+    fn tsc(&mut self) -> u64 {
+        self.tsc
+    }
+
+    fn wait_for_tick(&mut self) {
+        self.pit_us += self.tick_us;
+        self.tsc += self.cycles_per_tick;
+    }
+}
+
+#[test_case]
+fn test_drift_with_mocked_clocks() {
+    // a 100 MHz tsc and a 100 Hz tick agree
+    let mut clocks = MockClocks {
+        pit_us: 5_000,
+        tsc: 123,
+        tick_us: 10_000,
+        cycles_per_tick: 1_000_000,
+    };
+    assert_eq!(drift_us(&mut clocks, 100_000_000, 100), 0);
+
+    // the tsc really runs 1% faster than estimated: 100ms by the PIT are 101ms by the tsc
+    clocks.cycles_per_tick = 1_010_000;
+    assert_eq!(drift_us(&mut clocks, 100_000_000, 100), -1000);
+
+    // a lost tick every other one: the PIT only sees half of the time go by
+    clocks.cycles_per_tick = 2_000_000;
+    assert_eq!(drift_us(&mut clocks, 100_000_000, 100), -100_000);
+}
+
+#[test_case]
+fn test_check_drift() {
+    assert!(tsc_hz() > 0);
+    // qemu doesnt deliver every tick on time, only a drift of half the window is a real problem
+    let drift = check_drift();
+    assert!(
+        drift.unsigned_abs() < DRIFT_WINDOW_MS * 1000 / 2,
+        "PIT and tsc drifted {}us apart",
+        drift
+    );
+}
@@ -0,0 +1,457 @@
+// small helpers that dont belong to a subsystem. there is no heap, so everything in here works
+// on fixed size buffers.
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+/// a string in a fixed buffer. writing more than fits keeps what fits (cut at a char boundary)
+/// and returns an error
+#[derive(Clone, Copy)]
+pub struct FixedString<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> FixedString<N> {
+    pub const fn new() -> FixedString<N> {
+        FixedString {
+            bytes: [0; N],
+            len: 0,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // only whole chars are ever copied in
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+impl<const N: usize> Default for FixedString<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Write for FixedString<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = N - self.len;
+        let mut take = s.len().min(room);
+        while !s.is_char_boundary(take) {
+            take -= 1;
+        }
+        self.bytes[self.len..self.len + take].copy_from_slice(&s.as_bytes()[..take]);
+        self.len += take;
+        if take < s.len() {
+            Err(fmt::Error)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<const N: usize> fmt::Display for FixedString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// ** Tables
+// diagnostic dumps (mtrrs, serial stats...) are tables, and lining up their columns by hand gets
+// old quickly. Table keeps the formatted cells and works out the column widths when displayed:
+//      name   count
+//      -----  -----
+//      timer   1234
+//      kbd        7
+// cells longer than TABLE_CELL_LEN are cut, rows past MAX_TABLE_ROWS are dropped.
+pub const TABLE_CELL_LEN: usize = 32;
+pub const MAX_TABLE_ROWS: usize = 16;
+const COLUMN_GAP: &str = "  ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+type Row<const COLS: usize> = [FixedString<TABLE_CELL_LEN>; COLS];
+
+pub struct Table<const COLS: usize> {
+    align: [Align; COLS],
+    header: Option<Row<COLS>>,
+    rows: [Row<COLS>; MAX_TABLE_ROWS],
+    len: usize,
+}
+
+impl<const COLS: usize> Table<COLS> {
+    pub fn new(align: [Align; COLS]) -> Table<COLS> {
+        Table {
+            align,
+            header: None,
+            rows: [[FixedString::new(); COLS]; MAX_TABLE_ROWS],
+            len: 0,
+        }
+    }
+
+    fn format_row(cells: [&dyn fmt::Display; COLS]) -> Row<COLS> {
+        let mut row = [FixedString::new(); COLS];
+        for (cell, value) in row.iter_mut().zip(cells) {
+            // a cell that doesnt fit is cut, thats fine for a table
+            let _ = write!(cell, "{}", value);
+        }
+        row
+    }
+
+    pub fn header(&mut self, cells: [&dyn fmt::Display; COLS]) -> &mut Self {
+        self.header = Some(Self::format_row(cells));
+        self
+    }
+
+    /// adds a row, returns false if the table is full
+    pub fn row(&mut self, cells: [&dyn fmt::Display; COLS]) -> bool {
+        if self.len == MAX_TABLE_ROWS {
+            return false;
+        }
+        self.rows[self.len] = Self::format_row(cells);
+        self.len += 1;
+        true
+    }
+
+    fn widths(&self) -> [usize; COLS] {
+        let mut widths = [0; COLS];
+        for row in self.header.iter().chain(&self.rows[..self.len]) {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.as_str().chars().count());
+            }
+        }
+        widths
+    }
+
+    fn write_row(
+        &self,
+        f: &mut fmt::Formatter,
+        row: &Row<COLS>,
+        widths: &[usize; COLS],
+    ) -> fmt::Result {
+        for (i, cell) in row.iter().enumerate() {
+            if i > 0 {
+                f.write_str(COLUMN_GAP)?;
+            }
+            let last = i + 1 == COLS;
+            match self.align[i] {
+                // no trailing spaces at the end of the line
+                Align::Left if last => write!(f, "{}", cell)?,
+                Align::Left => write!(f, "{:<1$}", cell.as_str(), widths[i])?,
+                Align::Right => write!(f, "{:>1$}", cell.as_str(), widths[i])?,
+            }
+        }
+        writeln!(f)
+    }
+}
+
+impl<const COLS: usize> fmt::Display for Table<COLS> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let widths = self.widths();
+        if let Some(header) = &self.header {
+            self.write_row(f, header, &widths)?;
+            for (i, width) in widths.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(COLUMN_GAP)?;
+                }
+                for _ in 0..*width {
+                    f.write_char('-')?;
+                }
+            }
+            writeln!(f)?;
+        }
+        for row in &self.rows[..self.len] {
+            self.write_row(f, row, &widths)?;
+        }
+        Ok(())
+    }
+}
+
+/// builds a Table: the alignment of each column, the header, then the rows. print it with
+/// println!/serial_println!
+///
+/// table![
+///     [Align::Left, Align::Right],
+///     ["name", "count"],
+///     ["timer", ticks],
+/// ]
+#[macro_export]
+macro_rules! table {
+    ([$($align:expr),+ $(,)?], [$($header:expr),+ $(,)?] $(, [$($cell:expr),+ $(,)?])* $(,)?) => {{
+        let mut table = $crate::util::Table::new([$($align),+]);
+        table.header([$(&$header as &dyn core::fmt::Display),+]);
+        $(table.row([$(&$cell as &dyn core::fmt::Display),+]);)*
+        table
+    }};
+}
+
+// ** BitMap
+// one bit per frame/slot/id, 1 means taken. the bits live in a slice of u64 words the caller
+// owns (ie. a static array), bit i is bit i % 64 of word i / 64. the bitmap can be shorter than
+// the words hold, the bits past `len` in the last word are never handed out.
+const WORD_BITS: usize = u64::BITS as usize;
+
+pub struct BitMap<'a> {
+    words: &'a mut [u64],
+    len: usize,
+}
+
+impl<'a> BitMap<'a> {
+    /// a bitmap of `len` bits stored in `words`, which must hold at least that many
+    pub fn new(words: &'a mut [u64], len: usize) -> BitMap<'a> {
+        assert!(
+            len <= words.len() * WORD_BITS,
+            "bitmap doesnt fit its words"
+        );
+        BitMap { words, len }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, index: usize) -> bool {
+        assert!(index < self.len, "bit {} out of range", index);
+        self.words[index / WORD_BITS] & (1 << (index % WORD_BITS)) != 0
+    }
+
+    pub fn set(&mut self, index: usize) {
+        assert!(index < self.len, "bit {} out of range", index);
+        self.words[index / WORD_BITS] |= 1 << (index % WORD_BITS);
+    }
+
+    pub fn clear(&mut self, index: usize) {
+        assert!(index < self.len, "bit {} out of range", index);
+        self.words[index / WORD_BITS] &= !(1 << (index % WORD_BITS));
+    }
+
+    /// the lowest clear bit
+    pub fn find_first_zero(&self) -> Option<usize> {
+        // full words are skipped whole
+        let (word, bits) = self
+            .words
+            .iter()
+            .enumerate()
+            .find(|(_, bits)| **bits != u64::MAX)?;
+        let index = word * WORD_BITS + bits.trailing_ones() as usize;
+        // the zero may be one of the unused bits at the end of the last word
+        (index < self.len).then_some(index)
+    }
+
+    /// the start of the lowest run of `n` clear bits
+    pub fn find_n_contiguous_zeros(&self, n: usize) -> Option<usize> {
+        if n == 0 {
+            return Some(0);
+        }
+        let mut start = 0;
+        let mut run = 0;
+        let mut index = 0;
+        while index < self.len {
+            let word = self.words[index / WORD_BITS];
+            // a full word ends any run, a clear one extends it by 64 at once
+            if index.is_multiple_of(WORD_BITS) && index + WORD_BITS <= self.len {
+                if word == u64::MAX {
+                    run = 0;
+                    index += WORD_BITS;
+                    continue;
+                }
+                if word == 0 {
+                    if run == 0 {
+                        start = index;
+                    }
+                    run += WORD_BITS;
+                    if run >= n {
+                        return Some(start);
+                    }
+                    index += WORD_BITS;
+                    continue;
+                }
+            }
+            if word & (1 << (index % WORD_BITS)) != 0 {
+                run = 0;
+            } else {
+                if run == 0 {
+                    start = index;
+                }
+                run += 1;
+                if run >= n {
+                    return Some(start);
+                }
+            }
+            index += 1;
+        }
+        None
+    }
+}
+
+// ** ByteQueue
+// a ring of bytes that an interrupt handler can push into while normal code pops, without a lock
+// (a lock the handler spins on while the interrupted code holds it never gets released). head
+// and tail only ever count up, the slot of a position is position % N.
+// when the queue is full the producer drops the oldest byte by moving head itself, so both sides
+// move head with compare_exchange: a consumer whose byte was dropped (or overwritten) under it
+// sees head move and tries again.
+
+pub struct ByteQueue<const N: usize> {
+    slots: [AtomicU8; N],
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+impl<const N: usize> Default for ByteQueue<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> ByteQueue<N> {
+    pub const fn new() -> ByteQueue<N> {
+        ByteQueue {
+            slots: [const { AtomicU8::new(0) }; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// adds `byte`, dropping the oldest one if the queue is full. returns false if it did.
+    /// only one producer may push at a time
+    pub fn push(&self, byte: u8) -> bool {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let mut kept = true;
+        loop {
+            let head = self.head.load(Ordering::Acquire);
+            if tail - head < N {
+                break;
+            }
+            if self
+                .head
+                .compare_exchange(head, head + 1, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+            {
+                kept = false;
+                break;
+            }
+        }
+        self.slots[tail % N].store(byte, Ordering::Relaxed);
+        self.tail.store(tail + 1, Ordering::Release);
+        kept
+    }
+
+    /// takes the oldest byte
+    pub fn pop(&self) -> Option<u8> {
+        loop {
+            let head = self.head.load(Ordering::Acquire);
+            if head == self.tail.load(Ordering::Acquire) {
+                return None;
+            }
+            let byte = self.slots[head % N].load(Ordering::Relaxed);
+            if self
+                .head
+                .compare_exchange(head, head + 1, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+            {
+                return Some(byte);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tail.load(Ordering::Acquire) - self.head.load(Ordering::Acquire)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[test_case]
+fn test_table_alignment() {
+    let table = crate::table![
+        [Align::Left, Align::Right],
+        ["name", "count"],
+        ["timer", 1234],
+        ["kbd", 7],
+    ];
+    let mut out = FixedString::<128>::new();
+    write!(out, "{}", table).unwrap();
+    assert_eq!(
+        out.as_str(),
+        "name   count\n-----  -----\ntimer   1234\nkbd        7\n"
+    );
+}
+
+#[test_case]
+fn test_fixed_string_truncates() {
+    let mut s = FixedString::<4>::new();
+    assert!(write!(s, "ab°c").is_err());
+    // the ° takes 2 bytes, c doesnt fit anymore
+    assert_eq!(s.as_str(), "ab°");
+}
+
+#[test_case]
+fn test_bitmap_set_clear() {
+    let mut words = [0u64; 2];
+    let mut bitmap = BitMap::new(&mut words, 100);
+    bitmap.set(0);
+    bitmap.set(63);
+    bitmap.set(64);
+    assert!(bitmap.get(63) && bitmap.get(64));
+    assert!(!bitmap.get(1));
+    assert_eq!(bitmap.find_first_zero(), Some(1));
+    bitmap.clear(63);
+    assert!(!bitmap.get(63));
+
+    // fill everything, only the unused bits 100..128 of the last word are left clear
+    for i in 0..100 {
+        bitmap.set(i);
+    }
+    assert_eq!(bitmap.find_first_zero(), None);
+    bitmap.clear(99);
+    assert_eq!(bitmap.find_first_zero(), Some(99));
+}
+
+#[test_case]
+fn test_bitmap_contiguous_zeros() {
+    let mut words = [u64::MAX, 0, 0, 0];
+    let mut bitmap = BitMap::new(&mut words, 200);
+    // bits 60..64 are free, and so is all of word 1 behind them, so a run of 8 starts at 60
+    // and crosses into word 1
+    for i in 60..64 {
+        bitmap.clear(i);
+    }
+    assert_eq!(bitmap.find_n_contiguous_zeros(4), Some(60));
+    assert_eq!(bitmap.find_n_contiguous_zeros(8), Some(60));
+    bitmap.set(66);
+    // 60..66 is too short now, the next run starts after 66 and crosses into word 2
+    assert_eq!(bitmap.find_n_contiguous_zeros(8), Some(67));
+    assert_eq!(bitmap.find_n_contiguous_zeros(133), Some(67));
+    // 67..200 is only 133 bits
+    assert_eq!(bitmap.find_n_contiguous_zeros(134), None);
+}
+
+#[test_case]
+fn test_byte_queue_drops_oldest() {
+    let queue = ByteQueue::<4>::new();
+    assert_eq!(queue.pop(), None);
+    for byte in 1..=4 {
+        assert!(queue.push(byte));
+    }
+    // full, 1 has to go
+    assert!(!queue.push(5));
+    assert_eq!(queue.len(), 4);
+    for byte in 2..=5 {
+        assert_eq!(queue.pop(), Some(byte));
+    }
+    assert!(queue.is_empty());
+}
@@ -30,13 +30,20 @@
 
 use core::fmt;
 use lazy_static::lazy_static;
-use spin::Mutex;
+use pc_keyboard::KeyCode;
+use spin::{Mutex, MutexGuard};
 use volatile::Volatile;
+use x86_64::VirtAddr;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
 
 lazy_static! {
     pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
         column_pos: 0,
+        row_pos: BUFFER_HEIGHT - 1,
+        overflow: OverflowMode::Scroll,
         color_code: ColorCode::new(Color::Cyan, Color::Black),
+        replacement_color: ColorCode::new(Color::Cyan, Color::Black),
         buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
     });
 }
@@ -63,25 +70,64 @@ pub enum Color {
     White = 15,
 }
 
+impl Color {
+    const ALL: [Color; 16] = [
+        Color::Black,
+        Color::Blue,
+        Color::Green,
+        Color::Cyan,
+        Color::Red,
+        Color::Magenta,
+        Color::Brown,
+        Color::LightGray,
+        Color::DarkGray,
+        Color::LightBlue,
+        Color::LightGreen,
+        Color::LightCyan,
+        Color::LightRed,
+        Color::Pink,
+        Color::Yellow,
+        Color::White,
+    ];
+
+    /// the color of the lower 4 bits of `value`
+    fn from_nibble(value: u8) -> Color {
+        Color::ALL[(value & 0x0F) as usize]
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
-struct ColorCode(u8);
+pub struct ColorCode(u8);
 
 impl ColorCode {
-    fn new(fg: Color, bg: Color) -> ColorCode {
+    pub fn new(fg: Color, bg: Color) -> ColorCode {
         ColorCode((bg as u8) << 4 | (fg as u8))
     }
+
+    fn foreground(self) -> Color {
+        Color::from_nibble(self.0)
+    }
+
+    fn background(self) -> Color {
+        Color::from_nibble(self.0 >> 4)
+    }
+
+    /// same background, different foreground
+    fn with_foreground(self, fg: Color) -> ColorCode {
+        ColorCode(self.0 & 0xF0 | fg as u8)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
-struct ScreenChar {
-    ascii_char: u8,
-    color_code: ColorCode,
+pub struct ScreenChar {
+    pub ascii_char: u8,
+    pub color_code: ColorCode,
 }
 
-const BUFFER_HEIGHT: usize = 25;
-const BUFFER_WIDTH: usize = 80;
+pub const BUFFER_HEIGHT: usize = 25;
+pub const BUFFER_WIDTH: usize = 80;
 
 // The problem is that we only write to the Buffer and never read from it again.
 // The compiler doesn’t know that we really access VGA buffer memory (instead of normal RAM)
@@ -94,12 +140,124 @@ struct Buffer {
     chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
 }
 
-/// always writes to the last line and shifts lines up when a line
-/// is full or on \n
+// ** Code Page 437
+// the glyphs in the vga font follow code page 437: 0x20-0x7E are the same as ascii, the upper
+// half holds accented letters, box drawing, greek letters and math symbols. these are the
+// unicode chars for 0x80-0xFF, in order. 0xFF is a non breaking space
+const CP437_HIGH: &str = concat!(
+    "ÇüéâäàåçêëèïîìÄÅ",
+    "ÉæÆôöòûùÿÖÜ¢£¥₧ƒ",
+    "áíóúñÑªº¿⌐¬½¼¡«»",
+    "░▒▓│┤╡╢╖╕╣║╗╝╜╛┐",
+    "└┴┬├─┼╞╟╚╔╩╦╠═╬╧",
+    "╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀",
+    "αßΓπΣσµτΦΘΩδ∞φε∩",
+    "≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}",
+);
+
+/// the cp437 glyph showing `c`, if the font has one
+fn cp437_glyph(c: char) -> Option<u8> {
+    match c {
+        ' '..='~' => Some(c as u8),
+        _ => CP437_HIGH
+            .chars()
+            .position(|glyph| glyph == c)
+            .map(|i| 0x80 + i as u8),
+    }
+}
+
+const TAB_WIDTH: usize = 8;
+
+// ** CRT Controller (CRTC)
+// the hardware cursor is not part of the text buffer, it is controlled by the CRT controller
+// which is accessed through two io ports:
+//  0x3D4: index port, selects which CRTC register we want to access
+//  0x3D5: data port, reads or writes the selected register
+// so every access is "write the register index to 0x3D4, then read/write 0x3D5".
+//
+// the cursor is drawn between two scanlines of the 16 scanlines a character cell has:
+// Register	Bits	Description
+// 0x0A	    0-4	    Cursor start scanline
+// 0x0A	    5	    Cursor disable (1: cursor is hidden)
+// 0x0B	    0-4	    Cursor end scanline
+// the rest of the bits belong to other settings and must be preserved.
+//
+// where the cursor is drawn is a cell index, row * 80 + column, split over two registers:
+// Register	Description
+// 0x0E	    Cursor location, high byte
+// 0x0F	    Cursor location, low byte
+// the writer moves the cursor after every glyph and new line so it sits where the next char goes.
+const CRTC_INDEX_PORT: u16 = 0x3D4;
+const CRTC_DATA_PORT: u16 = 0x3D5;
+const CRTC_CURSOR_START: u8 = 0x0A;
+const CRTC_CURSOR_END: u8 = 0x0B;
+const CRTC_CURSOR_LOCATION_HIGH: u8 = 0x0E;
+const CRTC_CURSOR_LOCATION_LOW: u8 = 0x0F;
+const CURSOR_DISABLE: u8 = 1 << 5;
+const CURSOR_SCANLINE_MASK: u8 = 0x1F;
+
+fn crtc_read(index: u8) -> u8 {
+    let mut index_port: Port<u8> = Port::new(CRTC_INDEX_PORT);
+    let mut data_port: Port<u8> = Port::new(CRTC_DATA_PORT);
+    unsafe {
+        index_port.write(index);
+        data_port.read()
+    }
+}
+
+fn crtc_write(index: u8, value: u8) {
+    let mut index_port: Port<u8> = Port::new(CRTC_INDEX_PORT);
+    let mut data_port: Port<u8> = Port::new(CRTC_DATA_PORT);
+    unsafe {
+        index_port.write(index);
+        data_port.write(value);
+    }
+}
+
+/// color presets for the writer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    /// cyan on black
+    Default,
+    /// green on black
+    Matrix,
+    /// amber (brown on vga) on black
+    Amber,
+    /// white on black
+    HighContrast,
+}
+
+impl Theme {
+    /// (foreground, background, replacement char foreground)
+    fn colors(self) -> (Color, Color, Color) {
+        match self {
+            Theme::Default => (Color::Cyan, Color::Black, Color::Cyan),
+            Theme::Matrix => (Color::Green, Color::Black, Color::LightGreen),
+            Theme::Amber => (Color::Brown, Color::Black, Color::Yellow),
+            Theme::HighContrast => (Color::White, Color::Black, Color::Yellow),
+        }
+    }
+}
+
+/// what the writer does when a new line is needed on the last row
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowMode {
+    /// shifts every line up and keeps writing on the last row (streaming logs)
+    Scroll,
+    /// jumps back to row 0 and overwrites it (fixed-frame rendering)
+    WrapTop,
+}
+
+/// writes to the last line and shifts lines up when a line
+/// is full or on \n (unless the overflow mode says otherwise)
 pub struct Writer {
-    ///keeps track of current position in the last row
+    ///keeps track of current position in the current row
     column_pos: usize,
+    row_pos: usize,
+    overflow: OverflowMode,
     color_code: ColorCode,
+    /// color of the ■ printed in place of unprintable bytes
+    replacement_color: ColorCode,
     buffer: &'static mut Buffer,
 }
 
@@ -107,43 +265,124 @@ impl Writer {
     pub fn write_byte(&mut self, byte: u8) {
         match byte {
             b'\n' => self.new_line(),
-            byte => {
-                if self.column_pos >= BUFFER_WIDTH {
-                    self.new_line();
-                }
-                let row = BUFFER_HEIGHT - 1;
-                let col = self.column_pos;
-                let color_code = self.color_code;
-                self.buffer.chars[row][col].write(ScreenChar {
-                    ascii_char: byte,
-                    color_code,
-                });
-                self.column_pos += 1;
-            }
+            0x08 => self.backspace(),
+            byte => self.write_glyph(byte, self.color_code),
         }
     }
 
+    /// blanks the char before the write position and moves back onto it. does nothing at the
+    /// start of a row, it doesnt go back to the previous one
+    fn backspace(&mut self) {
+        if self.column_pos == 0 {
+            return;
+        }
+        self.column_pos -= 1;
+        self.buffer.chars[self.row_pos][self.column_pos].write(ScreenChar {
+            ascii_char: b' ',
+            color_code: self.color_code,
+        });
+        self.update_cursor();
+    }
+
+    fn write_glyph(&mut self, byte: u8, color_code: ColorCode) {
+        if self.column_pos >= BUFFER_WIDTH {
+            self.new_line();
+        }
+        let row = self.row_pos;
+        let col = self.column_pos;
+        self.buffer.chars[row][col].write(ScreenChar {
+            ascii_char: byte,
+            color_code,
+        });
+        self.column_pos += 1;
+        self.update_cursor();
+    }
+
     pub fn write_string(&mut self, s: &str) {
         for byte in s.bytes() {
             match byte {
                 //ascii chars can already be printed
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
+                0x20..=0x7e | b'\n' | 0x08 => self.write_byte(byte),
                 // not printable ascii range
-                _ => self.write_byte(0xfe),
+                _ => self.write_glyph(0xfe, self.replacement_color),
+            }
+        }
+    }
+
+    /// writes `c` as its cp437 glyph, the replacement glyph if there is none.
+    /// \n, \r and \t move the cursor instead, backspace (\x08) erases the char before it
+    pub fn write_char(&mut self, c: char) {
+        match c {
+            '\n' => self.new_line(),
+            '\r' => self.column_pos = 0,
+            '\t' => {
+                let stop = (self.column_pos / TAB_WIDTH + 1) * TAB_WIDTH;
+                while self.column_pos < stop.min(BUFFER_WIDTH) {
+                    self.write_glyph(b' ', self.color_code);
+                }
             }
+            '\x08' => self.backspace(),
+            c => match cp437_glyph(c) {
+                Some(glyph) => self.write_glyph(glyph, self.color_code),
+                None => self.write_glyph(0xfe, self.replacement_color),
+            },
         }
     }
-    /// We iterate over all the screen characters and move each character one row up.
+
+    /// only affects what is written from now on, the text on screen keeps its colors
+    pub fn apply_theme(&mut self, theme: Theme) {
+        let (fg, bg, replacement_fg) = theme.colors();
+        self.color_code = ColorCode::new(fg, bg);
+        self.replacement_color = ColorCode::new(replacement_fg, bg);
+    }
+
+    /// the color for everything written (and every row cleared) from now on. the replacement
+    /// glyph keeps its foreground but moves to the new background
+    pub fn set_color(&mut self, fg: Color, bg: Color) {
+        self.color_code = ColorCode::new(fg, bg);
+        self.replacement_color = ColorCode::new(self.replacement_color.foreground(), bg);
+    }
+
+    /// the current foreground and background
+    pub fn color(&self) -> (Color, Color) {
+        (self.color_code.foreground(), self.color_code.background())
+    }
+
+    pub fn set_overflow(&mut self, mode: OverflowMode) {
+        self.overflow = mode;
+    }
+
+    /// moves down a row if there is one left. on the last row, Scroll mode iterates over all
+    /// the screen characters and moves each character one row up, while WrapTop mode
+    /// starts over from row 0
     fn new_line(&mut self) {
-        for row in 1..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                let char = self.buffer.chars[row][col].read();
-                self.buffer.chars[row - 1][col].write(char);
+        if self.row_pos < BUFFER_HEIGHT - 1 {
+            self.row_pos += 1;
+        } else {
+            match self.overflow {
+                OverflowMode::Scroll => {
+                    for row in 1..BUFFER_HEIGHT {
+                        for col in 0..BUFFER_WIDTH {
+                            let char = self.buffer.chars[row][col].read();
+                            self.buffer.chars[row - 1][col].write(char);
+                        }
+                    }
+                }
+                OverflowMode::WrapTop => self.row_pos = 0,
             }
         }
-        self.clear_row(BUFFER_HEIGHT - 1);
+        self.clear_row(self.row_pos);
+        self.column_pos = 0;
+        self.update_cursor();
+    }
+    /// blanks every row in the current color and moves to the start of the current row
+    pub fn clear_screen(&mut self) {
+        for row in 0..BUFFER_HEIGHT {
+            self.clear_row(row);
+        }
         self.column_pos = 0;
     }
+
     fn clear_row(&mut self, row: usize) {
         let blank = ScreenChar {
             ascii_char: b' ',
@@ -153,6 +392,128 @@ impl Writer {
             self.buffer.chars[row][col].write(blank);
         }
     }
+
+    /// the char on screen at `row`/`col`
+    pub fn read_char_at(&self, row: usize, col: usize) -> ScreenChar {
+        assert!(
+            row < BUFFER_HEIGHT && col < BUFFER_WIDTH,
+            "read_char_at({}, {}) is outside the {}x{} screen",
+            row,
+            col,
+            BUFFER_WIDTH,
+            BUFFER_HEIGHT
+        );
+        self.buffer.chars[row][col].read()
+    }
+
+    /// the glyphs of `row`, as cp437 bytes
+    pub fn read_string_row(&self, row: usize) -> [u8; BUFFER_WIDTH] {
+        assert!(
+            row < BUFFER_HEIGHT,
+            "read_string_row({}) is outside the {} row screen",
+            row,
+            BUFFER_HEIGHT
+        );
+        core::array::from_fn(|col| self.buffer.chars[row][col].read().ascii_char)
+    }
+
+    /// copies the visible text into `out`, one line per row with the trailing spaces removed, and
+    /// returns how many bytes were written. glyphs outside printable ascii come out as `.`, so
+    /// the result is always valid utf-8. stops early if `out` is too small
+    pub fn screen_text(&self, out: &mut [u8]) -> usize {
+        let mut len = 0;
+        for row in self.buffer.chars.iter() {
+            let line: [u8; BUFFER_WIDTH] =
+                core::array::from_fn(|col| match row[col].read().ascii_char {
+                    byte @ 0x20..=0x7e => byte,
+                    _ => b'.',
+                });
+            let end = line
+                .iter()
+                .rposition(|&byte| byte != b' ')
+                .map_or(0, |i| i + 1);
+            for &byte in line[..end].iter().chain(b"\n") {
+                let Some(slot) = out.get_mut(len) else {
+                    return len;
+                };
+                *slot = byte;
+                len += 1;
+            }
+        }
+        len
+    }
+
+    /// sets the scanlines the hardware cursor covers, ie. (14, 15) is an underline
+    /// and (0, 15) is a full block
+    pub fn set_cursor_shape(&mut self, start_scanline: u8, end_scanline: u8) {
+        let start = crtc_read(CRTC_CURSOR_START) & !CURSOR_SCANLINE_MASK;
+        crtc_write(
+            CRTC_CURSOR_START,
+            start | (start_scanline & CURSOR_SCANLINE_MASK),
+        );
+        let end = crtc_read(CRTC_CURSOR_END) & !CURSOR_SCANLINE_MASK;
+        crtc_write(CRTC_CURSOR_END, end | (end_scanline & CURSOR_SCANLINE_MASK));
+    }
+
+    /// shows the cursor between the two scanlines
+    pub fn enable_cursor(&mut self, start_scanline: u8, end_scanline: u8) {
+        self.set_cursor_shape(start_scanline, end_scanline);
+        self.set_cursor_visible(true);
+    }
+
+    pub fn disable_cursor(&mut self) {
+        self.set_cursor_visible(false);
+    }
+
+    /// moves the hardware cursor to the write position. after the last column of a row it stays
+    /// on that column until the next char wraps
+    pub fn update_cursor(&self) {
+        let col = self.column_pos.min(BUFFER_WIDTH - 1);
+        let position = (self.row_pos * BUFFER_WIDTH + col) as u16;
+        crtc_write(CRTC_CURSOR_LOCATION_LOW, position as u8);
+        crtc_write(CRTC_CURSOR_LOCATION_HIGH, (position >> 8) as u8);
+    }
+
+    pub fn set_cursor_visible(&mut self, visible: bool) {
+        let start = crtc_read(CRTC_CURSOR_START);
+        if visible {
+            crtc_write(CRTC_CURSOR_START, start & !CURSOR_DISABLE);
+        } else {
+            crtc_write(CRTC_CURSOR_START, start | CURSOR_DISABLE);
+        }
+    }
+    /// writes a char at a fixed position with the current color, without moving the
+    /// write position or scrolling
+    pub fn write_byte_at(&mut self, row: usize, col: usize, byte: u8) {
+        self.buffer.chars[row][col].write(ScreenChar {
+            ascii_char: byte,
+            color_code: self.color_code,
+        });
+    }
+
+    /// writes `s` so that its last char lands on `right_col` of `row`, in `color` on the current
+    /// background. whatever doesnt fit left of column 0 is cut off. the cursor doesnt move
+    pub fn write_right_aligned(&mut self, row: usize, right_col: usize, s: &str, color: Color) {
+        if row >= BUFFER_HEIGHT {
+            return;
+        }
+        let right_col = right_col.min(BUFFER_WIDTH - 1);
+        let bytes = s.as_bytes();
+        let visible = &bytes[bytes.len().saturating_sub(right_col + 1)..];
+        let start = right_col + 1 - visible.len();
+        let color_code = self.color_code.with_foreground(color);
+        for (i, &byte) in visible.iter().enumerate() {
+            let (byte, color_code) = match byte {
+                0x20..=0x7e => (byte, color_code),
+                _ => (0xfe, self.replacement_color),
+            };
+            self.buffer.chars[row][start + i].write(ScreenChar {
+                ascii_char: byte,
+                color_code,
+            });
+        }
+    }
+
     // pub fn print_something() {
     //     use core::fmt::Write;
     //     let mut writer = Writer {
@@ -172,6 +533,11 @@ impl fmt::Write for Writer {
         self.write_string(s);
         Ok(())
     }
+
+    fn write_char(&mut self, c: char) -> fmt::Result {
+        Writer::write_char(self, c);
+        Ok(())
+    }
 }
 
 #[macro_export]
@@ -180,6 +546,30 @@ macro_rules! print {
         ($crate::vga_buffer::_print(format_args!($($arg)*)))
     };
 }
+/// wipes the whole screen
+#[macro_export]
+macro_rules! clear {
+    () => {
+        $crate::vga_buffer::_clear()
+    };
+}
+
+/// changes the color of what gets printed next, ie. `set_color!(Color::Red, Color::Black)`
+#[macro_export]
+macro_rules! set_color {
+    ($fg:expr, $bg:expr) => {
+        $crate::vga_buffer::_set_color($fg, $bg)
+    };
+}
+
+/// println in another color, the previous color is restored afterwards
+#[macro_export]
+macro_rules! cprintln {
+    ($fg:expr, $bg:expr, $($arg:tt)*) => {
+        $crate::vga_buffer::_print_colored($fg, $bg, format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
 #[macro_export]
 macro_rules! println {
     () => {
@@ -190,8 +580,837 @@ macro_rules! println {
     };
 }
 
+/// a part of a single row
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub row: usize,
+    pub col: usize,
+    pub width: usize,
+}
+
+impl Region {
+    /// the part of the region that is on the screen
+    fn clipped(self) -> Region {
+        let col = self.col.min(BUFFER_WIDTH);
+        Region {
+            row: self.row.min(BUFFER_HEIGHT - 1),
+            col,
+            width: self.width.min(BUFFER_WIDTH - col),
+        }
+    }
+}
+
+/// spaces shown between the end of the text and its start coming around again
+const MARQUEE_GAP: usize = 3;
+
+/// scrolls `text` to the left inside `region`, one char per `tick`.
+/// text that fits in the region doesnt move at all
+pub struct Marquee {
+    pub region: Region,
+    pub text: &'static str,
+    pub offset: usize,
+}
+
+impl Marquee {
+    /// the region is clipped to the screen
+    pub fn new(region: Region, text: &'static str) -> Marquee {
+        Marquee {
+            region: region.clipped(),
+            text,
+            offset: 0,
+        }
+    }
+
+    fn scrolls(&self) -> bool {
+        self.text.len() > self.region.width
+    }
+
+    /// the char shown in the i-th cell of the region
+    fn visible_byte(&self, i: usize) -> u8 {
+        let text = self.text.as_bytes();
+        let pos = if self.scrolls() {
+            (self.offset + i) % (text.len() + MARQUEE_GAP)
+        } else {
+            i
+        };
+        text.get(pos).copied().unwrap_or(b' ')
+    }
+
+    /// advances the text by one char and redraws it
+    pub fn tick(&mut self) {
+        if self.scrolls() {
+            self.offset = (self.offset + 1) % (self.text.len() + MARQUEE_GAP);
+        }
+        self.draw();
+    }
+
+    pub fn draw(&self) {
+        let mut writer = WRITER.lock();
+        for i in 0..self.region.width {
+            writer.write_byte_at(self.region.row, self.region.col + i, self.visible_byte(i));
+        }
+    }
+}
+
+pub const VIEWER_BYTES_PER_ROW: usize = 8;
+
+/// shows memory as rows of "address  hex bytes  ascii":
+/// 00000000002051a0  68 65 6c 6c 6f 00 00 00  hello...
+/// bytes that cant be read show up as ?? instead of faulting
+pub struct MemoryViewer {
+    pub base: VirtAddr,
+    /// the first row, every following row is right below it
+    pub region: Region,
+    pub rows: usize,
+    /// tells if a byte can be read without faulting. there is no page fault recovery, so the
+    /// viewer has to ask before touching anything
+    pub readable: fn(VirtAddr) -> bool,
+}
+
+fn hex_digit(value: u8) -> u8 {
+    b"0123456789abcdef"[(value & 0xF) as usize]
+}
+
+impl MemoryViewer {
+    /// shows memory of the kernel image only, see memory::in_kernel_image
+    pub fn new(base: VirtAddr, region: Region, rows: usize) -> MemoryViewer {
+        MemoryViewer {
+            base,
+            region,
+            rows,
+            readable: crate::memory::in_kernel_image,
+        }
+    }
+
+    /// moves the view by `delta` rows (negative is up) and redraws it
+    pub fn scroll(&mut self, delta: isize) {
+        let offset = delta.unsigned_abs() as u64 * VIEWER_BYTES_PER_ROW as u64;
+        let base = self.base.as_u64();
+        let base = if delta < 0 {
+            base.saturating_sub(offset)
+        } else {
+            base.saturating_add(offset)
+        };
+        // skips over the non canonical hole instead of panicking in VirtAddr::new
+        self.base = VirtAddr::new_truncate(base);
+        self.draw();
+    }
+
+    /// the address `offset` bytes past base, None past the end of memory or in the non
+    /// canonical hole
+    fn addr_at(&self, offset: u64) -> Option<VirtAddr> {
+        let addr = self.base.as_u64().checked_add(offset)?;
+        VirtAddr::try_new(addr).ok()
+    }
+
+    /// the text of one row, `line` is relative to base
+    fn render_row(&self, line: usize) -> [u8; BUFFER_WIDTH] {
+        let mut text = [b' '; BUFFER_WIDTH];
+        let row_offset = (line * VIEWER_BYTES_PER_ROW) as u64;
+        match self.addr_at(row_offset) {
+            Some(addr) => {
+                for (i, cell) in text[..16].iter_mut().enumerate() {
+                    *cell = hex_digit((addr.as_u64() >> ((15 - i) * 4)) as u8);
+                }
+            }
+            None => text[..16].fill(b'?'),
+        }
+        for i in 0..VIEWER_BYTES_PER_ROW {
+            let byte_addr = self.addr_at(row_offset + i as u64);
+            let hex = 18 + i * 3;
+            let ascii = 18 + VIEWER_BYTES_PER_ROW * 3 + 1 + i;
+            if let Some(byte_addr) = byte_addr.filter(|&addr| (self.readable)(addr)) {
+                let byte = unsafe { core::ptr::read_volatile(byte_addr.as_ptr::<u8>()) };
+                text[hex] = hex_digit(byte >> 4);
+                text[hex + 1] = hex_digit(byte);
+                text[ascii] = if (0x20..=0x7e).contains(&byte) {
+                    byte
+                } else {
+                    b'.'
+                };
+            } else {
+                text[hex] = b'?';
+                text[hex + 1] = b'?';
+                text[ascii] = b'?';
+            }
+        }
+        text
+    }
+
+    pub fn draw(&self) {
+        let region = self.region.clipped();
+        let rows = self.rows.min(BUFFER_HEIGHT - region.row);
+        let mut writer = WRITER.lock();
+        for line in 0..rows {
+            let text = self.render_row(line);
+            for (i, &byte) in text[..region.width].iter().enumerate() {
+                writer.write_byte_at(region.row + line, region.col + i, byte);
+            }
+        }
+    }
+}
+
+/// the viewer the memview command opened, the arrow keys scroll it
+static MEMVIEW: Mutex<Option<MemoryViewer>> = Mutex::new(None);
+
+/// the `memview <addr>` shell command: shows memory from the hex address `addr` on, using the
+/// screen below the top row. anything mapped can be viewed, which includes mmio: reading a
+/// device register can have side effects
+pub fn memview_command(arg: &str, out: &mut impl fmt::Write) -> fmt::Result {
+    let arg = arg.trim();
+    let digits = arg.strip_prefix("0x").unwrap_or(arg);
+    let Some(base) = u64::from_str_radix(digits, 16)
+        .ok()
+        .and_then(|addr| VirtAddr::try_new(addr).ok())
+    else {
+        return writeln!(out, "memview: `{}` is not a canonical hex address", arg);
+    };
+    let region = Region {
+        row: 1,
+        col: 0,
+        width: BUFFER_WIDTH,
+    };
+    let viewer = MemoryViewer {
+        readable: crate::memory::is_mapped,
+        ..MemoryViewer::new(base, region, BUFFER_HEIGHT - 1)
+    };
+    viewer.draw();
+    *MEMVIEW.lock() = Some(viewer);
+    writeln!(
+        out,
+        "memview: {:#x}, arrows and page up/down scroll, esc closes",
+        base.as_u64()
+    )
+}
+
+/// scrolls the memview viewer: a row with the arrow keys, a screen with page up/down. escape
+/// closes it. returns whether the key was used, which it never is without an open viewer
+pub fn memview_key(key: KeyCode) -> bool {
+    let mut memview = MEMVIEW.lock();
+    let Some(viewer) = memview.as_mut() else {
+        return false;
+    };
+    let page = viewer.rows as isize;
+    match key {
+        KeyCode::ArrowUp => viewer.scroll(-1),
+        KeyCode::ArrowDown => viewer.scroll(1),
+        KeyCode::PageUp => viewer.scroll(-page),
+        KeyCode::PageDown => viewer.scroll(page),
+        KeyCode::Escape => *memview = None,
+        _ => return false,
+    }
+    true
+}
+
+/// cp437 full block and light shade
+const BAR_FILLED: u8 = 0xDB;
+const BAR_EMPTY: u8 = 0xB0;
+/// " 100%"
+const PERCENT_LABEL_WIDTH: usize = 5;
+
+/// a horizontal bar on one row, filled from the left, with the percentage on its right
+/// if there is room for it
+pub struct ProgressBar {
+    pub row: usize,
+    pub left: usize,
+    pub width: usize,
+}
+
+impl ProgressBar {
+    /// the bar is clipped to the screen
+    pub fn new(row: usize, left: usize, width: usize) -> ProgressBar {
+        let left = left.min(BUFFER_WIDTH);
+        ProgressBar {
+            row: row.min(BUFFER_HEIGHT - 1),
+            left,
+            width: width.min(BUFFER_WIDTH - left),
+        }
+    }
+
+    /// redraws the bar `fraction` (0.0 to 1.0) full
+    pub fn set_progress(&self, fraction: f32) {
+        // NaN ends up as 0 with the casts below
+        let fraction = fraction.clamp(0.0, 1.0);
+        // a bar too narrow for the label and at least one cell is drawn without it
+        let label = self.width > PERCENT_LABEL_WIDTH;
+        let bar_width = if label {
+            self.width - PERCENT_LABEL_WIDTH
+        } else {
+            self.width
+        };
+        let filled = (fraction * bar_width as f32 + 0.5) as usize;
+
+        let mut writer = WRITER.lock();
+        for i in 0..bar_width {
+            let glyph = if i < filled { BAR_FILLED } else { BAR_EMPTY };
+            writer.write_byte_at(self.row, self.left + i, glyph);
+        }
+        if label {
+            let percent = (fraction * 100.0 + 0.5) as usize;
+            let mut text = *b"    %";
+            let mut value = percent;
+            for cell in text[..4].iter_mut().rev() {
+                *cell = b'0' + (value % 10) as u8;
+                value /= 10;
+                if value == 0 {
+                    break;
+                }
+            }
+            for (i, &byte) in text.iter().enumerate() {
+                writer.write_byte_at(self.row, self.left + bar_width + i, byte);
+            }
+        }
+    }
+}
+
+// ** Banner Text
+// print_big draws text two rows tall for titles. every glyph is 3 cells wide and 4 "pixels"
+// tall, two pixels stacked in each cell using the cp437 half blocks:
+// top	bottom	glyph
+// on	on	    0xDB full block
+// on	off	    0xDF upper half block
+// off	on	    0xDC lower half block
+// off	off	    space
+// the font rows below are 3 bits each, the leftmost pixel is the highest bit.
+const BLOCK_FULL: u8 = 0xDB;
+const BLOCK_UPPER: u8 = 0xDF;
+const BLOCK_LOWER: u8 = 0xDC;
+const BIG_GLYPH_WIDTH: usize = 3;
+/// a glyph and the empty column after it
+const BIG_GLYPH_ADVANCE: usize = BIG_GLYPH_WIDTH + 1;
+
+type BigGlyph = [u8; 4];
+
+#[rustfmt::skip]
+const BIG_LETTERS: [BigGlyph; 26] = [
+    [0b111, 0b101, 0b111, 0b101], // A
+    [0b110, 0b111, 0b101, 0b111], // B
+    [0b111, 0b100, 0b100, 0b111], // C
+    [0b110, 0b101, 0b101, 0b110], // D
+    [0b111, 0b110, 0b100, 0b111], // E
+    [0b111, 0b110, 0b100, 0b100], // F
+    [0b111, 0b100, 0b101, 0b111], // G
+    [0b101, 0b111, 0b101, 0b101], // H
+    [0b111, 0b010, 0b010, 0b111], // I
+    [0b001, 0b001, 0b101, 0b111], // J
+    [0b101, 0b110, 0b101, 0b101], // K
+    [0b100, 0b100, 0b100, 0b111], // L
+    [0b111, 0b111, 0b101, 0b101], // M
+    [0b110, 0b101, 0b101, 0b101], // N
+    [0b111, 0b101, 0b101, 0b111], // O
+    [0b111, 0b101, 0b111, 0b100], // P
+    [0b111, 0b101, 0b111, 0b001], // Q
+    [0b111, 0b101, 0b110, 0b101], // R
+    [0b011, 0b100, 0b001, 0b110], // S
+    [0b111, 0b010, 0b010, 0b010], // T
+    [0b101, 0b101, 0b101, 0b111], // U
+    [0b101, 0b101, 0b101, 0b010], // V
+    [0b101, 0b101, 0b111, 0b111], // W
+    [0b101, 0b010, 0b010, 0b101], // X
+    [0b101, 0b101, 0b010, 0b010], // Y
+    [0b111, 0b001, 0b100, 0b111], // Z
+];
+
+#[rustfmt::skip]
+const BIG_DIGITS: [BigGlyph; 10] = [
+    [0b010, 0b101, 0b101, 0b010], // 0
+    [0b010, 0b110, 0b010, 0b111], // 1
+    [0b110, 0b001, 0b010, 0b111], // 2
+    [0b111, 0b011, 0b001, 0b111], // 3
+    [0b101, 0b111, 0b001, 0b001], // 4
+    [0b111, 0b110, 0b001, 0b110], // 5
+    [0b100, 0b111, 0b101, 0b111], // 6
+    [0b111, 0b001, 0b010, 0b010], // 7
+    [0b111, 0b111, 0b101, 0b111], // 8
+    [0b111, 0b111, 0b001, 0b111], // 9
+];
+
+/// lowercase letters use the uppercase glyphs, anything without a glyph is left blank
+fn big_glyph(c: char) -> BigGlyph {
+    match c.to_ascii_uppercase() {
+        c @ 'A'..='Z' => BIG_LETTERS[c as usize - 'A' as usize],
+        c @ '0'..='9' => BIG_DIGITS[c as usize - '0' as usize],
+        _ => [0; 4],
+    }
+}
+
+fn half_blocks(top: bool, bottom: bool) -> u8 {
+    match (top, bottom) {
+        (true, true) => BLOCK_FULL,
+        (true, false) => BLOCK_UPPER,
+        (false, true) => BLOCK_LOWER,
+        (false, false) => b' ',
+    }
+}
+
+/// draws `text` two rows tall with its top left corner at `row`/`col`, in `color` on the
+/// current background. whatever falls off the screen is cut off. the cursor doesnt move
+pub fn print_big(text: &str, row: usize, col: usize, color: Color) {
+    let mut writer = WRITER.lock();
+    let color_code = writer.color_code.with_foreground(color);
+    for (i, c) in text.chars().enumerate() {
+        let glyph = big_glyph(c);
+        let left = col + i * BIG_GLYPH_ADVANCE;
+        for (half, pixels) in glyph.chunks(2).enumerate() {
+            let screen_row = row + half;
+            if screen_row >= BUFFER_HEIGHT {
+                break;
+            }
+            for x in 0..BIG_GLYPH_WIDTH {
+                let screen_col = left + x;
+                if screen_col >= BUFFER_WIDTH {
+                    break;
+                }
+                let bit = 1 << (BIG_GLYPH_WIDTH - 1 - x);
+                writer.buffer.chars[screen_row][screen_col].write(ScreenChar {
+                    ascii_char: half_blocks(pixels[0] & bit != 0, pixels[1] & bit != 0),
+                    color_code,
+                });
+            }
+        }
+    }
+}
+
+/// writes `value` to the first cell after the visible screen, reads it back and restores the
+/// cell. the text buffer is 32KiB but only 80x25 cells are shown, so nothing flickers
+pub(crate) fn offscreen_round_trip(value: u16) -> bool {
+    let cell = (0xb8000 + BUFFER_HEIGHT * BUFFER_WIDTH * 2) as *mut u16;
+    unsafe {
+        let saved = core::ptr::read_volatile(cell);
+        core::ptr::write_volatile(cell, value);
+        let read = core::ptr::read_volatile(cell);
+        core::ptr::write_volatile(cell, saved);
+        read == value
+    }
+}
+
+pub fn apply_theme(theme: Theme) {
+    WRITER.lock().apply_theme(theme);
+}
+
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
-    WRITER.lock().write_fmt(args).unwrap();
+    // an interrupt handler that prints while we hold the lock would spin on it forever
+    interrupts::without_interrupts(|| {
+        WRITER.lock().write_fmt(args).unwrap();
+    });
+}
+
+#[doc(hidden)]
+pub fn _set_color(fg: Color, bg: Color) {
+    // an interrupt handler printing while we hold the lock would deadlock, same as in _print
+    interrupts::without_interrupts(|| WRITER.lock().set_color(fg, bg));
+}
+
+#[doc(hidden)]
+pub fn _clear() {
+    interrupts::without_interrupts(|| WRITER.lock().clear_screen());
+}
+
+/// holds the writer in a temporary color and puts the previous one back when dropped, so every
+/// way out of the print restores it
+struct ColorGuard<'a> {
+    writer: MutexGuard<'a, Writer>,
+    color_code: ColorCode,
+    replacement_color: ColorCode,
+}
+
+impl<'a> ColorGuard<'a> {
+    fn new(mut writer: MutexGuard<'a, Writer>, fg: Color, bg: Color) -> ColorGuard<'a> {
+        let color_code = writer.color_code;
+        let replacement_color = writer.replacement_color;
+        writer.set_color(fg, bg);
+        ColorGuard {
+            writer,
+            color_code,
+            replacement_color,
+        }
+    }
+}
+
+impl Drop for ColorGuard<'_> {
+    fn drop(&mut self) {
+        self.writer.color_code = self.color_code;
+        self.writer.replacement_color = self.replacement_color;
+    }
+}
+
+#[doc(hidden)]
+pub fn _print_colored(fg: Color, bg: Color, args: fmt::Arguments) {
+    use core::fmt::Write;
+    // one lock for the color change, the text and the restore, so nobody else prints in between
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        if !crate::output::vga_colors() {
+            writer.write_fmt(args).unwrap();
+            return;
+        }
+        let mut guard = ColorGuard::new(writer, fg, bg);
+        guard.writer.write_fmt(args).unwrap();
+    });
+}
+
+#[test_case]
+fn test_wrap_top_overflow() {
+    use core::fmt::Write;
+
+    let mut writer = WRITER.lock();
+    writer.set_overflow(OverflowMode::WrapTop);
+    // move past the last row, which should land us back on row 0
+    for _ in writer.row_pos..BUFFER_HEIGHT {
+        writeln!(writer).unwrap();
+    }
+    let s = "wrapped to the top";
+    write!(writer, "{}", s).unwrap();
+    for (i, c) in s.chars().enumerate() {
+        let screen_char = writer.buffer.chars[0][i].read();
+        assert_eq!(char::from(screen_char.ascii_char), c);
+    }
+    writer.set_overflow(OverflowMode::Scroll);
+}
+
+#[test_case]
+fn test_cursor_shape() {
+    let mut writer = WRITER.lock();
+
+    writer.set_cursor_shape(0, 15);
+    assert_eq!(crtc_read(CRTC_CURSOR_START) & CURSOR_SCANLINE_MASK, 0);
+    assert_eq!(crtc_read(CRTC_CURSOR_END) & CURSOR_SCANLINE_MASK, 15);
+
+    writer.set_cursor_shape(14, 15);
+    assert_eq!(crtc_read(CRTC_CURSOR_START) & CURSOR_SCANLINE_MASK, 14);
+    assert_eq!(crtc_read(CRTC_CURSOR_END) & CURSOR_SCANLINE_MASK, 15);
+
+    writer.set_cursor_visible(false);
+    assert_ne!(crtc_read(CRTC_CURSOR_START) & CURSOR_DISABLE, 0);
+    writer.set_cursor_visible(true);
+    assert_eq!(crtc_read(CRTC_CURSOR_START) & CURSOR_DISABLE, 0);
+    // the scanlines are not touched by hiding and showing the cursor
+    assert_eq!(crtc_read(CRTC_CURSOR_START) & CURSOR_SCANLINE_MASK, 14);
+}
+
+#[test_case]
+fn test_cursor_follows_writes() {
+    use core::fmt::Write;
+
+    let mut writer = WRITER.lock();
+    write!(writer, "\nabc").unwrap();
+    let expected = (writer.row_pos * BUFFER_WIDTH + 3) as u16;
+    let location = (crtc_read(CRTC_CURSOR_LOCATION_HIGH) as u16) << 8
+        | crtc_read(CRTC_CURSOR_LOCATION_LOW) as u16;
+    assert_eq!(location, expected);
+
+    writer.disable_cursor();
+    assert_ne!(crtc_read(CRTC_CURSOR_START) & CURSOR_DISABLE, 0);
+    writer.enable_cursor(14, 15);
+    assert_eq!(crtc_read(CRTC_CURSOR_START) & CURSOR_DISABLE, 0);
+}
+
+#[test_case]
+fn test_apply_theme() {
+    apply_theme(Theme::Matrix);
+    let color_code = WRITER.lock().color_code;
+    apply_theme(Theme::Default);
+    assert_eq!(color_code, ColorCode::new(Color::Green, Color::Black));
+}
+
+#[test_case]
+fn test_write_right_aligned() {
+    let mut writer = WRITER.lock();
+    writer.write_right_aligned(1, 79, "42", Color::Yellow);
+    assert_eq!(writer.buffer.chars[1][78].read().ascii_char, b'4');
+    assert_eq!(writer.buffer.chars[1][79].read().ascii_char, b'2');
+    assert_eq!(
+        writer.buffer.chars[1][78].read().color_code,
+        writer.color_code.with_foreground(Color::Yellow)
+    );
+
+    // only the last 3 chars fit when ending at column 2
+    writer.write_right_aligned(1, 2, "clipped", Color::Yellow);
+    for (col, &c) in b"ped".iter().enumerate() {
+        assert_eq!(writer.buffer.chars[1][col].read().ascii_char, c);
+    }
+}
+
+#[test_case]
+fn test_write_char() {
+    let mut writer = WRITER.lock();
+    writer.write_char('\n');
+    let row = writer.row_pos;
+    assert_eq!(writer.column_pos, 0);
+    writer.write_char('°');
+    writer.write_char('a');
+    writer.write_char('€');
+    assert_eq!(writer.buffer.chars[row][0].read().ascii_char, 0xF8);
+    assert_eq!(writer.buffer.chars[row][1].read().ascii_char, b'a');
+    // not in cp437
+    let replaced = writer.buffer.chars[row][2].read();
+    assert_eq!(replaced.ascii_char, 0xfe);
+    assert_eq!(replaced.color_code, writer.replacement_color);
+    writer.write_char('\n');
+    assert_eq!(writer.column_pos, 0);
+}
+
+#[test_case]
+fn test_marquee_ticks() {
+    let region = Region {
+        row: 0,
+        col: 70,
+        width: 5,
+    };
+    let mut marquee = Marquee::new(region, "hello world");
+    marquee.tick();
+    marquee.tick();
+    let expected = b"llo w";
+    {
+        let writer = WRITER.lock();
+        for (i, &c) in expected.iter().enumerate() {
+            assert_eq!(writer.buffer.chars[0][70 + i].read().ascii_char, c);
+        }
+    }
+
+    // once the text is through, the gap comes before it starts over
+    for _ in 0..9 {
+        marquee.tick();
+    }
+    let writer = WRITER.lock();
+    let window: [u8; 5] =
+        core::array::from_fn(|i| writer.buffer.chars[0][70 + i].read().ascii_char);
+    assert_eq!(&window, b"   he");
+}
+
+#[test_case]
+fn test_progress_bar_half() {
+    let bar = ProgressBar::new(2, 10, 25);
+    bar.set_progress(0.5);
+    let writer = WRITER.lock();
+    let cells: [u8; 25] =
+        core::array::from_fn(|i| writer.buffer.chars[2][10 + i].read().ascii_char);
+    assert!(cells[..10].iter().all(|&c| c == BAR_FILLED));
+    assert!(cells[10..20].iter().all(|&c| c == BAR_EMPTY));
+    assert_eq!(&cells[20..], b"  50%");
+}
+
+#[test_case]
+fn test_memory_viewer() {
+    static BYTES: [u8; 8] = *b"hi\x00\x01view";
+    let region = Region {
+        row: 3,
+        col: 0,
+        width: BUFFER_WIDTH,
+    };
+    let viewer = MemoryViewer::new(VirtAddr::from_ptr(&raw const BYTES), region, 1);
+    viewer.draw();
+
+    let writer = WRITER.lock();
+    let row: [u8; 51] = core::array::from_fn(|i| writer.buffer.chars[3][i].read().ascii_char);
+    assert_eq!(&row[16..], b"  68 69 00 01 76 69 65 77  hi..view");
+
+    let unreadable = MemoryViewer {
+        readable: |_| false,
+        ..viewer
+    };
+    assert_eq!(&unreadable.render_row(0)[18..20], b"??");
+
+    // the last row before the non canonical hole runs into it instead of panicking
+    let at_the_hole = MemoryViewer {
+        base: VirtAddr::new(0x7fff_ffff_fffc),
+        readable: |_| true,
+        ..viewer
+    };
+    let row = at_the_hole.render_row(1);
+    assert_eq!(&row[..16], b"????????????????");
+    assert!(row[18..42].iter().all(|&c| c == b'?' || c == b' '));
+    let at_the_end = MemoryViewer {
+        base: VirtAddr::new(u64::MAX - 3),
+        readable: |_| false,
+        ..viewer
+    };
+    assert_eq!(&at_the_end.render_row(1)[..16], b"????????????????");
+}
+
+#[test_case]
+fn test_memview_command() {
+    use crate::util::FixedString;
+    use core::fmt::Write;
+
+    static BYTES: [u8; 8] = *b"memview!";
+    let mut out = FixedString::<80>::new();
+    memview_command("zz", &mut out).unwrap();
+    assert!(out.as_str().starts_with("memview: `zz` is not"));
+    assert!(!memview_key(KeyCode::ArrowDown));
+
+    let addr = VirtAddr::from_ptr(&raw const BYTES).as_u64();
+    let mut arg = FixedString::<32>::new();
+    write!(arg, "{:#x}", addr).unwrap();
+    out.clear();
+    memview_command(arg.as_str(), &mut out).unwrap();
+    assert!(out.as_str().starts_with("memview: "));
+    assert!(out.as_str()[9..].starts_with(arg.as_str()));
+    assert!(
+        out.as_str()
+            .ends_with(", arrows and page up/down scroll, esc closes\n")
+    );
+    let base = || MEMVIEW.lock().as_ref().map(|viewer| viewer.base.as_u64());
+    assert_eq!(base(), Some(addr));
+
+    assert!(memview_key(KeyCode::ArrowDown));
+    assert!(memview_key(KeyCode::PageDown));
+    assert!(!memview_key(KeyCode::A));
+    let rows = (BUFFER_HEIGHT - 1) as u64;
+    assert_eq!(
+        base(),
+        Some(addr + (1 + rows) * VIEWER_BYTES_PER_ROW as u64)
+    );
+
+    assert!(memview_key(KeyCode::Escape));
+    assert_eq!(base(), None);
+    _clear();
+}
+
+#[test_case]
+fn test_print_big() {
+    print_big("OS", 5, 0, Color::LightCyan);
+    let writer = WRITER.lock();
+    let cells = |row: usize| -> [u8; 7] {
+        core::array::from_fn(|i| writer.buffer.chars[row][i].read().ascii_char)
+    };
+    // O is 111 101 101 111, S is 011 100 001 110
+    assert_eq!(
+        cells(5),
+        [
+            BLOCK_FULL,
+            BLOCK_UPPER,
+            BLOCK_FULL,
+            b' ',
+            BLOCK_LOWER,
+            BLOCK_UPPER,
+            BLOCK_UPPER
+        ]
+    );
+    assert_eq!(
+        cells(6),
+        [
+            BLOCK_FULL,
+            BLOCK_LOWER,
+            BLOCK_FULL,
+            b' ',
+            BLOCK_LOWER,
+            BLOCK_LOWER,
+            BLOCK_UPPER
+        ]
+    );
+    assert_eq!(
+        writer.buffer.chars[5][0].read().color_code,
+        writer.color_code.with_foreground(Color::LightCyan)
+    );
+    drop(writer);
+
+    // past the right and bottom edges
+    print_big("OS", BUFFER_HEIGHT - 1, BUFFER_WIDTH - 2, Color::LightCyan);
+}
+
+#[test_case]
+fn test_screen_text() {
+    use core::fmt::Write;
+
+    let mut writer = WRITER.lock();
+    for row in 0..BUFFER_HEIGHT {
+        writer.clear_row(row);
+    }
+    writer.row_pos = 0;
+    writer.column_pos = 0;
+    write!(writer, "hello\n  world  \n\n\u{1}").unwrap();
+
+    let mut out = [0u8; BUFFER_HEIGHT * (BUFFER_WIDTH + 1)];
+    let len = writer.screen_text(&mut out);
+    let text = core::str::from_utf8(&out[..len]).unwrap();
+    let (top, rest) = text.split_at(17);
+    assert_eq!(top, "hello\n  world\n\n.\n");
+    // the other 21 rows are empty
+    assert_eq!(rest.len(), BUFFER_HEIGHT - 4);
+    assert!(rest.bytes().all(|byte| byte == b'\n'));
+
+    let mut short = [0u8; 8];
+    assert_eq!(writer.screen_text(&mut short), 8);
+    assert_eq!(&short, b"hello\n  ");
+}
+
+#[test_case]
+fn test_read_back_after_scroll() {
+    use core::fmt::Write;
+
+    let mut writer = WRITER.lock();
+    writer.set_overflow(OverflowMode::Scroll);
+    writer.row_pos = BUFFER_HEIGHT - 1;
+    write!(writer, "\nfirst\nsecond").unwrap();
+
+    // "first" was pushed up a row by the newline before "second"
+    assert_eq!(&writer.read_string_row(BUFFER_HEIGHT - 2)[..6], b"first ");
+    assert_eq!(&writer.read_string_row(BUFFER_HEIGHT - 1)[..7], b"second ");
+    assert_eq!(
+        writer.read_char_at(BUFFER_HEIGHT - 1, 0),
+        ScreenChar {
+            ascii_char: b's',
+            color_code: writer.color_code,
+        }
+    );
+}
+
+#[test_case]
+fn test_backspace() {
+    use core::fmt::Write;
+
+    let mut writer = WRITER.lock();
+    write!(writer, "\nab\x08c").unwrap();
+    let row = writer.row_pos;
+    assert_eq!(&writer.read_string_row(row)[..3], b"ac ");
+    assert_eq!(writer.column_pos, 2);
+
+    // nothing to erase at the start of a row
+    write!(writer, "\n\x08").unwrap();
+    assert_eq!(writer.column_pos, 0);
+}
+
+#[test_case]
+fn test_set_color() {
+    use core::fmt::Write;
+
+    let previous = WRITER.lock().color();
+    crate::set_color!(Color::Red, Color::Blue);
+    let mut writer = WRITER.lock();
+    assert_eq!(writer.color(), (Color::Red, Color::Blue));
+
+    // the new line is cleared in the new color, the text is written in it
+    write!(writer, "\nx").unwrap();
+    let row = writer.row_pos;
+    let red_on_blue = ColorCode::new(Color::Red, Color::Blue);
+    assert_eq!(writer.read_char_at(row, 0).color_code, red_on_blue);
+    assert_eq!(writer.read_char_at(row, 1).color_code, red_on_blue);
+    assert_eq!(writer.replacement_color.background(), Color::Blue);
+
+    writer.set_color(previous.0, previous.1);
+}
+
+#[test_case]
+fn test_cprintln_restores_color() {
+    let previous = {
+        let mut writer = WRITER.lock();
+        writer.set_overflow(OverflowMode::Scroll);
+        writer.row_pos = BUFFER_HEIGHT - 1;
+        writer.color()
+    };
+    // the earlier tests may have left the cursor anywhere in the row, the leading newline
+    // starts a fresh one
+    crate::cprintln!(Color::LightRed, Color::Black, "\nerror {}", 42);
+    let writer = WRITER.lock();
+    assert_eq!(writer.color(), previous);
+    // the text scrolled up a row with the newline
+    let row = BUFFER_HEIGHT - 2;
+    assert_eq!(&writer.read_string_row(row)[..8], b"error 42");
+    assert_eq!(
+        writer.read_char_at(row, 0).color_code,
+        ColorCode::new(Color::LightRed, Color::Black)
+    );
 }
@@ -0,0 +1,68 @@
+// maps a page in a second address space only: it has to be readable after switching to it, and
+// gone again once we are back in the kernel's
+#![no_std]
+#![no_main]
+
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use os::memory::{self, AddressSpace, AddressSpaceError, BootInfoFrameAllocator};
+use os::{exit_qemu, serial_print, serial_println};
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{FrameAllocator, Page, PageTableFlags};
+
+const VALUE: u64 = 0x1234_5678_9abc_def0;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os::init();
+    serial_print!("address_space::page_only_mapped_in_its_space...\t");
+
+    let offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let _mapper = unsafe { memory::init(offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    let mut space = AddressSpace::new(&mut frame_allocator).expect("no frame for the table");
+    // the first user space slot (512 GiB each) the kernel doesnt use
+    let page = (1..256u64)
+        .map(|slot| Page::containing_address(VirtAddr::new(slot << 39)))
+        .find(|&page| !space.is_shared(page))
+        .expect("every user space slot is taken by the kernel");
+
+    let frame = frame_allocator.allocate_frame().unwrap();
+    // filled in through the physical memory mapping, which every address space shares
+    let through_offset = (offset + frame.start_address().as_u64()).as_mut_ptr::<u64>();
+    unsafe { through_offset.write_volatile(VALUE) };
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    unsafe { space.map(page, frame, flags, &mut frame_allocator) }.expect("map failed");
+
+    // the kernel's own mappings are off limits
+    let kernel_page = Page::containing_address(VirtAddr::from_ptr(&VALUE));
+    assert!(matches!(
+        unsafe { space.map(kernel_page, frame, flags, &mut frame_allocator) },
+        Err(AddressSpaceError::SharedEntry(_))
+    ));
+
+    unsafe { space.switch_to() };
+    assert!(space.is_active());
+    let value = unsafe { page.start_address().as_ptr::<u64>().read_volatile() };
+    let translated = unsafe { memory::translate_addr(page.start_address(), offset) };
+    unsafe { memory::switch_to_kernel() };
+
+    assert_eq!(value, VALUE);
+    assert_eq!(translated, Some(frame.start_address()));
+    assert!(!space.is_active());
+    assert_eq!(
+        unsafe { memory::translate_addr(page.start_address(), offset) },
+        None
+    );
+
+    serial_println!("[ok]");
+    exit_qemu(os::QemuExitCode::Success);
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
@@ -24,7 +24,7 @@ use os::println;
 #[unsafe(no_mangle)]
 pub extern "C" fn _start() -> ! {
     test_main();
-    loop {}
+    os::hlt_loop();
 }
 
 #[panic_handler]
@@ -0,0 +1,40 @@
+#![no_main]
+#![no_std]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+use os::{clear, println};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    test_main();
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+/// the vga text buffer, 80x25 cells of an ascii byte and a color byte
+const VGA_BUFFER: *const u16 = 0xb8000 as *const u16;
+const CELLS: usize = 80 * 25;
+
+#[test_case]
+fn test_clear_blanks_every_cell() {
+    for i in 0..30 {
+        println!("line {} with some text on it", i);
+    }
+    clear!();
+
+    let first = unsafe { VGA_BUFFER.read_volatile() };
+    for i in 0..CELLS {
+        let cell = unsafe { VGA_BUFFER.add(i).read_volatile() };
+        assert_eq!(cell as u8, b' ', "cell {} isnt blank", i);
+        // all in the same color, the writer's current one
+        assert_eq!(cell >> 8, first >> 8);
+    }
+}
@@ -0,0 +1,65 @@
+// maps a copy-on-write page read only and writes to it. the write has to land in a private copy:
+// the page reads the new value, while the frame it was mapped to keeps the old one
+#![no_std]
+#![no_main]
+
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use os::memory::{self, BootInfoFrameAllocator};
+use os::{exit_qemu, serial_print, serial_println};
+use spin::{Mutex, Once};
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB};
+
+/// nothing is mapped here, page_fault.rs and create_mapping.rs use the ones before
+const UNUSED_PAGE: u64 = 0x5ead_d000_0000;
+const ORIGINAL: u64 = 0x0123_4567_89ab_cdef;
+const WRITTEN: u64 = 0xfedc_ba98_7654_3210;
+
+static FRAME_ALLOCATOR: Once<Mutex<BootInfoFrameAllocator>> = Once::new();
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os::init();
+    serial_print!("cow::write_gets_private_copy...\t");
+
+    let offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(offset) };
+    let frame_allocator = FRAME_ALLOCATOR
+        .call_once(|| Mutex::new(unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) }));
+
+    let page = Page::<Size4KiB>::containing_address(VirtAddr::new(UNUSED_PAGE));
+    let frame = {
+        let mut frame_allocator = frame_allocator.lock();
+        let frame = frame_allocator.allocate_frame().unwrap();
+        let through_offset = (offset + frame.start_address().as_u64()).as_mut_ptr::<u64>();
+        unsafe { through_offset.write_volatile(ORIGINAL) };
+        let flags = PageTableFlags::PRESENT;
+        unsafe { mapper.map_to(page, frame, flags, &mut *frame_allocator) }
+            .expect("mapping an unused page failed")
+            .flush();
+        frame
+    };
+    memory::set_cow_frame_allocator(frame_allocator);
+    memory::register_cow_region(page.start_address()..page.start_address() + 4096u64)
+        .expect("no free cow region");
+
+    let through_page = page.start_address().as_mut_ptr::<u64>();
+    unsafe { through_page.write_volatile(WRITTEN) };
+
+    assert_eq!(unsafe { through_page.read_volatile() }, WRITTEN);
+    let through_offset = (offset + frame.start_address().as_u64()).as_ptr::<u64>();
+    assert_eq!(unsafe { through_offset.read_volatile() }, ORIGINAL);
+    let copy = unsafe { memory::translate_addr(page.start_address(), offset) };
+    assert!(copy.is_some_and(|copy| copy != frame.start_address()));
+
+    serial_println!("[ok]");
+    exit_qemu(os::QemuExitCode::Success);
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
@@ -0,0 +1,54 @@
+// maps a fresh page to the vga buffer's frame and writes through it. the text has to show up in
+// the buffer's usual (identity mapped) place, both addresses end up in the same frame
+#![no_std]
+#![no_main]
+
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use os::memory::{self, BootInfoFrameAllocator};
+use os::{exit_qemu, serial_print, serial_println};
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{Page, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// nothing is mapped here, page_fault.rs uses 0x5ead_b000_0000
+const UNUSED_PAGE: u64 = 0x5ead_c000_0000;
+/// "New!" in white on black, little endian so the first character comes first
+const TEXT: u64 = 0x0f21_0f77_0f65_0f4e;
+/// row 2, column 40
+const OFFSET: u64 = (2 * 80 + 40) * 2;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os::init();
+    serial_print!("create_mapping::write_through_new_page...\t");
+
+    let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(physical_memory_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    let page = Page::containing_address(VirtAddr::new(UNUSED_PAGE));
+    let vga_frame = PhysFrame::containing_address(PhysAddr::new(0xb8000));
+    unsafe { memory::create_mapping(page, vga_frame, &mut mapper, &mut frame_allocator) }
+        .expect("mapping an unused page failed");
+
+    let through_page = (page.start_address() + OFFSET).as_mut_ptr::<u64>();
+    unsafe { through_page.write_volatile(TEXT) };
+    let on_screen = unsafe { ((0xb8000 + OFFSET) as *const u64).read_volatile() };
+    assert_eq!(on_screen, TEXT);
+
+    // the error comes back instead of a panic
+    let again =
+        unsafe { memory::create_mapping(page, vga_frame, &mut mapper, &mut frame_allocator) };
+    assert!(matches!(again, Err(MapToError::PageAlreadyMapped(frame)) if frame == vga_frame));
+
+    serial_println!("[ok]");
+    exit_qemu(os::QemuExitCode::Success);
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
@@ -0,0 +1,28 @@
+#![no_main]
+#![no_std]
+
+use core::arch::asm;
+use core::panic::PanicInfo;
+use core::ptr;
+
+use x86_64::structures::idt::ExceptionVector;
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    os::test_fault_exit(
+        "divide_error::division_by_zero_is_reported",
+        ExceptionVector::Division,
+        || {
+            // `/` checks for zero and panics before dividing, so the div has to be written
+            // out. the volatile read keeps the compiler from knowing the divisor
+            static ZERO: u64 = 0;
+            let zero = unsafe { ptr::read_volatile(&ZERO) };
+            unsafe { asm!("div {}", in(reg) zero, inout("rax") 1u64 => _, inout("rdx") 0u64 => _) };
+        },
+    )
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
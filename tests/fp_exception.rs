@@ -0,0 +1,59 @@
+#![no_main]
+#![no_std]
+#![feature(abi_x86_interrupt)]
+
+use core::arch::asm;
+use core::panic::PanicInfo;
+
+use lazy_static::lazy_static;
+use os::{QemuExitCode, exit_qemu, serial_print, serial_println};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.simd_floating_point
+            .set_handler_fn(test_simd_floating_point_handler);
+        idt
+    };
+}
+
+extern "x86-interrupt" fn test_simd_floating_point_handler(_stack_frame: InterruptStackFrame) {
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    os::hlt_loop();
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    serial_print!("fp_exception::divide_by_zero_hits_xm...\t");
+
+    os::gdt::init();
+    TEST_IDT.load();
+    os::cpu::enable_sse();
+    os::cpu::unmask_fp_exceptions();
+
+    // 1.0 / 0.0 with sse, the target is soft-float so this has to be written by hand.
+    // the compiler never touches the xmm registers on our target, so clobbering xmm0 is fine
+    // (and it cant be declared as an operand without the sse target feature anyway)
+    let one: f32 = 1.0;
+    let zero: f32 = 0.0;
+    unsafe {
+        asm!(
+            "movss xmm0, [{one}]",
+            "divss xmm0, [{zero}]",
+            one = in(reg) &one,
+            zero = in(reg) &zero,
+            options(nostack),
+        );
+    }
+
+    serial_println!("[no exception]");
+    exit_qemu(QemuExitCode::Failed);
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
@@ -0,0 +1,25 @@
+#![no_main]
+#![no_std]
+
+use core::panic::PanicInfo;
+
+use x86_64::instructions::segmentation::{DS, Segment};
+use x86_64::structures::gdt::SegmentSelector;
+use x86_64::structures::idt::ExceptionVector;
+
+/// GDT index 512, way past the end of the kernel's GDT
+const INVALID_SELECTOR: u16 = 512 << 3;
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    os::test_fault_exit(
+        "general_protection_fault::invalid_selector",
+        ExceptionVector::GeneralProtection,
+        || unsafe { DS::set_reg(SegmentSelector(INVALID_SELECTOR)) },
+    )
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
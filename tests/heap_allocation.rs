@@ -0,0 +1,112 @@
+#![no_main]
+#![no_std]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use os::allocator::{self, HEAP_SIZE};
+use os::memory::{self, BootInfoFrameAllocator};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os::init();
+    let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(physical_memory_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+#[test_case]
+fn test_simple_allocation() {
+    let value = Box::new(41);
+    let other = Box::new(13);
+    assert_eq!(*value, 41);
+    assert_eq!(*other, 13);
+}
+
+#[test_case]
+fn test_large_vec() {
+    let n = 1000;
+    let mut vec = Vec::new();
+    for i in 0..n {
+        vec.push(i);
+    }
+    assert_eq!(vec.iter().sum::<u64>(), (n - 1) * n / 2);
+}
+
+/// every box is freed before the next one is allocated, the heap would run out after a few
+/// thousand iterations if freed memory wasnt reused
+#[test_case]
+fn test_many_boxes() {
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+}
+
+/// same, but a long lived allocation stays in the way the whole time. the bump allocator
+/// (bump-allocator feature) fails this one, it only reuses memory once everything is freed
+#[test_case]
+fn test_many_boxes_long_lived() {
+    let long_lived = Box::new(1);
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+    assert_eq!(*long_lived, 1);
+}
+
+/// growing a vec frees its old buffer every time, repeating it checks that nothing leaks
+#[test_case]
+fn test_vec_grow_and_free() {
+    for round in 0..50 {
+        let mut vec = Vec::new();
+        for i in 0..4000u32 {
+            vec.push(i ^ round);
+        }
+        assert_eq!(vec[3999], 3999 ^ round);
+    }
+}
+
+static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+/// counts its drops, the heap memory behind a Box of it is freed right after
+struct DropCounter([u64; 16]);
+
+impl Drop for DropCounter {
+    fn drop(&mut self) {
+        DROPS.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// dropping a Box runs the value's drop and frees it. 128 bytes times HEAP_SIZE / 64 boxes is
+/// twice the heap, so it only fits if every one of them is freed again
+#[test_case]
+fn test_box_drop_deallocates() {
+    let before = DROPS.load(Ordering::SeqCst);
+    let count = HEAP_SIZE / 64;
+    for i in 0..count {
+        let counter = Box::new(DropCounter([i as u64; 16]));
+        assert_eq!(counter.0[15], i as u64);
+        drop(counter);
+        assert_eq!(DROPS.load(Ordering::SeqCst), before + i + 1);
+    }
+    assert_eq!(DROPS.load(Ordering::SeqCst), before + count);
+}
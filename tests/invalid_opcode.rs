@@ -0,0 +1,21 @@
+#![no_main]
+#![no_std]
+
+use core::arch::asm;
+use core::panic::PanicInfo;
+
+use x86_64::structures::idt::ExceptionVector;
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    os::test_fault_exit(
+        "invalid_opcode::ud2_is_reported",
+        ExceptionVector::InvalidOpcode,
+        || unsafe { asm!("ud2") },
+    )
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
@@ -0,0 +1,58 @@
+#![no_main]
+#![no_std]
+#![feature(abi_x86_interrupt)]
+
+use core::arch::asm;
+use core::panic::PanicInfo;
+
+use lazy_static::lazy_static;
+use os::{QemuExitCode, exit_qemu, serial_print, serial_println};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+/// the first vector after the cpu exceptions, where the timer irq goes
+const TIMER_VECTOR: u8 = 32;
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        unsafe {
+            idt[TIMER_VECTOR]
+                .set_handler_fn(test_timer_handler)
+                .set_stack_index(os::gdt::IRQ_IST_INDEX);
+        }
+        idt
+    };
+}
+
+extern "x86-interrupt" fn test_timer_handler(_stack_frame: InterruptStackFrame) {
+    let rsp: u64;
+    unsafe { asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack)) };
+    let stack = os::gdt::irq_stack();
+    if stack.start.as_u64() <= rsp && rsp < stack.end.as_u64() {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\nrsp {:#x} is outside of {:?}", rsp, stack);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    os::hlt_loop();
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    serial_print!("irq_stack::timer_handler_runs_on_ist...\t");
+
+    os::gdt::init();
+    TEST_IDT.load();
+    // raises the timer vector in software, the cpu switches stacks the same way as for the irq
+    unsafe { asm!("int 32") };
+
+    serial_println!("[handler returned]");
+    exit_qemu(QemuExitCode::Failed);
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
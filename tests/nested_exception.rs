@@ -0,0 +1,74 @@
+#![no_main]
+#![no_std]
+#![feature(abi_x86_interrupt)]
+
+use core::panic::PanicInfo;
+
+use lazy_static::lazy_static;
+use os::interrupts::{ExceptionGuard, exception_depth, first_exception};
+use os::{QemuExitCode, exit_qemu, serial_print, serial_println};
+use x86_64::structures::idt::{
+    ExceptionVector, InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode,
+};
+
+lazy_static! {
+    // no breakpoint handler on purpose
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+        unsafe {
+            idt.double_fault
+                .set_handler_fn(test_double_fault_handler)
+                .set_stack_index(os::gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+        idt
+    };
+}
+
+/// a buggy handler: the int3 has no handler, which turns into a double fault
+extern "x86-interrupt" fn test_page_fault_handler(
+    stack_frame: InterruptStackFrame,
+    _error_code: PageFaultErrorCode,
+) {
+    let _guard = ExceptionGuard::enter(ExceptionVector::Page, &stack_frame);
+    x86_64::instructions::interrupts::int3();
+}
+
+extern "x86-interrupt" fn test_double_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    _error_code: u64,
+) -> ! {
+    let first = first_exception().map(|(vector, _)| vector);
+    if exception_depth() == 1 && first == Some(ExceptionVector::Page) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!(
+            "[failed]\ndepth {}, first exception {:?}",
+            exception_depth(),
+            first
+        );
+        exit_qemu(QemuExitCode::Failed);
+    }
+    os::hlt_loop();
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    serial_print!("nested_exception::double_fault_reports_page_fault...\t");
+
+    os::gdt::init();
+    TEST_IDT.load();
+    unsafe {
+        core::ptr::write_volatile(0xdeadbeef as *mut u8, 42);
+    }
+
+    serial_println!("[no exception]");
+    exit_qemu(QemuExitCode::Failed);
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
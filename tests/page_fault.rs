@@ -0,0 +1,25 @@
+#![no_main]
+#![no_std]
+
+use core::panic::PanicInfo;
+
+use x86_64::structures::idt::ExceptionVector;
+
+/// far away from the kernel image, its stacks and the bootloader's page tables
+const UNMAPPED: u64 = 0x5ead_b000_0000;
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    os::test_fault_exit(
+        "page_fault::handler_reports_fault",
+        ExceptionVector::Page,
+        || unsafe {
+            core::ptr::read_volatile(UNMAPPED as *const u64);
+        },
+    )
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
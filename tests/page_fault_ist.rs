@@ -0,0 +1,68 @@
+// runs the kernel stack into its guard page with the kernel's own handlers installed. the page
+// fault has to be handled on its IST stack, and the handler has to recognize the overflow
+#![no_main]
+#![no_std]
+
+use core::arch::asm;
+use core::panic::PanicInfo;
+
+use os::interrupts::{STACK_OVERFLOW_MESSAGE, first_exception};
+use os::vga_buffer::{BUFFER_HEIGHT, WRITER};
+use os::{QemuExitCode, exit_qemu, serial_print, serial_println};
+use x86_64::structures::idt::ExceptionVector;
+
+/// whether `text` is on the screen, at the start of a row
+fn on_screen(text: &str) -> bool {
+    let writer = WRITER.lock();
+    (0..BUFFER_HEIGHT).any(|row| writer.read_string_row(row).starts_with(text.as_bytes()))
+}
+
+fn fail(reason: core::fmt::Arguments) {
+    serial_println!("[failed]\n{}", reason);
+    exit_qemu(QemuExitCode::Failed);
+}
+
+/// the page fault handler halts instead of returning, but runs the panic hooks first. they run
+/// deeper on the same stack as the handler
+fn after_page_fault() {
+    let rsp: u64;
+    unsafe { asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack)) };
+    let stack = os::gdt::page_fault_stack();
+    match first_exception() {
+        Some((ExceptionVector::Page, _)) => {}
+        other => return fail(format_args!("hooks ran while handling {:?}", other)),
+    }
+    if !(stack.start.as_u64() <= rsp && rsp < stack.end.as_u64()) {
+        return fail(format_args!("rsp {:#x} is outside of {:?}", rsp, stack));
+    }
+    if !on_screen("EXCEPTION: PAGE FAULT") || !on_screen(STACK_OVERFLOW_MESSAGE) {
+        return fail(format_args!("the handler didnt report a stack overflow"));
+    }
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    serial_print!("page_fault_ist::page_fault_with_exhausted_stack...\t");
+
+    os::gdt::init();
+    os::interrupts::init_idt();
+    os::panic::on_panic(after_page_fault).unwrap();
+
+    // runs the kernel stack down into its guard page
+    stack_overflow();
+
+    panic!("Execution continued after stack overflow");
+}
+
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+    stack_overflow();
+    volatile::Volatile::new(0).read(); // prevent tail recursion optimizations
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
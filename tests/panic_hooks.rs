@@ -0,0 +1,43 @@
+#![no_main]
+#![no_std]
+
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use os::{QemuExitCode, exit_qemu, serial_print, serial_println};
+
+/// one bit per hook that ran
+static RAN: AtomicU8 = AtomicU8::new(0);
+
+fn failing_hook() {
+    RAN.fetch_or(1, Ordering::SeqCst);
+    panic!("hook failed");
+}
+
+fn flushing_hook() {
+    RAN.fetch_or(2, Ordering::SeqCst);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    serial_print!("panic_hooks::hooks_run_on_panic...\t");
+
+    os::panic::on_panic(failing_hook).unwrap();
+    os::panic::on_panic(flushing_hook).unwrap();
+    panic!("test panic");
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    // the failing hook panics again, which lands here a second time and continues with the
+    // next hook, so by the time the outer run_hooks returns both of them ran
+    os::panic::run_hooks();
+    if RAN.load(Ordering::SeqCst) == 3 {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        exit_qemu(QemuExitCode::Failed);
+    }
+    os::hlt_loop();
+}
@@ -0,0 +1,82 @@
+// makes a mapped page read only with protect_range and writes to it. the write has to fault as
+// a protection violation caused by a write, at the page's address
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use os::memory::{self, BootInfoFrameAllocator};
+use os::{QemuExitCode, exit_qemu, serial_print, serial_println};
+use x86_64::VirtAddr;
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::structures::paging::{FrameAllocator, Page, PageTableFlags};
+
+/// nothing is mapped here, the other paging tests use the ones before
+const UNUSED_PAGE: u64 = 0x5ead_e000_0000;
+const VALUE: u64 = 42;
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+        idt
+    };
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    let expected = PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE;
+    let addr = Cr2::read();
+    if error_code.contains(expected) && addr.as_ref().is_ok_and(|addr| addr.as_u64() == UNUSED_PAGE)
+    {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\nunexpected fault: {:?} at {:?}", error_code, addr);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    os::hlt_loop();
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("protect_range::write_to_read_only_page_faults...\t");
+
+    os::gdt::init();
+    TEST_IDT.load();
+    // ring 0 ignores read only pages otherwise
+    os::cpu::enable_write_protect();
+
+    let offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    let page = Page::containing_address(VirtAddr::new(UNUSED_PAGE));
+    let frame = frame_allocator.allocate_frame().unwrap();
+    unsafe { memory::create_mapping(page, frame, &mut mapper, &mut frame_allocator) }
+        .expect("mapping an unused page failed");
+
+    let ptr = page.start_address().as_mut_ptr::<u64>();
+    unsafe { ptr.write_volatile(VALUE) };
+    let range = page.start_address()..page.start_address() + 8u64;
+    unsafe { memory::protect_range(range, PageTableFlags::PRESENT, &mut mapper) }
+        .expect("the page is mapped");
+    // still readable
+    assert_eq!(unsafe { ptr.read_volatile() }, VALUE);
+
+    unsafe { ptr.write_volatile(VALUE + 1) };
+
+    serial_println!("[no page fault]");
+    exit_qemu(QemuExitCode::Failed);
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
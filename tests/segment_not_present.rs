@@ -0,0 +1,71 @@
+#![no_main]
+#![no_std]
+#![feature(abi_x86_interrupt)]
+
+use core::panic::PanicInfo;
+
+use lazy_static::lazy_static;
+use os::{QemuExitCode, exit_qemu, serial_print, serial_println};
+use x86_64::instructions::segmentation::{CS, DS, Segment};
+use x86_64::structures::gdt::{
+    Descriptor, DescriptorFlags, GlobalDescriptorTable, SegmentSelector,
+};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, SelectorErrorCode};
+
+lazy_static! {
+    // a code segment to keep running on, and a data segment with its present bit cleared
+    static ref TEST_GDT: (GlobalDescriptorTable, SegmentSelector, SegmentSelector) = {
+        let mut gdt = GlobalDescriptorTable::new();
+        let code = gdt.append(Descriptor::kernel_code_segment());
+        let not_present = gdt.append(Descriptor::UserSegment(
+            DescriptorFlags::KERNEL_DATA.bits() & !DescriptorFlags::PRESENT.bits(),
+        ));
+        (gdt, code, not_present)
+    };
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.segment_not_present
+            .set_handler_fn(test_segment_not_present_handler);
+        idt
+    };
+}
+
+extern "x86-interrupt" fn test_segment_not_present_handler(
+    _stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    let selector = os::interrupts::faulting_selector(SelectorErrorCode::new_truncate(error_code));
+    if selector == TEST_GDT.2.0 {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!(
+            "[failed]\nfaulting selector {:#x}, expected {:#x}",
+            selector,
+            TEST_GDT.2.0
+        );
+        exit_qemu(QemuExitCode::Failed);
+    }
+    os::hlt_loop();
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    serial_print!("segment_not_present::bad_selector_is_reported...\t");
+
+    TEST_GDT.0.load();
+    TEST_IDT.load();
+    unsafe {
+        CS::set_reg(TEST_GDT.1);
+        DS::set_reg(TEST_GDT.2);
+    }
+
+    serial_println!("[no exception]");
+    exit_qemu(QemuExitCode::Failed);
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
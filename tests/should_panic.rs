@@ -20,7 +20,7 @@ pub extern "C" fn _start() -> ! {
     should_fail();
     serial_println!("[test did not panic]");
     exit_qemu(os::QemuExitCode::Failed);
-    loop {}
+    os::hlt_loop();
 }
 
 // we could either define the code below as our test runner or disable the harness attr in cargo.toml
@@ -41,7 +41,7 @@ pub extern "C" fn _start() -> ! {
 fn panic(_info: &PanicInfo) -> ! {
     serial_println!("[ok]");
     exit_qemu(os::QemuExitCode::Success);
-    loop {}
+    os::hlt_loop();
 }
 
 //------------Tests-------------//
@@ -31,12 +31,20 @@ fn init_test_idt() {
 }
 
 extern "x86-interrupt" fn test_double_fault_handler(
-    _stack_frame: InterruptStackFrame,
+    stack_frame: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
+    // the kernel's handler prints the message only when it recognizes the overflow
+    if !os::interrupts::is_stack_overflow(&stack_frame) {
+        serial_println!("[failed]");
+        serial_println!("double fault not recognized as a stack overflow");
+        exit_qemu(QemuExitCode::Failed);
+        os::hlt_loop();
+    }
+    serial_println!("{}", os::interrupts::STACK_OVERFLOW_MESSAGE);
     serial_println!("[ok]");
     exit_qemu(QemuExitCode::Success);
-    loop {}
+    os::hlt_loop();
 }
 
 #[unsafe(no_mangle)]
@@ -0,0 +1,42 @@
+#![no_main]
+#![no_std]
+
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use os::{QemuExitCode, Testable, exit_qemu, serial_println};
+use x86_64::instructions::port::Port;
+
+/// LSR of COM1, bit 6 is set once the transmitter is completely empty
+const COM1_LINE_STATUS: u16 = 0x3FD;
+const LSR_TRANSMITTER_EMPTY: u8 = 1 << 6;
+
+static NAME_FLUSHED: AtomicBool = AtomicBool::new(false);
+
+fn crashing_test() {
+    // everything printed so far, including our name, has to have left already
+    let mut lsr: Port<u8> = Port::new(COM1_LINE_STATUS);
+    let empty = unsafe { lsr.read() } & LSR_TRANSMITTER_EMPTY != 0;
+    NAME_FLUSHED.store(empty, Ordering::SeqCst);
+    panic!("simulated crash");
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    crashing_test.run();
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    if NAME_FLUSHED.load(Ordering::SeqCst) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\nthe test name was still being sent when it crashed");
+        exit_qemu(QemuExitCode::Failed);
+    }
+    os::hlt_loop();
+}
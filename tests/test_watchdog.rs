@@ -0,0 +1,38 @@
+#![no_main]
+#![no_std]
+
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use os::{QemuExitCode, exit_qemu, serial_print, serial_println};
+
+const WATCHDOG_SECS: u32 = 1;
+
+/// uptime when the watchdog was armed, u64::MAX until then
+static ARMED_AT_MS: AtomicU64 = AtomicU64::new(u64::MAX);
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    serial_print!("test_watchdog::hanging_test_fails...\t");
+
+    os::init();
+    ARMED_AT_MS.store(os::time::uptime_ms(), Ordering::SeqCst);
+    os::arm_test_watchdog(WATCHDOG_SECS).unwrap();
+
+    // a test that never finishes
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let armed_at = ARMED_AT_MS.load(Ordering::SeqCst);
+    let elapsed = os::time::uptime_ms().saturating_sub(armed_at);
+    if armed_at != u64::MAX && elapsed >= WATCHDOG_SECS as u64 * 1000 {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\nError: {}\n", info);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    os::hlt_loop();
+}